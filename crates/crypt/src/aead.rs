@@ -3,3 +3,13 @@ pub mod gcm;
 
 pub mod chacha20_poly1305;
 pub mod poly1305;
+
+/// Compare authentication tags in constant time, the position of a mismatch must not leak.
+fn tags_match(tag: &[u8], tag_check: &[u8]) -> bool {
+    tag.len() == tag_check.len()
+        && tag
+            .iter()
+            .zip(tag_check)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
@@ -27,6 +27,18 @@ pub fn encrypt_aes_256_gcm(
     gcm::encrypt(&block_cipher, iv, plaintext, additional_data)
 }
 
+#[inline]
+pub fn decrypt_aes_128_gcm(
+    secret: &[u8],
+    iv: &[u8],
+    ciphertext: &[u8],
+    additional_data: &[u8],
+    tag: &[u8],
+) -> Result<Box<[u8]>> {
+    let block_cipher = Aes::new(Aes128Cipher::new(secret.try_into()?));
+    gcm::decrypt(&block_cipher, iv, ciphertext, additional_data, tag)
+}
+
 #[inline]
 pub fn decrypt_aes_256_gcm(
     secret: &[u8],
@@ -108,6 +120,14 @@ mod tests {
         let ad = [];
 
         let (c, t) = encrypt_aes_128_gcm(&key, &iv, &plaintext, &ad).unwrap();
+        assert_eq!(
+            *decrypt_aes_128_gcm(&key, &iv, &c, &ad, &t).unwrap(),
+            plaintext
+        );
+        let mut tampered = t.clone();
+        tampered[15] ^= 1;
+        assert!(decrypt_aes_128_gcm(&key, &iv, &c, &ad, &tampered).is_err());
+        assert!(decrypt_aes_128_gcm(&key, &iv, &c, &ad, &t[..15]).is_err());
 
         assert_eq!(
             *c,
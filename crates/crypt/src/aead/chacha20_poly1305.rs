@@ -1,5 +1,7 @@
+use anyhow::{Result, anyhow};
+
 use crate::{
-    aead::poly1305::poly1305_mac,
+    aead::{poly1305::poly1305_mac, tags_match},
     symmetric::chacha20::{chacha20_block, chacha20_encrypt},
 };
 
@@ -8,32 +10,52 @@ fn poly1305_key_gen(key: [u8; 32], nonce: [u8; 12]) -> [u8; 32] {
     block[0..32].try_into().unwrap()
 }
 
-pub fn encrypt_chacha20_poly1305(
-    key: [u8; 32],
-    iv: [u8; 12],
-    plaintext: &[u8],
-    additional_data: &[u8],
-) -> (Box<[u8]>, [u8; 16]) {
-    //     nonce = constant | iv
-    let otk = poly1305_key_gen(key, iv);
-    let ciphertext = chacha20_encrypt(key, 1, iv, plaintext);
-
+fn tag(otk: [u8; 32], ciphertext: &[u8], additional_data: &[u8]) -> [u8; 16] {
     let mut mac_data = Vec::new();
 
     mac_data.extend(additional_data);
     mac_data.extend([0].repeat(mac_data.len().div_ceil(16) * 16 - mac_data.len()));
 
-    mac_data.extend(&ciphertext);
+    mac_data.extend(ciphertext);
     mac_data.extend([0].repeat(mac_data.len().div_ceil(16) * 16 - mac_data.len()));
 
     mac_data.extend((additional_data.len() as u64).to_le_bytes());
     mac_data.extend((ciphertext.len() as u64).to_le_bytes());
 
-    let tag = poly1305_mac(&mac_data, otk);
+    poly1305_mac(&mac_data, otk)
+}
+
+pub fn encrypt_chacha20_poly1305(
+    key: [u8; 32],
+    iv: [u8; 12],
+    plaintext: &[u8],
+    additional_data: &[u8],
+) -> (Box<[u8]>, [u8; 16]) {
+    //     nonce = constant | iv
+    let otk = poly1305_key_gen(key, iv);
+    let ciphertext = chacha20_encrypt(key, 1, iv, plaintext);
+    let tag = tag(otk, &ciphertext, additional_data);
 
     (ciphertext, tag)
 }
 
+#[allow(clippy::missing_errors_doc)]
+pub fn decrypt_chacha20_poly1305(
+    key: [u8; 32],
+    iv: [u8; 12],
+    ciphertext: &[u8],
+    additional_data: &[u8],
+    tag_check: &[u8],
+) -> Result<Box<[u8]>> {
+    let otk = poly1305_key_gen(key, iv);
+    let tag = tag(otk, ciphertext, additional_data);
+    if !tags_match(&tag, tag_check) {
+        return Err(anyhow!("Tag does not match"));
+    }
+
+    Ok(chacha20_encrypt(key, 1, iv, ciphertext))
+}
+
 #[cfg(test)]
 mod tests {
     use hex_literal::hex;
@@ -58,4 +80,37 @@ mod tests {
             tag
         );
     }
+
+    /// RFC 8439 §2.8.2
+    #[test]
+    fn test_aead_chacha20_poly1305_decrypt() -> Result<()> {
+        let plaintext = b"Ladies and Gentlemen of the class of '99: If I could \
+            offer you only one tip for the future, sunscreen would be it.";
+        let additional_data = hex!("50 51 52 53 c0 c1 c2 c3 c4 c5 c6 c7");
+        let key = hex!(
+            "80 81 82 83 84 85 86 87 88 89 8a 8b 8c 8d 8e 8f
+             90 91 92 93 94 95 96 97 98 99 9a 9b 9c 9d 9e 9f"
+        );
+        let iv = hex!("07 00 00 00 40 41 42 43 44 45 46 47");
+        let ciphertext = hex!(
+            "d3 1a 8d 34 64 8e 60 db 7b 86 af bc 53 ef 7e c2
+             a4 ad ed 51 29 6e 08 fe a9 e2 b5 a7 36 ee 62 d6
+             3d be a4 5e 8c a9 67 12 82 fa fb 69 da 92 72 8b
+             1a 71 de 0a 9e 06 0b 29 05 d6 a5 b6 7e cd 3b 36
+             92 dd bd 7f 2d 77 8b 8c 98 03 ae e3 28 09 1b 58
+             fa b3 24 e4 fa d6 75 94 55 85 80 8b 48 31 d7 bc
+             3f f4 de f0 8e 4b 7a 9d e5 76 d2 65 86 ce c6 4b
+             61 16"
+        );
+        let mut tag = hex!("1a e1 0b 59 4f 09 e2 6a 7e 90 2e cb d0 60 06 91");
+
+        assert_eq!(
+            *decrypt_chacha20_poly1305(key, iv, &ciphertext, &additional_data, &tag)?,
+            *plaintext
+        );
+
+        tag[15] ^= 1;
+        assert!(decrypt_chacha20_poly1305(key, iv, &ciphertext, &additional_data, &tag).is_err());
+        Ok(())
+    }
 }
@@ -2,7 +2,7 @@
 
 use anyhow::{Result, anyhow, ensure};
 
-use crate::block_cipher::BlockCipher;
+use crate::{aead::tags_match, block_cipher::BlockCipher};
 
 fn xor(mut a: [u8; 16], b: [u8; 16]) -> [u8; 16] {
     for i in 0..16 {
@@ -170,7 +170,7 @@ pub fn decrypt(
     let tag_block = ghash_tag(&hash_key, additional_data, ciphertext)?;
     let tag_check = gctr(block_cipher, counter_initial, &tag_block)?;
 
-    if *tag != *tag_check {
+    if !tags_match(tag, &tag_check) {
         return Err(anyhow!("Tag does not match"));
     }
 
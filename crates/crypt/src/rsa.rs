@@ -77,7 +77,8 @@ fn emsa_pss_encode_fixed<H: Hasher>(salt: &[u8], message: &[u8], bits: usize) ->
     let padding = [0u8].repeat(em_len - salt.len() - h_len - 2); // PS
     let db = concat_dyn![padding, [0x01], salt]; // DB
     let db_mask = generate_mask::<H>(&msg_derived_hash, em_len - h_len - 1); // dbMask
-    let masked_db = xor_dyn(&db, &db_mask); // maskedDB
+    let mut masked_db = xor_dyn(&db, &db_mask); // maskedDB
+    masked_db[0] &= 0xFF >> (8 * em_len - bits);
 
     // EM
     concat_dyn!(masked_db, msg_derived_hash, [0xbc])
@@ -110,8 +111,13 @@ fn emsa_pss_verify<H: Hasher>(
     let (masked_db, msg_derived_hash) =
         encoded_message[..encoded_message.len() - 1].split_at(em_len - h_len - 1);
 
+    if masked_db[0] & !(0xFF >> (8 * em_len - bits)) != 0 {
+        bail!("Leftmost bits are not zero");
+    }
+
     let db_mask = generate_mask::<H>(msg_derived_hash, em_len - h_len - 1);
-    let db = xor_dyn(masked_db, &db_mask); // D
+    let mut db = xor_dyn(masked_db, &db_mask); // D
+    db[0] &= 0xFF >> (8 * em_len - bits);
 
     if db[..(em_len - salt_len - h_len - 2)]
         .iter()
@@ -272,4 +278,35 @@ mod tests {
             .is_err()
         );
     }
+
+    #[test]
+    fn test_sign_verify_leftmost_bits() {
+        let modulus = BigUint::from_bytes_be(&hex!(
+            "bcb47b2e0dafcba81ff2a2b5cb115ca7e757184c9d72bcdcda707a146b3b4e29
+             989ddc660bd694865b932b71ca24a335cf4d339c719183e6222e4c9ea6875acd
+             528a49ba21863fe08147c3a47e41990b51a03f77d22137f8d74c43a5a45f4e9e
+             18a2d15db051dc89385db9cf8374b63a8cc88113710e6d8179075b7dc79ee76b"
+        ));
+        let private_key = PrivateKey {
+            modulus: modulus.clone(),
+            exponent: BigUint::from_bytes_be(&hex!(
+                "383a6f19e1ea27fd08c7fbc3bfa684bd6329888c0bbe4c98625e7181f411cfd0
+                 853144a3039404dda41bce2e31d588ec57c0e148146f0fa65b39008ba5835f82
+                 9ba35ae2f155d61b8a12581b99c927fd2f22252c5e73cba4a610db3973e019ee
+                 0f95130d4319ed413432f2e5e20d5215cdd27c2164206b3f80edee51938a25c1"
+            )),
+        };
+        let public_key = PublicKey {
+            modulus,
+            exponent: BigUint::from(0x10001u32),
+        };
+
+        // Encoded message has one bit less than the modulus, so about half of
+        // the salts produce a leftmost bit that must be cleared
+        for i in 0..16 {
+            let salt = [i; 32];
+            let signature = rsassa_pss_sign_fixed::<Sha256>(&salt, &private_key, b"message");
+            assert!(rsassa_pss_verify::<Sha256, 32>(&public_key, b"message", &signature).is_ok());
+        }
+    }
 }
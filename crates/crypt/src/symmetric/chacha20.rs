@@ -70,7 +70,6 @@ pub(crate) fn chacha20_block(key: [u8; 32], counter: u32, nonce: [u8; 12]) -> [u
     state[14] = u32::from_le_bytes([nonce[4], nonce[5], nonce[6], nonce[7]]);
     state[15] = u32::from_le_bytes([nonce[8], nonce[9], nonce[10], nonce[11]]);
 
-    println!("{state:08x?}");
     let initial_state = state;
 
     for _ in 0..10 {
@@ -80,7 +79,6 @@ pub(crate) fn chacha20_block(key: [u8; 32], counter: u32, nonce: [u8; 12]) -> [u
     for i in 0..16 {
         state[i] = state[i].wrapping_add(initial_state[i]);
     }
-    println!("{state:08x?}");
 
     // Serialization
     let mut res = [0u8; 64];
@@ -102,7 +100,6 @@ pub fn chacha20_encrypt(
 
     for (block, j) in blocks.iter().zip(0u32..) {
         let key_stream = chacha20_block(key, counter + j, nonce);
-        println!("{key_stream:02x?}");
         encrypted.extend(xor(*block, key_stream));
     }
 
@@ -110,7 +107,6 @@ pub fn chacha20_encrypt(
         #[allow(clippy::cast_possible_truncation)]
         let j = (plaintext.len() / 64) as u32;
         let key_stream = chacha20_block(key, counter + j, nonce);
-        println!("{key_stream:02x?}");
         encrypted.extend(remainder.iter().zip(key_stream).map(|(a, b)| a ^ b));
     }
 
@@ -1,6 +1,164 @@
-#[derive(Clone, Copy, Debug)]
+use anyhow::{Result, bail};
+use crypt::{
+    aead::{aes_gcm, chacha20_poly1305},
+    hash::{
+        Hasher,
+        sha::{Sha256, Sha384},
+    },
+    hmac::hmac_hash,
+};
+
+use crate::hkdf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct CipherSuite(pub u16);
 
 pub const TLS_AES_128_GCM_SHA256: CipherSuite = CipherSuite(0x13_01);
 pub const TLS_AES_256_GCM_SHA384: CipherSuite = CipherSuite(0x13_02);
 pub const TLS_CHACHA20_POLY1305_SHA256: CipherSuite = CipherSuite(0x13_03);
+
+/// Implemented suites, in default order of preference.
+pub const SUPPORTED_CIPHER_SUITES: [CipherSuite; 3] = [
+    TLS_AES_256_GCM_SHA384,
+    TLS_CHACHA20_POLY1305_SHA256,
+    TLS_AES_128_GCM_SHA256,
+];
+
+/// Length of the AEAD authentication tag, the same for all suites.
+pub(crate) const TAG_LENGTH: usize = 16;
+
+/// Call a generic hash function with the hash of the suite.
+macro_rules! with_hash {
+    ($suite:expr, $f:ident($($arg:expr),*)) => {
+        if $suite == TLS_AES_256_GCM_SHA384 {
+            $f::<Sha384>($($arg),*)
+        } else {
+            $f::<Sha256>($($arg),*)
+        }
+    };
+}
+
+impl CipherSuite {
+    /// IANA name of the suite.
+    pub fn name(self) -> &'static str {
+        match self {
+            TLS_AES_128_GCM_SHA256 => "TLS_AES_128_GCM_SHA256",
+            TLS_AES_256_GCM_SHA384 => "TLS_AES_256_GCM_SHA384",
+            TLS_CHACHA20_POLY1305_SHA256 => "TLS_CHACHA20_POLY1305_SHA256",
+            _ => "unknown",
+        }
+    }
+
+    /// Suite with the given IANA name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        SUPPORTED_CIPHER_SUITES
+            .into_iter()
+            .find(|x| x.name() == name)
+    }
+
+    pub(crate) fn key_length(self) -> usize {
+        match self {
+            TLS_AES_128_GCM_SHA256 => 16,
+            _ => 32,
+        }
+    }
+
+    pub(crate) fn hash_length(self) -> usize {
+        if self == TLS_AES_256_GCM_SHA384 {
+            Sha384::DIGEST_SIZE
+        } else {
+            Sha256::DIGEST_SIZE
+        }
+    }
+
+    pub(crate) fn hash(self, data: &[u8]) -> Box<[u8]> {
+        fn hash<H: Hasher>(data: &[u8]) -> Box<[u8]> {
+            H::hash(data)
+        }
+        with_hash!(self, hash(data))
+    }
+
+    pub(crate) fn hmac(self, key: &[u8], data: &[u8]) -> Box<[u8]> {
+        with_hash!(self, hmac_hash(key, data))
+    }
+
+    pub(crate) fn hkdf_extract(self, salt: &[u8], ikm: &[u8]) -> Box<[u8]> {
+        use hkdf::hkdf_extract;
+        with_hash!(self, hkdf_extract(salt, ikm))
+    }
+
+    pub(crate) fn hkdf_expand_label(
+        self,
+        secret: &[u8],
+        label: &str,
+        context: &[u8],
+        length: u16,
+    ) -> Box<[u8]> {
+        use hkdf::hkdf_expand_label;
+        with_hash!(self, hkdf_expand_label(secret, label, context, length))
+    }
+
+    pub(crate) fn derive_secret(self, secret: &[u8], label: &str, messages: &[u8]) -> Box<[u8]> {
+        use hkdf::derive_secret;
+        with_hash!(self, derive_secret(secret, label, messages))
+    }
+
+    /// AEAD encrypt, the tag is appended to the ciphertext.
+    pub(crate) fn seal(
+        self,
+        key: &[u8],
+        nonce: [u8; 12],
+        plaintext: &[u8],
+        additional_data: &[u8],
+    ) -> Result<Box<[u8]>> {
+        let (ciphertext, tag) = match self {
+            TLS_AES_128_GCM_SHA256 => {
+                aes_gcm::encrypt_aes_128_gcm(key, &nonce, plaintext, additional_data)?
+            }
+            TLS_AES_256_GCM_SHA384 => {
+                aes_gcm::encrypt_aes_256_gcm(key, &nonce, plaintext, additional_data)?
+            }
+            TLS_CHACHA20_POLY1305_SHA256 => {
+                let (ciphertext, tag) = chacha20_poly1305::encrypt_chacha20_poly1305(
+                    key.try_into()?,
+                    nonce,
+                    plaintext,
+                    additional_data,
+                );
+                (ciphertext, Box::from(tag))
+            }
+            _ => bail!("Unsupported cipher suite {self:?}"),
+        };
+        Ok([ciphertext, tag].concat().into_boxed_slice())
+    }
+
+    /// AEAD decrypt, the tag is the end of `ciphertext`.
+    pub(crate) fn open(
+        self,
+        key: &[u8],
+        nonce: [u8; 12],
+        ciphertext: &[u8],
+        additional_data: &[u8],
+    ) -> Result<Box<[u8]>> {
+        let Some(split) = ciphertext.len().checked_sub(TAG_LENGTH) else {
+            bail!("Ciphertext is shorter than the tag");
+        };
+        let (ciphertext, tag) = ciphertext.split_at(split);
+        match self {
+            TLS_AES_128_GCM_SHA256 => {
+                aes_gcm::decrypt_aes_128_gcm(key, &nonce, ciphertext, additional_data, tag)
+            }
+            TLS_AES_256_GCM_SHA384 => {
+                aes_gcm::decrypt_aes_256_gcm(key, &nonce, ciphertext, additional_data, tag)
+            }
+            TLS_CHACHA20_POLY1305_SHA256 => chacha20_poly1305::decrypt_chacha20_poly1305(
+                key.try_into()?,
+                nonce,
+                ciphertext,
+                additional_data,
+                tag,
+            ),
+            _ => bail!("Unsupported cipher suite {self:?}"),
+        }
+    }
+}
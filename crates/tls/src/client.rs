@@ -0,0 +1,707 @@
+use anyhow::{Result, anyhow, bail};
+use crypt::elliptic::x25519;
+
+use crate::{
+    VERSION,
+    cipher_suite::{CipherSuite, SUPPORTED_CIPHER_SUITES},
+    connection::{Event, HandshakeBuffer, RecordReader, next_record},
+    error::TlsAlert,
+    key_schedule::{KeySchedule, TrafficKeys, server_signed_content},
+    parse::RawDeser,
+    record::{
+        TlsCiphertext, TlsPlaintext,
+        alert::{Alert, AlertDescription, AlertLevel},
+        content_types,
+        handshake::{
+            Handshake,
+            certificate::CertificateEntryContent,
+            client_hello::{ClientHello, ClientHelloExtension},
+            extension::{KeyShareEntry, NamedGroup, SignatureScheme},
+            finished::Finished,
+            server_hello::{ServerHello, ServerHelloExtensionContent},
+        },
+    },
+};
+
+/// Signature schemes offered to servers.
+const SIGNATURE_SCHEMES: [SignatureScheme; 2] = [
+    SignatureScheme::rsa_pss_rsae_sha256,
+    SignatureScheme::rsa_pss_pss_sha256,
+];
+
+/// `ServerHello.random` of a HelloRetryRequest, RFC 8446 §4.1.3.
+const HELLO_RETRY_REQUEST: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+/// Checks of the server identity, called during the handshake.
+pub trait ServerCertVerifier {
+    /// Check the certificate chain, leaf first, is valid for `server_name`.
+    fn verify_certificate(&self, certificates: &[&[u8]], server_name: &str) -> Result<()>;
+
+    /// Check `signature` over `message` was made with the key of `certificate`.
+    fn verify_signature(
+        &self,
+        certificate: &[u8],
+        scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()>;
+}
+
+/// Settings shared by all connections of a client.
+pub struct ClientConfig {
+    /// Offered application protocols in order of preference
+    pub alpn_protocols: Vec<String>,
+    pub verifier: Box<dyn ServerCertVerifier + Send + Sync>,
+}
+
+/// Handshake progress, named after the states of RFC 8446 Appendix A.1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    WaitServerHello,
+    WaitEncryptedExtensions,
+    WaitCertificate,
+    WaitCertificateVerify,
+    WaitFinished,
+    Connected,
+    Closed,
+}
+
+/// Client side of a TLS 1.3 connection without any I/O.
+///
+/// ClientHello is queued on creation. Received bytes are fed to
+/// [`ClientConnection::receive`], bytes to send are taken with
+/// [`ClientConnection::take_output`]
+pub struct ClientConnection<'a> {
+    config: &'a ClientConfig,
+    server_name: String,
+    state: State,
+
+    received: Vec<u8>,
+    output: Vec<u8>,
+
+    handshake: HandshakeBuffer,
+    transcript: Vec<u8>,
+    legacy_session_id: [u8; 32],
+    private_key: [u8; 32],
+    schedule: Option<KeySchedule>,
+    read_keys: Option<TrafficKeys>,
+    write_keys: Option<TrafficKeys>,
+
+    alpn: Option<String>,
+    cipher_suite: Option<CipherSuite>,
+    /// Leaf certificate, for CertificateVerify
+    certificate: Option<Box<[u8]>>,
+}
+
+impl<'a> ClientConnection<'a> {
+    /// Start a handshake, `server_name` is sent unless it is an IP address.
+    pub fn new(config: &'a ClientConfig, server_name: &str) -> Result<Self> {
+        let (public_key, private_key) = x25519::get_keypair();
+
+        let mut res = Self {
+            config,
+            server_name: String::from(server_name),
+            state: State::WaitServerHello,
+            received: Vec::new(),
+            output: Vec::new(),
+            handshake: HandshakeBuffer::default(),
+            transcript: Vec::new(),
+            legacy_session_id: rand::random(),
+            private_key,
+            schedule: None,
+            read_keys: None,
+            write_keys: None,
+            alpn: None,
+            cipher_suite: None,
+            certificate: None,
+        };
+
+        let mut extensions = vec![
+            ClientHelloExtension::new_supported_versions(&[VERSION])?,
+            ClientHelloExtension::new_supported_groups(&[NamedGroup::x25519])?,
+            ClientHelloExtension::new_signature_algorithms(&SIGNATURE_SCHEMES)?,
+            ClientHelloExtension::new_key_share(&[KeyShareEntry::new(
+                NamedGroup::x25519,
+                &public_key,
+            )])?,
+        ];
+        if server_name.parse::<std::net::IpAddr>().is_err() {
+            extensions.push(ClientHelloExtension::new_server_name(
+                server_name.as_bytes(),
+            )?);
+        }
+        if !config.alpn_protocols.is_empty() {
+            let protocols = config
+                .alpn_protocols
+                .iter()
+                .map(String::as_bytes)
+                .collect::<Vec<_>>();
+            extensions.push(ClientHelloExtension::new_alpn(&protocols)?);
+        }
+
+        let client_hello = Handshake::ClientHello(ClientHello::new(
+            &rand::random(),
+            &res.legacy_session_id,
+            &SUPPORTED_CIPHER_SUITES,
+            &extensions,
+        )?);
+        let record = TlsPlaintext::new_handshake(client_hello)?.to_raw();
+        res.transcript.extend(&record[5..]);
+        res.output.extend(record);
+
+        Ok(res)
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_handshaking(&self) -> bool {
+        !matches!(self.state, State::Connected | State::Closed)
+    }
+
+    /// Application protocol selected by the server.
+    pub fn alpn(&self) -> Option<&str> {
+        self.alpn.as_deref()
+    }
+
+    /// Cipher suite selected by the server.
+    pub fn cipher_suite(&self) -> Option<CipherSuite> {
+        self.cipher_suite
+    }
+
+    /// Bytes to send to the server.
+    pub fn take_output(&mut self) -> Box<[u8]> {
+        std::mem::take(&mut self.output).into_boxed_slice()
+    }
+
+    /// Process bytes received from the server.
+    ///
+    /// Incomplete records are kept until the rest arrives, as are records
+    /// following the end of the handshake. On error the connection is closed
+    /// and the alert, if any, queued for the server
+    pub fn receive(&mut self, data: &[u8]) -> Result<Vec<Event>> {
+        if self.state == State::Closed {
+            return Ok(Vec::new());
+        }
+        self.received.extend(data);
+
+        let mut events = Vec::new();
+        while let Some(record) = next_record(&mut self.received) {
+            if let Err(e) = record.and_then(|raw| self.process_record(&raw, &mut events)) {
+                self.state = State::Closed;
+                if let Some(alert) = e.downcast_ref::<TlsAlert>() {
+                    self.send_alert(*alert)?;
+                }
+                return Err(e);
+            }
+            // Records after the handshake are left for the reading half
+            if self.state == State::Closed || events.contains(&Event::HandshakeComplete) {
+                break;
+            }
+        }
+        Ok(events)
+    }
+
+    /// Queue application data for the server.
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        if self.state != State::Connected {
+            bail!("Connection is not established");
+        }
+        for chunk in data.chunks(1 << 14) {
+            self.write_record(&TlsPlaintext::new_application_data(chunk)?)?;
+        }
+        Ok(())
+    }
+
+    /// Queue `close_notify`, nothing can be sent afterwards.
+    pub fn close(&mut self) -> Result<()> {
+        if self.state != State::Closed {
+            self.write_record(&TlsPlaintext::new_alert(Alert {
+                level: AlertLevel::Warning,
+                description: AlertDescription::CloseNotify,
+            }))?;
+            self.state = State::Closed;
+        }
+        Ok(())
+    }
+
+    /// Reading half and client traffic keys.
+    ///
+    /// Records received after the handshake are passed to the reading half
+    pub fn into_parts(self) -> Result<(RecordReader, TrafficKeys)> {
+        if self.state != State::Connected {
+            bail!("Connection is not established");
+        }
+        match (self.read_keys, self.write_keys) {
+            (Some(read_keys), Some(write_keys)) => Ok((
+                RecordReader::with_received(read_keys, self.received),
+                write_keys,
+            )),
+            _ => bail!("Missing traffic keys"),
+        }
+    }
+
+    fn process_record(&mut self, raw: &[u8], events: &mut Vec<Event>) -> Result<()> {
+        match (self.state, raw[0]) {
+            (State::WaitServerHello, content_types::HANDSHAKE) => {
+                self.process_content(content_types::HANDSHAKE, &raw[5..], events)
+            }
+            // Sent for middlebox compatibility, RFC 8446 §5
+            (
+                State::WaitEncryptedExtensions
+                | State::WaitCertificate
+                | State::WaitCertificateVerify
+                | State::WaitFinished,
+                content_types::CHANGE_CIPHER_SPEC,
+            ) => {
+                if raw[5..] != [1] {
+                    bail!(TlsAlert::UnexpectedMessage);
+                }
+                Ok(())
+            }
+            (_, content_types::ALERT) if self.read_keys.is_none() => {
+                let record = TlsPlaintext::from_raw(raw).map_err(|_| TlsAlert::DecodeError)?;
+                self.state = State::Closed;
+                Err(anyhow!("Received alert: {:?}", record.fragment))
+            }
+            (State::WaitServerHello, _) | (_, content_types::HANDSHAKE) => {
+                bail!(TlsAlert::UnexpectedMessage)
+            }
+            (_, content_types::APPLICATION_DATA) => {
+                let (content_type, content) = self.decrypt(raw)?;
+                self.process_content(content_type, &content, events)
+            }
+            _ => bail!(TlsAlert::UnexpectedMessage),
+        }
+    }
+
+    fn decrypt(&mut self, raw: &[u8]) -> Result<(u8, Box<[u8]>)> {
+        let keys = self.read_keys.as_mut().ok_or(TlsAlert::UnexpectedMessage)?;
+        if raw.len() < 5 + 17 {
+            bail!(TlsAlert::BadRecordMac);
+        }
+        let record = TlsCiphertext::from_raw(raw).map_err(|_| TlsAlert::DecodeError)?;
+        keys.open(&record)
+            .map_err(|_| TlsAlert::BadRecordMac.into())
+    }
+
+    fn process_content(
+        &mut self,
+        content_type: u8,
+        content: &[u8],
+        events: &mut Vec<Event>,
+    ) -> Result<()> {
+        if content_type != content_types::HANDSHAKE && !self.handshake.is_empty() {
+            bail!(TlsAlert::UnexpectedMessage);
+        }
+        match (self.state, content_type) {
+            (_, content_types::HANDSHAKE) => {
+                self.handshake.push(content)?;
+                while let Some(message) = self.handshake.next_message() {
+                    self.process_handshake(&message?, events)?;
+                }
+                Ok(())
+            }
+            (State::Connected, content_types::APPLICATION_DATA) => {
+                events.push(Event::ApplicationData(Box::from(content)));
+                Ok(())
+            }
+            (_, content_types::ALERT) => {
+                let alert = Alert::deser(content).map_err(|_| TlsAlert::DecodeError)?;
+                self.state = State::Closed;
+                if matches!(alert.description, AlertDescription::CloseNotify) {
+                    events.push(Event::Closed);
+                    Ok(())
+                } else {
+                    Err(anyhow!("Received alert: {alert:?}"))
+                }
+            }
+            _ => bail!(TlsAlert::UnexpectedMessage),
+        }
+    }
+
+    /// Process one complete handshake message, `raw` includes its header.
+    fn process_handshake(&mut self, raw: &[u8], events: &mut Vec<Event>) -> Result<()> {
+        let handshake = Handshake::deser(raw).map_err(|_| TlsAlert::DecodeError)?;
+        match (self.state, handshake) {
+            (State::WaitServerHello, Handshake::ServerHello(server_hello)) => {
+                self.transcript.extend(raw);
+                self.server_hello(&server_hello)?;
+            }
+            (State::WaitServerHello, _) => bail!(TlsAlert::UnexpectedMessage),
+            // Resumption is not supported
+            (State::Connected, Handshake::NewSessionTicket) => return Ok(()),
+            (_, handshake) => {
+                self.handshake(handshake, raw)?;
+                if self.state != State::Connected {
+                    return Ok(());
+                }
+                events.push(Event::HandshakeComplete);
+            }
+        }
+        // ServerHello and server Finished are followed by a key change, which
+        // must happen on a record boundary, RFC 8446 §5.1
+        if !self.handshake.is_empty() {
+            bail!(TlsAlert::UnexpectedMessage);
+        }
+        Ok(())
+    }
+
+    fn server_hello(&mut self, server_hello: &ServerHello) -> Result<()> {
+        if *server_hello.random == HELLO_RETRY_REQUEST {
+            // Only sent if x25519 is not supported
+            bail!(TlsAlert::HandshakeFailure);
+        }
+        if *server_hello.legacy_session_id_echo != self.legacy_session_id
+            || !SUPPORTED_CIPHER_SUITES.contains(&server_hello.cipher_suite)
+        {
+            bail!(TlsAlert::IllegalParameter);
+        }
+
+        let mut server_share = None;
+        let mut selected_version = None;
+        for extension in &server_hello.extensions {
+            match &extension.content {
+                ServerHelloExtensionContent::KeyShare(e) => server_share = Some(&e.server_share),
+                ServerHelloExtensionContent::SupportedVersions(e) => {
+                    selected_version = Some(e.selected_version);
+                }
+                _ => {}
+            }
+        }
+        if selected_version != Some(VERSION) {
+            bail!(TlsAlert::ProtocolVersion);
+        }
+        let server_share = server_share.ok_or(TlsAlert::MissingExtension)?;
+        if server_share.group != NamedGroup::x25519 {
+            bail!(TlsAlert::IllegalParameter);
+        }
+        let server_share: [u8; 32] = server_share
+            .key_exchange
+            .as_ref()
+            .try_into()
+            .map_err(|_| TlsAlert::IllegalParameter)?;
+        let shared = x25519::get_shared_key(self.private_key, server_share);
+
+        let schedule = KeySchedule::new(server_hello.cipher_suite, &shared, &self.transcript);
+        self.cipher_suite = Some(server_hello.cipher_suite);
+        self.read_keys = Some(schedule.server_handshake_keys()?);
+        self.write_keys = Some(schedule.client_handshake_keys()?);
+        self.schedule = Some(schedule);
+        self.state = State::WaitEncryptedExtensions;
+        Ok(())
+    }
+
+    /// Process an encrypted handshake message, `raw` is its encoding.
+    fn handshake(&mut self, handshake: Handshake, raw: &[u8]) -> Result<()> {
+        match (self.state, handshake) {
+            (State::WaitEncryptedExtensions, Handshake::EncryptedExtensions(e)) => {
+                let protocol = e
+                    .extensions
+                    .as_slice()
+                    .iter()
+                    .find_map(|x| match &x.content {
+                        ServerHelloExtensionContent::ApplicationLayerProtocolNegotiation(list) => {
+                            list.protocol_name_list.first()
+                        }
+                        _ => None,
+                    })
+                    .map(|x| String::from_utf8(x.data.to_vec()))
+                    .transpose()
+                    .map_err(|_| TlsAlert::IllegalParameter)?;
+                if protocol
+                    .as_ref()
+                    .is_some_and(|x| !self.config.alpn_protocols.contains(x))
+                {
+                    bail!(TlsAlert::IllegalParameter);
+                }
+                self.alpn = protocol;
+                self.state = State::WaitCertificate;
+            }
+            (State::WaitCertificate, Handshake::Certificate(certificate)) => {
+                let chain = certificate
+                    .certificate_list
+                    .as_slice()
+                    .iter()
+                    .map(|x| match &x.content {
+                        CertificateEntryContent::X509 { cert_data } => cert_data.as_slice(),
+                        CertificateEntryContent::RawPublicKey {
+                            asn1_subject_public_key_info,
+                        } => asn1_subject_public_key_info.as_slice(),
+                    })
+                    .collect::<Vec<_>>();
+                let leaf = chain.first().ok_or(TlsAlert::DecodeError)?;
+                if let Err(e) = self
+                    .config
+                    .verifier
+                    .verify_certificate(&chain, &self.server_name)
+                {
+                    tracing::debug!("Server certificate rejected: {e}");
+                    bail!(TlsAlert::BadCertificate);
+                }
+                self.certificate = Some(Box::from(*leaf));
+                self.state = State::WaitCertificateVerify;
+            }
+            (State::WaitCertificateVerify, Handshake::CertificateVerify(verify)) => {
+                if !SIGNATURE_SCHEMES.contains(&verify.algorithm) {
+                    bail!(TlsAlert::IllegalParameter);
+                }
+                let certificate = self.certificate.as_deref().ok_or(TlsAlert::InternalError)?;
+                let cipher_suite = self.cipher_suite.ok_or(TlsAlert::InternalError)?;
+                if let Err(e) = self.config.verifier.verify_signature(
+                    certificate,
+                    verify.algorithm,
+                    &server_signed_content(cipher_suite, &self.transcript),
+                    verify.signature.as_slice(),
+                ) {
+                    tracing::debug!("Server signature rejected: {e}");
+                    bail!(TlsAlert::DecryptError);
+                }
+                self.state = State::WaitFinished;
+            }
+            (State::WaitFinished, Handshake::Finished(finished)) => {
+                let schedule = self.schedule.take().ok_or(TlsAlert::InternalError)?;
+                if finished.verify_data != schedule.server_finished(&self.transcript) {
+                    bail!(TlsAlert::DecryptError);
+                }
+                self.transcript.extend(raw);
+
+                let (client_keys, server_keys) = schedule.application_keys(&self.transcript)?;
+                let verify_data = schedule.client_finished(&self.transcript);
+                self.write_record(&TlsPlaintext::new_handshake(Handshake::Finished(
+                    Finished { verify_data },
+                ))?)?;
+
+                self.read_keys = Some(server_keys);
+                self.write_keys = Some(client_keys);
+                self.state = State::Connected;
+                return Ok(());
+            }
+            _ => bail!(TlsAlert::UnexpectedMessage),
+        }
+
+        self.transcript.extend(raw);
+        Ok(())
+    }
+
+    /// Protect the record once keys are known.
+    fn write_record(&mut self, record: &TlsPlaintext) -> Result<()> {
+        match &mut self.write_keys {
+            Some(keys) => self.output.extend(keys.encrypt(record)?.to_raw()),
+            None => self.output.extend(record.to_raw()),
+        }
+        Ok(())
+    }
+
+    fn send_alert(&mut self, alert: TlsAlert) -> Result<()> {
+        let description = AlertDescription::try_from(alert.code())?;
+        self.write_record(&TlsPlaintext::new_alert(Alert {
+            level: AlertLevel::Fatal,
+            description,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crypt::{
+        hash::{Hasher, sha::Sha256},
+        rsa::PublicKey,
+    };
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::server::{
+        ServerConfig, ServerConnection,
+        tests::{MODULUS, config},
+    };
+
+    struct TestVerifier {
+        reject_signature: bool,
+    }
+
+    impl ServerCertVerifier for TestVerifier {
+        fn verify_certificate(&self, certificates: &[&[u8]], server_name: &str) -> Result<()> {
+            assert_eq!(certificates, [[0x30, 0x00].as_slice()]);
+            assert_eq!(server_name, "example.com");
+            Ok(())
+        }
+
+        fn verify_signature(
+            &self,
+            _certificate: &[u8],
+            scheme: SignatureScheme,
+            message: &[u8],
+            signature: &[u8],
+        ) -> Result<()> {
+            assert_eq!(scheme, SignatureScheme::rsa_pss_rsae_sha256);
+            if self.reject_signature {
+                bail!("Rejected");
+            }
+            let key = PublicKey {
+                modulus: BigUint::from_bytes_be(&MODULUS),
+                exponent: BigUint::from(65537u32),
+            };
+            crypt::rsa::rsassa_pss_verify::<Sha256, { Sha256::DIGEST_SIZE }>(
+                &key, message, signature,
+            )
+        }
+    }
+
+    fn client_config(reject_signature: bool) -> ClientConfig {
+        ClientConfig {
+            alpn_protocols: vec![String::from("h2")],
+            verifier: Box::new(TestVerifier { reject_signature }),
+        }
+    }
+
+    /// Exchange output until both sides are quiet, returns their events.
+    fn pump(
+        client: &mut ClientConnection,
+        server: &mut ServerConnection,
+    ) -> Result<(Vec<Event>, Vec<Event>)> {
+        let mut client_events = Vec::new();
+        let mut server_events = Vec::new();
+        loop {
+            let to_server = client.take_output();
+            server_events.extend(server.receive(&to_server)?);
+            let to_client = server.take_output();
+            client_events.extend(client.receive(&to_client)?);
+            if to_server.is_empty() && to_client.is_empty() {
+                return Ok((client_events, server_events));
+            }
+        }
+    }
+
+    #[test]
+    fn test_loopback() -> Result<()> {
+        let server_config = config();
+        let client_config = client_config(false);
+        let mut server = ServerConnection::new(&server_config);
+        let mut client = ClientConnection::new(&client_config, "example.com")?;
+
+        let (client_events, server_events) = pump(&mut client, &mut server)?;
+        assert_eq!(client_events, [Event::HandshakeComplete]);
+        assert_eq!(server_events, [Event::HandshakeComplete]);
+        assert_eq!(client.alpn(), Some("h2"));
+        assert_eq!(server.info().and_then(|x| x.alpn.as_deref()), Some("h2"));
+
+        client.send(b"ping")?;
+        server.send(b"pong")?;
+        let (client_events, server_events) = pump(&mut client, &mut server)?;
+        assert_eq!(client_events, [Event::ApplicationData(Box::from(*b"pong"))]);
+        assert_eq!(server_events, [Event::ApplicationData(Box::from(*b"ping"))]);
+
+        client.close()?;
+        let (_, server_events) = pump(&mut client, &mut server)?;
+        assert_eq!(server_events, [Event::Closed]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_into_parts() -> Result<()> {
+        let server_config = config();
+        let client_config = client_config(false);
+        let mut server = ServerConnection::new(&server_config);
+        let mut client = ClientConnection::new(&client_config, "example.com")?;
+
+        server.receive(&client.take_output())?;
+        client.receive(&server.take_output())?;
+        // Client Finished and the first data arrive together
+        client.send(b"ping")?;
+        assert_eq!(
+            server.receive(&client.take_output())?,
+            [Event::HandshakeComplete]
+        );
+        let (mut records, _, _) = server.into_parts()?;
+        assert_eq!(
+            records.next_event()?,
+            Some(Event::ApplicationData(Box::from(*b"ping")))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_signature() -> Result<()> {
+        let server_config = config();
+        let client_config = client_config(true);
+        let mut server = ServerConnection::new(&server_config);
+        let mut client = ClientConnection::new(&client_config, "example.com")?;
+
+        server.receive(&client.take_output())?;
+        let e = client.receive(&server.take_output()).err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::DecryptError)
+        );
+        assert_eq!(client.state(), State::Closed);
+
+        // The alert is protected with the client handshake keys
+        assert!(server.receive(&client.take_output()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_server_hello() -> Result<()> {
+        let client_config = client_config(false);
+
+        let inputs: [&[u8]; 3] = [
+            // Empty ServerHello
+            &[22, 3, 3, 0, 4, 2, 0, 0, 0],
+            // ServerHello cut after the session id length
+            &[&[22, 3, 3, 0, 39, 2, 0, 0, 35, 3, 3][..], &[0; 32], &[32]].concat(),
+            // Unknown handshake type
+            &[22, 3, 3, 0, 4, 3, 0, 0, 0],
+        ];
+        for raw in inputs {
+            let mut client = ClientConnection::new(&client_config, "example.com")?;
+            client.take_output();
+            let e = client.receive(raw).err();
+            assert_eq!(
+                e.and_then(|x| x.downcast().ok()),
+                Some(TlsAlert::DecodeError),
+                "{raw:02x?}"
+            );
+            assert_eq!(client.state(), State::Closed);
+        }
+
+        // Server refusing the ClientHello
+        let mut client = ClientConnection::new(&client_config, "example.com")?;
+        client.take_output();
+        let e = client.receive(&[21, 3, 3, 0, 2, 2, 40]).err();
+        assert!(
+            e.as_ref()
+                .is_some_and(|x| x.downcast_ref::<TlsAlert>().is_none())
+        );
+        assert!(e.is_some_and(|x| x.to_string().starts_with("Received alert")));
+        assert_eq!(client.state(), State::Closed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cipher_suites() -> Result<()> {
+        let client_config = client_config(false);
+        for suite in SUPPORTED_CIPHER_SUITES {
+            let server_config = ServerConfig {
+                cipher_suites: vec![suite],
+                ..config()
+            };
+            let mut server = ServerConnection::new(&server_config);
+            let mut client = ClientConnection::new(&client_config, "example.com")?;
+            pump(&mut client, &mut server)?;
+            assert_eq!(client.cipher_suite(), Some(suite));
+            assert_eq!(server.info().map(|x| x.cipher_suite), Some(suite));
+
+            client.send(b"ping")?;
+            let (_, server_events) = pump(&mut client, &mut server)?;
+            assert_eq!(server_events, [Event::ApplicationData(Box::from(*b"ping"))]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    error::TlsAlert,
+    key_schedule::TrafficKeys,
+    parse::RawDeser,
+    record::{
+        TlsCiphertext,
+        alert::{Alert, AlertDescription},
+        content_types,
+        handshake::handshake_types,
+    },
+};
+
+/// Largest ciphertext record allowed by RFC 8446 §5.2.
+const MAX_RECORD_LENGTH: usize = (1 << 14) + 256;
+
+/// What a connection produced from received bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Both Finished messages were exchanged, application data can flow both ways
+    HandshakeComplete,
+    ApplicationData(Box<[u8]>),
+    /// Peer sent `close_notify`
+    Closed,
+}
+
+/// Split off the next complete record, `None` if more data is needed.
+pub(crate) fn next_record(received: &mut Vec<u8>) -> Option<Result<Box<[u8]>>> {
+    let header = received.get(..5)?;
+    let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+    if length > MAX_RECORD_LENGTH {
+        return Some(Err(TlsAlert::RecordOverflow.into()));
+    }
+    if received.len() < 5 + length {
+        return None;
+    }
+    Some(Ok(received.drain(..(5 + length)).collect()))
+}
+
+/// Largest plaintext fragment of a record, RFC 8446 §5.1.
+const MAX_FRAGMENT_LENGTH: usize = 1 << 14;
+
+/// Receiving half of an established connection.
+///
+/// Records are framed and decrypted one at a time, so bytes following the
+/// last record asked for can be taken unprocessed. Errors do not queue an
+/// alert, the sending half is kept separately
+pub struct RecordReader {
+    keys: TrafficKeys,
+    /// Received bytes not processed yet
+    received: Vec<u8>,
+    /// Post-handshake messages not complete yet
+    handshake: HandshakeBuffer,
+}
+
+impl RecordReader {
+    /// `keys` protect records sent by the peer.
+    pub fn new(keys: TrafficKeys) -> Self {
+        Self::with_received(keys, Vec::new())
+    }
+
+    pub(crate) fn with_received(keys: TrafficKeys, received: Vec<u8>) -> Self {
+        Self {
+            keys,
+            received,
+            handshake: HandshakeBuffer::default(),
+        }
+    }
+
+    /// Add bytes received from the peer.
+    pub fn push(&mut self, data: &[u8]) {
+        self.received.extend(data);
+    }
+
+    /// Process the next complete record, `None` if more data is needed.
+    ///
+    /// Yields application data and `close_notify`, post-handshake session
+    /// tickets are skipped
+    pub fn next_event(&mut self) -> Result<Option<Event>> {
+        while let Some(raw) = next_record(&mut self.received).transpose()? {
+            // change_cipher_spec is only allowed during the handshake, RFC 8446 §5
+            if raw[0] != content_types::APPLICATION_DATA {
+                bail!(TlsAlert::UnexpectedMessage);
+            }
+            let record = TlsCiphertext::from_raw(&raw).map_err(|_| TlsAlert::DecodeError)?;
+            let (content_type, content) = self
+                .keys
+                .open(&record)
+                .map_err(|e| e.downcast::<TlsAlert>().unwrap_or(TlsAlert::BadRecordMac))?;
+            if content.len() > MAX_FRAGMENT_LENGTH {
+                bail!(TlsAlert::RecordOverflow);
+            }
+            if content_type != content_types::HANDSHAKE && !self.handshake.is_empty() {
+                bail!(TlsAlert::UnexpectedMessage);
+            }
+
+            match content_type {
+                content_types::APPLICATION_DATA => {
+                    return Ok(Some(Event::ApplicationData(content)));
+                }
+                content_types::HANDSHAKE => {
+                    self.handshake.push(&content)?;
+                    while let Some(message) = self.handshake.next_message() {
+                        // KeyUpdate is not supported
+                        if message?[0] != handshake_types::NEW_SESSION_TICKET {
+                            bail!(TlsAlert::UnexpectedMessage);
+                        }
+                    }
+                }
+                content_types::ALERT => {
+                    let alert = Alert::deser(&content).map_err(|_| TlsAlert::DecodeError)?;
+                    if matches!(alert.description, AlertDescription::CloseNotify) {
+                        return Ok(Some(Event::Closed));
+                    }
+                    return Err(anyhow!("Received alert: {alert:?}"));
+                }
+                _ => bail!(TlsAlert::UnexpectedMessage),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether no partial record is pending.
+    pub fn is_empty(&self) -> bool {
+        self.received.is_empty()
+    }
+
+    /// Take received bytes that were not processed as records.
+    pub fn take_received(&mut self) -> Box<[u8]> {
+        std::mem::take(&mut self.received).into_boxed_slice()
+    }
+}
+
+/// Largest handshake message accepted, well above post-quantum ClientHellos.
+const MAX_HANDSHAKE_LENGTH: usize = 1 << 16;
+
+/// Reassembles handshake messages from record fragments, RFC 8446 §5.1.
+///
+/// A message may span several records and a record may hold several messages
+#[derive(Default)]
+pub(crate) struct HandshakeBuffer {
+    buffer: Vec<u8>,
+}
+
+impl HandshakeBuffer {
+    pub(crate) fn push(&mut self, fragment: &[u8]) -> Result<()> {
+        if fragment.is_empty() {
+            bail!(TlsAlert::UnexpectedMessage);
+        }
+        self.buffer.extend(fragment);
+        Ok(())
+    }
+
+    /// Split off the next complete message with its header, `None` if more data is needed.
+    pub(crate) fn next_message(&mut self) -> Option<Result<Box<[u8]>>> {
+        let header = self.buffer.get(..4)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        if length > MAX_HANDSHAKE_LENGTH {
+            return Some(Err(TlsAlert::IllegalParameter.into()));
+        }
+        if self.buffer.len() < 4 + length {
+            return None;
+        }
+        Some(Ok(self.buffer.drain(..(4 + length)).collect()))
+    }
+
+    /// Whether no partial message is pending.
+    ///
+    /// Handshake messages must not be interleaved with other content types
+    /// nor span a key change
+    pub(crate) fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cipher_suite::TLS_AES_128_GCM_SHA256,
+        record::{TlsPlaintext, alert::AlertLevel},
+    };
+
+    fn keys() -> Result<TrafficKeys> {
+        TrafficKeys::from_secret(TLS_AES_128_GCM_SHA256, &[1; 32])
+    }
+
+    #[test]
+    fn test_record_reader() -> Result<()> {
+        let mut peer = keys()?;
+        let mut reader = RecordReader::new(keys()?);
+
+        let data = peer
+            .seal(content_types::APPLICATION_DATA, b"ping")?
+            .to_raw();
+        reader.push(&data[..10]);
+        assert_eq!(reader.next_event()?, None);
+        assert!(!reader.is_empty());
+        reader.push(&data[10..]);
+        // Session tickets are skipped, bytes after the record are left alone
+        reader.push(
+            &peer
+                .seal(content_types::HANDSHAKE, &[4, 0, 0, 1, 0])?
+                .to_raw(),
+        );
+        reader.push(
+            &peer
+                .seal(content_types::APPLICATION_DATA, b"pong")?
+                .to_raw(),
+        );
+        reader.push(b"raw");
+        assert_eq!(
+            reader.next_event()?,
+            Some(Event::ApplicationData(Box::from(*b"ping")))
+        );
+        assert_eq!(
+            reader.next_event()?,
+            Some(Event::ApplicationData(Box::from(*b"pong")))
+        );
+        assert_eq!(&*reader.take_received(), b"raw");
+
+        let close_notify = Alert {
+            level: AlertLevel::Warning,
+            description: AlertDescription::CloseNotify,
+        };
+        reader.push(
+            &peer
+                .encrypt(&TlsPlaintext::new_alert(close_notify))?
+                .to_raw(),
+        );
+        assert_eq!(reader.next_event()?, Some(Event::Closed));
+        assert!(reader.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_reader_errors() -> Result<()> {
+        let alert = |raw: &[u8]| -> Result<Option<TlsAlert>> {
+            let mut reader = RecordReader::new(keys()?);
+            reader.push(raw);
+            Ok(reader.next_event().err().and_then(|x| x.downcast().ok()))
+        };
+        let mut peer = keys()?;
+
+        // Ciphertext longer than 2^14 + 256 is refused before it is read
+        let header = [23, 3, 3, 0x41, 0x01];
+        assert_eq!(alert(&header)?, Some(TlsAlert::RecordOverflow));
+        let oversized = peer.seal(content_types::APPLICATION_DATA, &[0; (1 << 14) + 1])?;
+        assert_eq!(alert(&oversized.to_raw())?, Some(TlsAlert::RecordOverflow));
+
+        assert_eq!(
+            alert(&[20, 3, 3, 0, 1, 1])?,
+            Some(TlsAlert::UnexpectedMessage)
+        );
+        assert_eq!(alert(&[23, 3, 3, 0, 17])?, None);
+        assert_eq!(
+            alert(&[&[23, 3, 3, 0, 17][..], &[0; 17]].concat())?,
+            Some(TlsAlert::BadRecordMac)
+        );
+        // KeyUpdate is not supported
+        let mut peer = keys()?;
+        let key_update = peer.seal(content_types::HANDSHAKE, &[24, 0, 0, 1, 0])?;
+        assert_eq!(
+            alert(&key_update.to_raw())?,
+            Some(TlsAlert::UnexpectedMessage)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_handshake_buffer() -> Result<()> {
+        let mut buffer = HandshakeBuffer::default();
+        // Two messages, the second split across fragments
+        buffer.push(&[20, 0, 0, 2, 1, 2, 20, 0])?;
+        assert_eq!(
+            buffer.next_message().transpose()?.as_deref(),
+            Some(&[20, 0, 0, 2, 1, 2][..])
+        );
+        assert!(buffer.next_message().is_none());
+        buffer.push(&[0, 1])?;
+        assert!(buffer.next_message().is_none());
+        assert!(!buffer.is_empty());
+        buffer.push(&[3])?;
+        assert_eq!(
+            buffer.next_message().transpose()?.as_deref(),
+            Some(&[20, 0, 0, 1, 3][..])
+        );
+        assert!(buffer.is_empty());
+        assert!(buffer.push(&[]).is_err());
+        Ok(())
+    }
+}
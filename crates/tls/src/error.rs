@@ -1,34 +1,42 @@
 use strum_macros::Display;
 
 /// <https://datatracker.ietf.org/doc/html/draft-ietf-tls-rfc8446bis-14#name-alert-protocol>
-#[derive(thiserror::Error, Debug, Display)]
+#[repr(u8)]
+#[derive(thiserror::Error, Clone, Copy, Debug, Display, PartialEq, Eq)]
 pub enum TlsAlert {
-    CloseNotify,
-    UnexpectedMessage,
-    BadRecordMac,
-    RecordOverflow,
-    HandshakeFailure,
-    BadCertificate,
-    UnsupportedCertificate,
-    CertificateRevoked,
-    CertificateExpired,
-    CertificateUnknown,
-    IllegalParameter,
-    UnknownCa,
-    AccessDenied,
-    DecodeError,
-    DecryptError,
-    ProtocolVersion,
-    InsufficientSecurity,
-    InternalError,
-    InappropriateFallback,
-    UserCanceled,
-    MissingExtension,
-    UnsupportedExtension,
-    UnrecognizedName,
-    BadCertificateStatusResponse,
-    UnknownPskIdentity,
-    CertificateRequired,
-    GeneralError,
-    NoApplicationProtocol,
+    CloseNotify = 0,
+    UnexpectedMessage = 10,
+    BadRecordMac = 20,
+    RecordOverflow = 22,
+    HandshakeFailure = 40,
+    BadCertificate = 42,
+    UnsupportedCertificate = 43,
+    CertificateRevoked = 44,
+    CertificateExpired = 45,
+    CertificateUnknown = 46,
+    IllegalParameter = 47,
+    UnknownCa = 48,
+    AccessDenied = 49,
+    DecodeError = 50,
+    DecryptError = 51,
+    ProtocolVersion = 70,
+    InsufficientSecurity = 71,
+    InternalError = 80,
+    InappropriateFallback = 86,
+    UserCanceled = 90,
+    MissingExtension = 109,
+    UnsupportedExtension = 110,
+    UnrecognizedName = 112,
+    BadCertificateStatusResponse = 113,
+    UnknownPskIdentity = 115,
+    CertificateRequired = 116,
+    GeneralError = 117,
+    NoApplicationProtocol = 120,
+}
+
+impl TlsAlert {
+    /// Alert description as sent on the wire.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
 }
@@ -0,0 +1,231 @@
+use anyhow::Result;
+
+use crate::{
+    cipher_suite::CipherSuite,
+    record::{TlsCiphertext, TlsPlaintext},
+};
+
+/// Record protection keys for one direction of the connection.
+///
+/// Each direction counts its own records for the nonce
+pub struct TrafficKeys {
+    suite: CipherSuite,
+    key: Box<[u8]>,
+    iv: [u8; 12],
+    seq: u64,
+}
+
+impl TrafficKeys {
+    pub fn from_secret(suite: CipherSuite, traffic_secret: &[u8]) -> Result<Self> {
+        #[allow(clippy::cast_possible_truncation)]
+        let key_length = suite.key_length() as u16;
+        Ok(Self {
+            suite,
+            key: suite.hkdf_expand_label(traffic_secret, "key", &[], key_length),
+            iv: suite
+                .hkdf_expand_label(traffic_secret, "iv", &[], 12)
+                .as_ref()
+                .try_into()?,
+            seq: 0,
+        })
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        nonce
+    }
+
+    pub fn encrypt(&mut self, record: &TlsPlaintext) -> Result<TlsCiphertext> {
+        let nonce = self.next_nonce();
+        TlsCiphertext::encrypt(record, self.suite, &self.key, nonce)
+    }
+
+    /// Encrypt already serialized content of the given type.
+    pub fn seal(&mut self, content_type: u8, content: &[u8]) -> Result<TlsCiphertext> {
+        let nonce = self.next_nonce();
+        TlsCiphertext::seal(content_type, content, self.suite, &self.key, nonce)
+    }
+
+    pub fn decrypt(&mut self, record: &TlsCiphertext) -> Result<TlsPlaintext> {
+        let nonce = self.next_nonce();
+        record.decrypt(self.suite, &self.key, nonce)
+    }
+
+    /// Decrypt into the inner content type and unparsed content.
+    pub fn open(&mut self, record: &TlsCiphertext) -> Result<(u8, Box<[u8]>)> {
+        let nonce = self.next_nonce();
+        record.open(self.suite, &self.key, nonce)
+    }
+}
+
+/// TLS 1.3 key schedule of a full handshake without PSK.
+///
+/// Transcripts are the concatenated handshake messages, record headers excluded
+pub struct KeySchedule {
+    suite: CipherSuite,
+    client_handshake_traffic_secret: Box<[u8]>,
+    server_handshake_traffic_secret: Box<[u8]>,
+    main_secret: Box<[u8]>,
+}
+
+impl KeySchedule {
+    /// `transcript` runs up to and including ServerHello.
+    pub fn new(suite: CipherSuite, shared_secret: &[u8], transcript: &[u8]) -> Self {
+        let zeros = vec![0; suite.hash_length()];
+        let early_secret = suite.hkdf_extract(&zeros, &zeros);
+
+        let handshake_secret = suite.hkdf_extract(
+            &suite.derive_secret(&early_secret, "derived", &[]),
+            shared_secret,
+        );
+
+        Self {
+            suite,
+            client_handshake_traffic_secret: suite.derive_secret(
+                &handshake_secret,
+                "c hs traffic",
+                transcript,
+            ),
+            server_handshake_traffic_secret: suite.derive_secret(
+                &handshake_secret,
+                "s hs traffic",
+                transcript,
+            ),
+            main_secret: suite.hkdf_extract(
+                &suite.derive_secret(&handshake_secret, "derived", &[]),
+                &zeros,
+            ),
+        }
+    }
+
+    pub fn client_handshake_keys(&self) -> Result<TrafficKeys> {
+        TrafficKeys::from_secret(self.suite, &self.client_handshake_traffic_secret)
+    }
+
+    pub fn server_handshake_keys(&self) -> Result<TrafficKeys> {
+        TrafficKeys::from_secret(self.suite, &self.server_handshake_traffic_secret)
+    }
+
+    fn finished(&self, traffic_secret: &[u8], transcript: &[u8]) -> Box<[u8]> {
+        #[allow(clippy::cast_possible_truncation)]
+        let finished_key = self.suite.hkdf_expand_label(
+            traffic_secret,
+            "finished",
+            &[],
+            self.suite.hash_length() as u16,
+        );
+        self.suite.hmac(&finished_key, &self.suite.hash(transcript))
+    }
+
+    /// Client Finished `verify_data`, `transcript` runs up to server Finished.
+    pub fn client_finished(&self, transcript: &[u8]) -> Box<[u8]> {
+        self.finished(&self.client_handshake_traffic_secret, transcript)
+    }
+
+    /// Server Finished `verify_data`, `transcript` runs up to CertificateVerify.
+    pub fn server_finished(&self, transcript: &[u8]) -> Box<[u8]> {
+        self.finished(&self.server_handshake_traffic_secret, transcript)
+    }
+
+    /// Client and server application traffic keys.
+    ///
+    /// `transcript` runs up to server Finished
+    pub fn application_keys(&self, transcript: &[u8]) -> Result<(TrafficKeys, TrafficKeys)> {
+        let client = self
+            .suite
+            .derive_secret(&self.main_secret, "c ap traffic", transcript);
+        let server = self
+            .suite
+            .derive_secret(&self.main_secret, "s ap traffic", transcript);
+
+        Ok((
+            TrafficKeys::from_secret(self.suite, &client)?,
+            TrafficKeys::from_secret(self.suite, &server)?,
+        ))
+    }
+}
+
+/// Content signed in CertificateVerify by the server.
+///
+/// The transcript is hashed with the hash of the negotiated suite
+pub fn server_signed_content(suite: CipherSuite, transcript: &[u8]) -> Box<[u8]> {
+    let mut content = [0x20].repeat(64);
+    content.extend(b"TLS 1.3, server CertificateVerify");
+    content.push(0);
+    content.extend(suite.hash(transcript));
+    content.into_boxed_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::{
+        cipher_suite::TLS_AES_128_GCM_SHA256,
+        record::handshake::{Handshake, finished::Finished},
+    };
+
+    /// RFC 8448 §3, Simple 1-RTT Handshake
+    #[test]
+    fn test_rfc8448() -> Result<()> {
+        let client_hello = hex!(
+            "010000c00303cb34ecb1e78163ba1c38c6dacb196a6dffa21a8d9912ec18a2ef6283024dece7
+             000006130113031302010000910000000b0009000006736572766572ff01000100000a0014
+             0012001d0017001800190100010101020103010400230000003300260024001d002099381d
+             e560e4bd43d23d8e435a7dbafeb3c06e51c13cae4d5413691e529aaf2c002b000302030400
+             0d0020001e040305030603020308040805080604010501060102010402050206020202002d
+             00020101001c00024001"
+        );
+        let server_hello = hex!(
+            "020000560303a6af06a4121860dc5e6e60249cd34c95930c8ac5cb1434dac155772ed3e269
+             2800130100002e00330024001d0020c9828876112095fe66762bdbf7c672e156d6cc253b83
+             3df1dd69b1b04e751f0f002b00020304"
+        );
+        let shared_secret =
+            hex!("8bd4054fb55b9d63fdfbacf9f04b9f0d35e6d63f537563efd46272900f89492d");
+
+        let schedule = KeySchedule::new(
+            TLS_AES_128_GCM_SHA256,
+            &shared_secret,
+            &[client_hello.as_slice(), &server_hello].concat(),
+        );
+        assert_eq!(
+            *schedule.client_handshake_traffic_secret,
+            hex!("b3eddb126e067f35a780b3abf45e2d8f3b1a950738f52e9600746a0e27a55a21")
+        );
+        assert_eq!(
+            *schedule.server_handshake_traffic_secret,
+            hex!("b67b7d690cc16c4e75e54213cb2d37b4e9c912bcded9105d42befd59d391ad38")
+        );
+        assert_eq!(
+            *schedule.main_secret,
+            hex!("18df06843d13a08bf2a449844c5f8a478001bc4d4c627984d5a41da8d0402919")
+        );
+
+        let server_keys = schedule.server_handshake_keys()?;
+        assert_eq!(*server_keys.key, hex!("3fce516009c21727d0f2e4e86ee403bc"));
+        assert_eq!(server_keys.iv, hex!("5d313eb2671276ee13000b30"));
+
+        // Client Finished record
+        let mut client_keys = schedule.client_handshake_keys()?;
+        let finished = TlsPlaintext::new_handshake(Handshake::Finished(Finished {
+            verify_data: Box::new(hex!(
+                "a8ec436d677634ae525ac1fcebe11a039ec17694fac6e98527b642f2edd5ce61"
+            )),
+        }))?;
+        assert_eq!(
+            *client_keys.encrypt(&finished)?.to_raw(),
+            hex!(
+                "170303003575ec4dc238cce60b298044a71e219c56cc77b0517fe9b93c7a4bfc44d87f38f8
+                 0338ac98fc46deb384bd1caeacab6867d726c40546"
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -3,12 +3,18 @@
 #![forbid(clippy::unwrap_used)]
 
 pub mod cipher_suite;
+pub mod client;
+pub mod connection;
 pub mod error;
 pub mod hkdf;
+pub mod key_schedule;
 pub(crate) mod macros;
 pub(crate) mod parse;
 pub mod record;
+pub mod server;
 pub(crate) mod util;
 
+/// TLS 1.3 in `supported_versions`.
+pub const VERSION: u16 = 0x0304;
 pub const LEGACY_VERSION: u16 = 0x0303;
 pub const LEGACY_VERSION_BYTES: &[u8] = &[0x03, 0x03];
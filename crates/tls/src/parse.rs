@@ -10,7 +10,9 @@ pub use data_vec_24::DataVec24;
 pub mod u16;
 pub mod u8;
 
-use anyhow::Result;
+use std::slice::SliceIndex;
+
+use anyhow::{Result, anyhow};
 
 pub trait RawSize {
     fn size(&self) -> usize;
@@ -23,3 +25,18 @@ pub trait RawSer {
 pub trait RawDeser: Sized {
     fn deser(raw: &[u8]) -> Result<Self>;
 }
+
+/// Read the first `N` bytes of `raw`.
+pub fn take<const N: usize>(raw: &[u8]) -> Result<[u8; N]> {
+    raw.get(..N)
+        .and_then(|x| x.try_into().ok())
+        .ok_or(anyhow!("Unexpected end of input"))
+}
+
+/// Get `raw[range]`, failing if `raw` is too short.
+pub fn slice<R>(raw: &[u8], range: R) -> Result<&[u8]>
+where
+    R: SliceIndex<[u8], Output = [u8]>,
+{
+    raw.get(range).ok_or(anyhow!("Unexpected end of input"))
+}
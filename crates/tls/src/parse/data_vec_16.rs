@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::{RawDeser, RawSer, RawSize};
+use super::{RawDeser, RawSer, RawSize, slice, take};
 
 #[derive(Clone, Debug)]
 pub struct DataVec16<T> {
@@ -19,6 +19,10 @@ impl<T> DataVec16<T> {
     pub fn into_inner(self) -> Box<[T]> {
         self.inner
     }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.inner
+    }
 }
 
 impl<T> TryFrom<&[T]> for DataVec16<T>
@@ -49,8 +53,8 @@ where
     T: RawSize + RawDeser,
 {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let length = u16::from_be_bytes([raw[0], raw[1]]);
-        let payload = &raw[2..];
+        let length = u16::from_be_bytes(take(raw)?);
+        let payload = slice(raw, 2..(2 + length as usize))?;
 
         let mut res = Vec::new();
         let mut offset: usize = 0;
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::{RawDeser, RawSer, RawSize};
+use super::{RawDeser, RawSer, RawSize, slice, take};
 
 #[derive(Clone, Debug)]
 pub struct DataVec24<T> {
@@ -19,6 +19,10 @@ impl<T> DataVec24<T> {
     pub fn into_inner(self) -> Box<[T]> {
         self.inner
     }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.inner
+    }
 }
 
 impl<T> TryFrom<&[T]> for DataVec24<T>
@@ -49,8 +53,9 @@ where
     T: RawSize + RawDeser,
 {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let length = u32::from_be_bytes([0, raw[0], raw[1], raw[2]]);
-        let payload = &raw[3..];
+        let [a, b, c] = take(raw)?;
+        let length = u32::from_be_bytes([0, a, b, c]);
+        let payload = slice(raw, 3..(3 + length as usize))?;
 
         let mut res = Vec::new();
         let mut offset: usize = 0;
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::{RawDeser, RawSer, RawSize};
+use super::{RawDeser, RawSer, RawSize, slice, take};
 
 #[derive(Clone, Debug)]
 pub struct DataVec8<T> {
@@ -19,6 +19,10 @@ impl<T> DataVec8<T> {
     pub fn into_inner(self) -> Box<[T]> {
         self.inner
     }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.inner
+    }
 }
 
 impl<T> TryFrom<&[T]> for DataVec8<T>
@@ -49,8 +53,8 @@ where
     T: RawSize + RawDeser,
 {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let length = raw[0];
-        let payload = &raw[1..];
+        let [length] = take(raw)?;
+        let payload = slice(raw, 1..(1 + length as usize))?;
 
         let mut res = Vec::new();
         let mut offset: usize = 0;
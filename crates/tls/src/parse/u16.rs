@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::{RawDeser, RawSer, RawSize};
+use super::{RawDeser, RawSer, RawSize, take};
 
 impl RawSize for u16 {
     fn size(&self) -> usize {
@@ -10,7 +10,7 @@ impl RawSize for u16 {
 
 impl RawDeser for u16 {
     fn deser(raw: &[u8]) -> Result<Self> {
-        Ok(u16::from_be_bytes([raw[0], raw[1]]))
+        Ok(u16::from_be_bytes(take(raw)?))
     }
 }
 
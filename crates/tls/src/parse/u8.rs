@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use super::{RawDeser, RawSer, RawSize};
+use super::{RawDeser, RawSer, RawSize, take};
 
 impl RawSize for u8 {
     fn size(&self) -> usize {
@@ -10,7 +10,8 @@ impl RawSize for u8 {
 
 impl RawDeser for u8 {
     fn deser(raw: &[u8]) -> Result<Self> {
-        Ok(raw[0])
+        let [res] = take(raw)?;
+        Ok(res)
     }
 }
 
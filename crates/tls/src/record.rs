@@ -6,12 +6,14 @@ pub mod handshake;
 use alert::Alert;
 use handshake::Handshake;
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, bail};
 use utils::concat_dyn;
 
 use crate::{
     LEGACY_VERSION_BYTES,
-    parse::{RawDeser, RawSer},
+    cipher_suite::{CipherSuite, TAG_LENGTH},
+    error::TlsAlert,
+    parse::{RawDeser, RawSer, slice, take},
 };
 
 pub mod content_types {
@@ -28,7 +30,7 @@ pub enum TlsContent {
     ChangeCipherSpec,
     Alert(Alert),
     Handshake(Handshake),
-    ApplicationData,
+    ApplicationData(Box<[u8]>),
 }
 
 impl TlsContent {
@@ -38,7 +40,7 @@ impl TlsContent {
             TlsContent::ChangeCipherSpec => content_types::CHANGE_CIPHER_SPEC,
             TlsContent::Alert(_) => content_types::ALERT,
             TlsContent::Handshake(_) => content_types::HANDSHAKE,
-            TlsContent::ApplicationData => content_types::APPLICATION_DATA,
+            TlsContent::ApplicationData(_) => content_types::APPLICATION_DATA,
         }
     }
 }
@@ -46,11 +48,11 @@ impl TlsContent {
 impl RawSer for TlsContent {
     fn ser(&self) -> Box<[u8]> {
         match self {
-            TlsContent::Invalid => todo!(),
-            TlsContent::ChangeCipherSpec => todo!(),
+            TlsContent::Invalid => Box::new([]),
+            TlsContent::ChangeCipherSpec => Box::new([1]),
             TlsContent::Alert(alert) => alert.ser(),
             TlsContent::Handshake(handshake) => handshake.ser(),
-            TlsContent::ApplicationData => todo!(),
+            TlsContent::ApplicationData(data) => data.clone(),
         }
     }
 }
@@ -76,19 +78,19 @@ impl RawSer for TlsPlaintext {
 
 impl TlsPlaintext {
     pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let content_type = raw[0];
-        let length = u16::from_be_bytes([raw[3], raw[4]]);
+        let [content_type, _, _, a, b] = take(raw)?;
+        let length = u16::from_be_bytes([a, b]);
 
-        let data = &raw[5..];
+        let data = slice(raw, 5..(5 + length as usize))?;
 
         let record = match content_type {
             content_types::INVALID => TlsContent::Invalid,
             content_types::CHANGE_CIPHER_SPEC => TlsContent::ChangeCipherSpec,
             content_types::ALERT => TlsContent::Alert(Alert::deser(data)?),
             content_types::HANDSHAKE => TlsContent::Handshake(Handshake::deser(data)?),
-            content_types::APPLICATION_DATA => TlsContent::ApplicationData,
-
-            _ => todo!(),
+            content_types::APPLICATION_DATA => TlsContent::ApplicationData(Box::from(data)),
+            // RFC 8446 §5
+            _ => bail!(TlsAlert::UnexpectedMessage),
         };
 
         Ok(Self {
@@ -104,6 +106,20 @@ impl TlsPlaintext {
         })
     }
 
+    pub fn new_alert(alert: Alert) -> Self {
+        Self {
+            length: 2,
+            fragment: TlsContent::Alert(alert),
+        }
+    }
+
+    pub fn new_application_data(data: &[u8]) -> Result<Self> {
+        Ok(Self {
+            length: data.len().try_into()?,
+            fragment: TlsContent::ApplicationData(Box::from(data)),
+        })
+    }
+
     pub fn to_raw(&self) -> Box<[u8]> {
         self.ser()
     }
@@ -118,16 +134,33 @@ pub struct TlsCiphertext {
 }
 
 impl TlsCiphertext {
-    pub fn encrypt(plain: &TlsPlaintext, key: [u8; 32], nonce: [u8; 12]) -> Result<Self> {
-        let content = plain.fragment.ser();
-        let content_type = plain.fragment.content_type();
+    pub fn encrypt(
+        plain: &TlsPlaintext,
+        suite: CipherSuite,
+        key: &[u8],
+        nonce: [u8; 12],
+    ) -> Result<Self> {
+        Self::seal(
+            plain.fragment.content_type(),
+            &plain.fragment.ser(),
+            suite,
+            key,
+            nonce,
+        )
+    }
+
+    /// Encrypt already serialized content, such as several coalesced handshake messages.
+    pub fn seal(
+        content_type: u8,
+        content: &[u8],
+        suite: CipherSuite,
+        key: &[u8],
+        nonce: [u8; 12],
+    ) -> Result<Self> {
         let padding: Vec<u8> = vec![];
         let plaintext = concat_dyn!(content, [content_type], &padding);
 
-        #[allow(clippy::cast_possible_truncation)]
-        let plaintext_length = plain.length + padding.len() as u16 + 1;
-
-        let length = plaintext_length + 16;
+        let length = u16::try_from(plaintext.len() + TAG_LENGTH)?;
 
         let additional_data = concat_dyn!(
             [content_types::APPLICATION_DATA],
@@ -135,9 +168,7 @@ impl TlsCiphertext {
             length.to_be_bytes()
         );
 
-        let (ciphertext, tag) =
-            crypt::aead::aes_gcm::encrypt_aes_256_gcm(&key, &nonce, &plaintext, &additional_data)?;
-        let encrypted_record = concat_dyn!(ciphertext, tag);
+        let encrypted_record = suite.seal(key, nonce, &plaintext, &additional_data)?;
 
         Ok(Self {
             length,
@@ -145,38 +176,38 @@ impl TlsCiphertext {
         })
     }
 
-    pub fn decrypt(&self, key: [u8; 32], nonce: [u8; 12]) -> Result<TlsPlaintext> {
+    pub fn decrypt(&self, suite: CipherSuite, key: &[u8], nonce: [u8; 12]) -> Result<TlsPlaintext> {
+        let (content_type, content) = self.open(suite, key, nonce)?;
+
+        TlsPlaintext::from_raw(&concat_dyn!(
+            [content_type],
+            LEGACY_VERSION_BYTES,
+            u16::try_from(content.len())?.to_be_bytes(),
+            content
+        ))
+    }
+
+    /// Decrypt into the inner content type and content, padding removed.
+    ///
+    /// Unlike `decrypt` the content is not parsed, as it may hold partial
+    /// or several handshake messages
+    pub fn open(&self, suite: CipherSuite, key: &[u8], nonce: [u8; 12]) -> Result<(u8, Box<[u8]>)> {
         let additional_data = concat_dyn!(
             [content_types::APPLICATION_DATA],
             LEGACY_VERSION_BYTES,
             self.length.to_be_bytes()
         );
 
-        let (ciphertext, tag) = self
-            .encrypted_record
-            .split_at(self.encrypted_record.len() - 16);
-
         // AEAD-Decrypt(peer_write_key, nonce, additional_data, AEADEncrypted)
 
-        let plaintext = crypt::aead::aes_gcm::decrypt_aes_256_gcm(
-            &key,
-            &nonce,
-            ciphertext,
-            &additional_data,
-            tag,
-        )?;
+        let plaintext = suite.open(key, nonce, &self.encrypted_record, &additional_data)?;
 
-        let index = plaintext.iter().rposition(|x| *x != 0).ok_or(anyhow!(""))?;
-
-        let content = &plaintext[..index];
-        let content_type = plaintext[index];
+        // Content type is the last non-zero byte, RFC 8446 §5.4
+        let Some(index) = plaintext.iter().rposition(|x| *x != 0) else {
+            bail!(TlsAlert::UnexpectedMessage);
+        };
 
-        TlsPlaintext::from_raw(&concat_dyn!(
-            [content_type],
-            LEGACY_VERSION_BYTES,
-            (content.len() as u16).to_be_bytes(),
-            content
-        ))
+        Ok((plaintext[index], Box::from(&plaintext[..index])))
     }
 
     pub fn to_raw(&self) -> Box<[u8]> {
@@ -193,8 +224,9 @@ impl RawDeser for TlsCiphertext {
         // let opaque_type = raw[0];
         // let legacy_record_version = u16::from_be_bytes([raw[1], raw[2]]);
 
-        let length = u16::from_be_bytes([raw[3], raw[4]]);
-        let encrypted_record = Box::from(&raw[5..(5 + length as usize)]);
+        let [_, _, _, a, b] = take(raw)?;
+        let length = u16::from_be_bytes([a, b]);
+        let encrypted_record = Box::from(slice(raw, 5..(5 + length as usize))?);
 
         Ok(Self {
             length,
@@ -213,3 +245,30 @@ impl RawSer for TlsCiphertext {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cipher_suite::TLS_AES_128_GCM_SHA256;
+
+    #[test]
+    fn test_malformed_records() -> Result<()> {
+        for raw in [&[][..], &[23, 3, 3, 0], &[23, 3, 3, 0, 4, 1, 2]] {
+            assert!(TlsPlaintext::from_raw(raw).is_err(), "{raw:02x?}");
+            assert!(TlsCiphertext::from_raw(raw).is_err(), "{raw:02x?}");
+        }
+
+        // Authenticated records of an unknown content type or without one
+        let key = [0; 16];
+        for (content_type, content) in [(24, &b"data"[..]), (0, &[0; 4])] {
+            let record =
+                TlsCiphertext::seal(content_type, content, TLS_AES_128_GCM_SHA256, &key, [0; 12])?;
+            let e = record.decrypt(TLS_AES_128_GCM_SHA256, &key, [0; 12]).err();
+            assert_eq!(
+                e.and_then(|x| x.downcast().ok()),
+                Some(TlsAlert::UnexpectedMessage)
+            );
+        }
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use crate::{
     macros::auto_try_from,
-    parse::{RawDeser, RawSer},
+    parse::{RawDeser, RawSer, take},
 };
 
 auto_try_from! {
@@ -57,8 +57,9 @@ pub struct Alert {
 
 impl RawDeser for Alert {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let level = AlertLevel::try_from(raw[0])?;
-        let description = AlertDescription::try_from(raw[1])?;
+        let [level, description] = take(raw)?;
+        let level = AlertLevel::try_from(level)?;
+        let description = AlertDescription::try_from(description)?;
 
         Ok(Self { level, description })
     }
@@ -15,9 +15,9 @@ use encrypted_extensions::EncryptedExtensions;
 use finished::Finished;
 use server_hello::ServerHello;
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail};
 
-use crate::parse::{RawDeser, RawSer};
+use crate::parse::{RawDeser, RawSer, take};
 
 pub mod handshake_types {
     pub const CLIENT_HELLO: u8 = 1;
@@ -51,30 +51,32 @@ pub enum Handshake {
 
 impl RawDeser for Handshake {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let msg_type = raw[0];
-        let data = &raw[1..];
-        let _length = u32::from_be_bytes([0, raw[1], raw[2], raw[3]]);
+        let [msg_type, a, b, c] = take(raw)?;
+        let length = u32::from_be_bytes([0, a, b, c]);
+        let body = raw
+            .get(4..(4 + length as usize))
+            .ok_or(anyhow!("Handshake message is shorter than its length"))?;
 
         Ok(match msg_type {
-            handshake_types::CLIENT_HELLO => Self::ClientHello(ClientHello::deser(data)?),
-            handshake_types::SERVER_HELLO => todo!(),
+            handshake_types::CLIENT_HELLO => Self::ClientHello(ClientHello::deser(body)?),
+            handshake_types::SERVER_HELLO => Self::ServerHello(ServerHello::deser(body)?),
             handshake_types::NEW_SESSION_TICKET => Self::NewSessionTicket,
             handshake_types::END_OF_EARLY_DATA => Self::EndOfEarlyData,
             handshake_types::ENCRYPTED_EXTENSIONS => {
-                Self::EncryptedExtensions(EncryptedExtensions::deser(data)?)
+                Self::EncryptedExtensions(EncryptedExtensions::deser(body)?)
             }
-            handshake_types::CERTIFICATE => Self::Certificate(Certificate::deser(data)?),
+            handshake_types::CERTIFICATE => Self::Certificate(Certificate::deser(body)?),
             handshake_types::CERTIFICATE_REQUEST => {
-                Self::CertificateRequest(CertificateRequest::deser(data)?)
+                Self::CertificateRequest(CertificateRequest::deser(body)?)
             }
             handshake_types::CERTIFICATE_VERIFY => {
-                Self::CertificateVerify(CertificateVerify::deser(data)?)
+                Self::CertificateVerify(CertificateVerify::deser(body)?)
             }
-            handshake_types::FINISHED => Self::Finished(Finished::deser(data)?),
+            handshake_types::FINISHED => Self::Finished(Finished::deser(body)?),
             handshake_types::KEY_UPDATE => Self::KeyUpdate,
             handshake_types::MESSAGE_HASH => Self::MessageHash,
 
-            _ => todo!("{msg_type}"),
+            _ => bail!("Unknown handshake message type: {msg_type}"),
         })
     }
 }
@@ -82,6 +84,22 @@ impl RawDeser for Handshake {
 impl RawSer for Handshake {
     fn ser(&self) -> Box<[u8]> {
         match self {
+            Self::ClientHello(c_h) => {
+                let mut res = Vec::new();
+
+                let raw = c_h.ser();
+                let length = raw.len();
+                let length_bytes = TryInto::<u32>::try_into(length)
+                    .expect("ClientHello size exceeds maximum u32 value")
+                    .to_be_bytes();
+
+                res.push(handshake_types::CLIENT_HELLO);
+                res.extend(&length_bytes[1..=3]);
+                res.extend(raw);
+
+                res.into_boxed_slice()
+            }
+
             Self::ServerHello(s_h) => {
                 let mut res = Vec::new();
 
@@ -181,3 +199,111 @@ impl RawSer for Handshake {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::{
+        cipher_suite::TLS_AES_256_GCM_SHA384,
+        record::handshake::{
+            client_hello::{ClientHelloExtension, ClientHelloExtensionContent},
+            extension::{KeyShareEntry, NamedGroup},
+            server_hello::{ServerHelloExtension, ServerHelloExtensionContent},
+        },
+    };
+
+    #[test]
+    fn test_hello_roundtrip() -> Result<()> {
+        let share = KeyShareEntry::new(NamedGroup::x25519, &[7; 32]);
+
+        let client_hello = Handshake::ClientHello(ClientHello::new(
+            &[1; 32],
+            &[2; 32],
+            &[TLS_AES_256_GCM_SHA384],
+            &[
+                ClientHelloExtension::new_server_name(b"example.com")?,
+                ClientHelloExtension::new_supported_versions(&[0x0304])?,
+                ClientHelloExtension::new_key_share(std::slice::from_ref(&share))?,
+                ClientHelloExtension::new_alpn(&[b"h2", b"http/1.1"])?,
+            ],
+        )?);
+        let raw = client_hello.ser();
+        let Handshake::ClientHello(parsed) = Handshake::deser(&raw)? else {
+            panic!("Not client hello");
+        };
+        assert_eq!(*parsed.random, [1; 32]);
+        assert_eq!(parsed.extensions.len(), 4);
+        assert!(matches!(
+            &parsed.extensions[0].content,
+            ClientHelloExtensionContent::ServerName(list) if list.server_name_list.len() == 1
+        ));
+        assert_eq!(Handshake::ClientHello(parsed).ser(), raw);
+
+        let server_hello = Handshake::ServerHello(ServerHello::new(
+            &[3; 32],
+            &[2; 32],
+            TLS_AES_256_GCM_SHA384,
+            &[
+                ServerHelloExtension::new_supported_versions(0x0304),
+                ServerHelloExtension::new_key_share(share)?,
+            ],
+        ));
+        let raw = server_hello.ser();
+        let Handshake::ServerHello(parsed) = Handshake::deser(&raw)? else {
+            panic!("Not server hello");
+        };
+        assert!(matches!(
+            &parsed.extensions[1].content,
+            ServerHelloExtensionContent::KeyShare(e) if *e.server_share.key_exchange == [7; 32]
+        ));
+        assert_eq!(Handshake::ServerHello(parsed).ser(), raw);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_hello_extensions() -> Result<()> {
+        let extensions: [&[u8]; 12] = [
+            // status_request
+            &hex!("0005 0005 01 0000 0000"),
+            // ec_point_formats
+            &hex!("000b 0004 03 000102"),
+            // signed_certificate_timestamp
+            &hex!("0012 0000"),
+            // extended_main_secret
+            &hex!("0017 0000"),
+            // compress_certificate
+            &hex!("001b 0003 02 0002"),
+            // record_size_limit
+            &hex!("001c 0002 4001"),
+            // session_ticket
+            &hex!("0023 0003 010203"),
+            // pre_shared_key
+            &hex!("0029 0016 0009 0003 616263 00000001 0009 08 0101010101010101"),
+            // psk_key_exchange_modes
+            &hex!("002d 0002 01 01"),
+            // post_handshake_auth
+            &hex!("0031 0000"),
+            // renegotiation_info
+            &hex!("ff01 0001 00"),
+            // GREASE
+            &hex!("0a0a 0001 00"),
+        ];
+
+        for raw in extensions {
+            let extension = ClientHelloExtension::deser(raw)?;
+            assert_eq!(
+                matches!(
+                    extension.content,
+                    ClientHelloExtensionContent::Unknown { .. }
+                ),
+                raw[0] == 0x0a
+            );
+            assert_eq!(*extension.ser(), *raw);
+        }
+
+        Ok(())
+    }
+}
@@ -1,14 +1,32 @@
 use anyhow::Result;
 use utils::concat_dyn;
 
-use crate::parse::{DataVec8, DataVec16, DataVec24, RawDeser, RawSer, RawSize};
+use crate::parse::{DataVec8, DataVec16, DataVec24, RawDeser, RawSer, RawSize, slice, take};
 
 #[derive(Clone, Debug)]
-pub struct CertificateExtension {}
+pub struct CertificateExtension {
+    pub extension_type: u16,
+    pub data: DataVec16<u8>,
+}
+
+impl RawSize for CertificateExtension {
+    fn size(&self) -> usize {
+        self.data.size() + 2
+    }
+}
 
 impl RawSer for CertificateExtension {
     fn ser(&self) -> Box<[u8]> {
-        Box::new([])
+        concat_dyn![self.extension_type.to_be_bytes(), self.data.ser()]
+    }
+}
+
+impl RawDeser for CertificateExtension {
+    fn deser(raw: &[u8]) -> Result<Self> {
+        Ok(Self {
+            extension_type: u16::from_be_bytes(take(raw)?),
+            data: DataVec16::deser(slice(raw, 2..)?)?,
+        })
     }
 }
 
@@ -70,7 +88,14 @@ impl RawSize for CertificateEntry {
 
 impl RawDeser for CertificateEntry {
     fn deser(raw: &[u8]) -> Result<Self> {
-        todo!()
+        // Raw public keys are never negotiated
+        let cert_data = DataVec24::deser(raw)?;
+        let extensions = DataVec16::deser(slice(raw, cert_data.size()..)?)?;
+
+        Ok(Self {
+            content: CertificateEntryContent::X509 { cert_data },
+            extensions,
+        })
     }
 }
 
@@ -98,7 +123,7 @@ impl Certificate {
 impl RawDeser for Certificate {
     fn deser(raw: &[u8]) -> Result<Self> {
         let context = DataVec8::deser(raw)?;
-        let list = DataVec24::<CertificateEntry>::deser(&raw[context.size()..])?;
+        let list = DataVec24::<CertificateEntry>::deser(slice(raw, context.size()..)?)?;
 
         Ok(Self {
             certificate_request_context: context,
@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 
 use super::extension::{SignatureAlgorithms, SignatureScheme, extension_types};
 use crate::parse::{DataVec8, DataVec16, RawDeser, RawSer, RawSize};
@@ -62,8 +62,8 @@ impl CertificateRequest {
 }
 
 impl RawDeser for CertificateRequest {
-    fn deser(raw: &[u8]) -> Result<Self> {
-        todo!()
+    fn deser(_raw: &[u8]) -> Result<Self> {
+        bail!("Client authentication is not supported")
     }
 }
 
@@ -2,7 +2,7 @@ use anyhow::Result;
 use utils::concat_dyn;
 
 use crate::{
-    parse::{DataVec16, RawDeser, RawSer},
+    parse::{DataVec16, RawDeser, RawSer, RawSize, slice},
     record::handshake::extension::SignatureScheme,
 };
 
@@ -29,6 +29,12 @@ impl RawSer for CertificateVerify {
 
 impl RawDeser for CertificateVerify {
     fn deser(raw: &[u8]) -> anyhow::Result<Self> {
-        todo!()
+        let algorithm = SignatureScheme::deser(raw)?;
+        let signature = DataVec16::deser(slice(raw, algorithm.size()..)?)?;
+
+        Ok(Self {
+            algorithm,
+            signature,
+        })
     }
 }
@@ -1,14 +1,15 @@
 use anyhow::{Context, Result, bail};
+use utils::concat_dyn;
 
 use super::extension::{
-    CertificateCompressionAlgorithms, EcPointFormats, KeyShareClientHello,
-    PreSharedKeyExtensionClientHello, ProtocolNameList, PskKeyExchangeModes, RenegotiationInfo,
-    ServerNameList, SignatureAlgorithms, StatusRequest, SupportedGroups,
-    SupportedVersionsClientHello, extension_types,
+    CertificateCompressionAlgorithms, EcPointFormats, KeyShareClientHello, KeyShareEntry,
+    NamedGroup, PreSharedKeyExtensionClientHello, ProtocolName, ProtocolNameList,
+    PskKeyExchangeModes, RenegotiationInfo, ServerName, ServerNameList, SignatureAlgorithms,
+    SignatureScheme, StatusRequest, SupportedGroups, SupportedVersionsClientHello, extension_types,
 };
 use crate::{
     cipher_suite::CipherSuite,
-    parse::{RawDeser, RawSize},
+    parse::{DataVec16, RawDeser, RawSer, RawSize, slice, take},
     util::{opaque_vec_8, opaque_vec_16},
 };
 
@@ -34,9 +35,9 @@ pub enum ClientHelloExtensionContent {
     /// ID: 27
     CertificateCompressionAlgorithms(CertificateCompressionAlgorithms),
     /// ID: 28
-    RecordSizeLimit(/* */),
+    RecordSizeLimit(u16),
     /// ID: 35
-    SessionTicket(/* TODO */),
+    SessionTicket(Box<[u8]>),
     /// ID: 41
     PreSharedKey(PreSharedKeyExtensionClientHello),
     /// ID: 43
@@ -50,12 +51,17 @@ pub enum ClientHelloExtensionContent {
     // 65037
     /// ID: 65281
     RenegotiationInfo(RenegotiationInfo),
+
+    Unknown {
+        extension_type: u16,
+        data: Box<[u8]>,
+    },
 }
 
 impl RawDeser for ClientHelloExtensionContent {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let extension_type = u16::from_be_bytes([raw[0], raw[1]]);
-        let data = &raw[4..];
+        let extension_type = u16::from_be_bytes(take(raw)?);
+        let data = slice(raw, 4..)?;
 
         Ok(match extension_type {
             extension_types::SERVER_NAME => {
@@ -78,10 +84,11 @@ impl RawDeser for ClientHelloExtensionContent {
                     ProtocolNameList::deser(data).context("ALPNegotiation")?,
                 )
             }
-            18 => Self::SignedCertificateTimestamp,
+            extension_types::SIGNED_CERTIFICATE_TIMESTAMP => Self::SignedCertificateTimestamp,
             extension_types::EXTENDED_MAIN_SECRET => Self::ExtendedMainSecret,
             extension_types::COMPRESS_CERTIFICATE => Self::CertificateCompressionAlgorithms(CertificateCompressionAlgorithms::deser(data)?),
-            extension_types::SESSION_TICKET => Self::SessionTicket(),
+            extension_types::RECORD_SIZE_LIMIT => Self::RecordSizeLimit(u16::deser(data)?),
+            extension_types::SESSION_TICKET => Self::SessionTicket(Box::from(data)),
             extension_types::PRE_SHARED_KEY => {
                 Self::PreSharedKey(PreSharedKeyExtensionClientHello::deser(data)?)
             }
@@ -97,7 +104,10 @@ impl RawDeser for ClientHelloExtensionContent {
                 Self::RenegotiationInfo(RenegotiationInfo::deser(data)?)
             }
 
-            _ => bail!("Unknown extension type: {extension_type}"),
+            _ => Self::Unknown {
+                extension_type,
+                data: Box::from(data),
+            },
         })
     }
 }
@@ -110,8 +120,135 @@ pub struct ClientHelloExtension {
 }
 
 impl ClientHelloExtension {
-    pub fn size_raw(raw: &[u8]) -> usize {
-        u16::from_be_bytes([raw[2], raw[3]]) as usize + 4
+    pub fn size_raw(raw: &[u8]) -> Result<usize> {
+        Ok(u16::from_be_bytes(take(slice(raw, 2..)?)?) as usize + 4)
+    }
+
+    fn new(content: ClientHelloExtensionContent) -> Result<Self> {
+        let mut res = Self { length: 0, content };
+        res.length = res.body().len().try_into()?;
+        Ok(res)
+    }
+
+    pub fn new_server_name(host_name: &[u8]) -> Result<Self> {
+        Self::new(ClientHelloExtensionContent::ServerName(ServerNameList {
+            server_name_list: Box::new([ServerName::HostName(Box::from(host_name))]),
+        }))
+    }
+
+    pub fn new_supported_groups(groups: &[NamedGroup]) -> Result<Self> {
+        Self::new(ClientHelloExtensionContent::SupportedGroups(
+            SupportedGroups {
+                named_group_list: Box::from(groups),
+            },
+        ))
+    }
+
+    pub fn new_signature_algorithms(algorithms: &[SignatureScheme]) -> Result<Self> {
+        Self::new(ClientHelloExtensionContent::SignatureAlgorithms(
+            SignatureAlgorithms {
+                supported_signature_algorithms: DataVec16::try_from(algorithms)?,
+            },
+        ))
+    }
+
+    pub fn new_alpn(protocols: &[&[u8]]) -> Result<Self> {
+        let protocol_name_list = protocols
+            .iter()
+            .map(|x| ProtocolName::new(x))
+            .collect::<Result<_>>()?;
+
+        Self::new(
+            ClientHelloExtensionContent::ApplicationLayerProtocolNegotiation(ProtocolNameList {
+                protocol_name_list,
+            }),
+        )
+    }
+
+    pub fn new_supported_versions(versions: &[u16]) -> Result<Self> {
+        Self::new(ClientHelloExtensionContent::SupportedVersions(
+            SupportedVersionsClientHello {
+                versions: Box::from(versions),
+            },
+        ))
+    }
+
+    pub fn new_key_share(shares: &[KeyShareEntry]) -> Result<Self> {
+        Self::new(ClientHelloExtensionContent::KeyShare(KeyShareClientHello {
+            client_shares: Box::from(shares),
+        }))
+    }
+
+    fn body(&self) -> Box<[u8]> {
+        match &self.content {
+            ClientHelloExtensionContent::ServerName(e) => e.ser(),
+            ClientHelloExtensionContent::StatusRequest(e) => e.ser(),
+            ClientHelloExtensionContent::SupportedGroups(e) => e.ser(),
+            ClientHelloExtensionContent::EcPointFormats(e) => e.ser(),
+            ClientHelloExtensionContent::SignatureAlgorithms(e) => e.ser(),
+            ClientHelloExtensionContent::ApplicationLayerProtocolNegotiation(e) => e.ser(),
+            ClientHelloExtensionContent::SignedCertificateTimestamp
+            | ClientHelloExtensionContent::ExtendedMainSecret
+            | ClientHelloExtensionContent::PostHandshakeAuth => Box::new([]),
+            ClientHelloExtensionContent::CertificateCompressionAlgorithms(e) => e.ser(),
+            ClientHelloExtensionContent::RecordSizeLimit(limit) => limit.ser(),
+            ClientHelloExtensionContent::SessionTicket(ticket) => ticket.clone(),
+            ClientHelloExtensionContent::PreSharedKey(e) => e.ser(),
+            ClientHelloExtensionContent::SupportedVersions(e) => e.ser(),
+            ClientHelloExtensionContent::PskKeyExchangeModes(e) => e.ser(),
+            ClientHelloExtensionContent::KeyShare(e) => e.ser(),
+            ClientHelloExtensionContent::RenegotiationInfo(e) => e.ser(),
+            ClientHelloExtensionContent::Unknown { data, .. } => data.clone(),
+        }
+    }
+
+    fn extension_type(&self) -> u16 {
+        match &self.content {
+            ClientHelloExtensionContent::ServerName(_) => extension_types::SERVER_NAME,
+            ClientHelloExtensionContent::SupportedGroups(_) => extension_types::SUPPORTED_GROUPS,
+            ClientHelloExtensionContent::SignatureAlgorithms(_) => {
+                extension_types::SIGNATURE_ALGORITHMS
+            }
+            ClientHelloExtensionContent::ApplicationLayerProtocolNegotiation(_) => {
+                extension_types::APPLICATION_LAYER_PROTOCOL_NEGOTIATION
+            }
+            ClientHelloExtensionContent::SupportedVersions(_) => {
+                extension_types::SUPPORTED_VERSIONS
+            }
+            ClientHelloExtensionContent::KeyShare(_) => extension_types::KEY_SHARE,
+            ClientHelloExtensionContent::StatusRequest(_) => extension_types::STATUS_REQUEST,
+            ClientHelloExtensionContent::EcPointFormats(_) => extension_types::EC_POINT_FORMATS,
+            ClientHelloExtensionContent::SignedCertificateTimestamp => {
+                extension_types::SIGNED_CERTIFICATE_TIMESTAMP
+            }
+            ClientHelloExtensionContent::ExtendedMainSecret => {
+                extension_types::EXTENDED_MAIN_SECRET
+            }
+            ClientHelloExtensionContent::CertificateCompressionAlgorithms(_) => {
+                extension_types::COMPRESS_CERTIFICATE
+            }
+            ClientHelloExtensionContent::RecordSizeLimit(_) => extension_types::RECORD_SIZE_LIMIT,
+            ClientHelloExtensionContent::SessionTicket(_) => extension_types::SESSION_TICKET,
+            ClientHelloExtensionContent::PreSharedKey(_) => extension_types::PRE_SHARED_KEY,
+            ClientHelloExtensionContent::PskKeyExchangeModes(_) => {
+                extension_types::PSK_KEY_EXCHANGE_MODES
+            }
+            ClientHelloExtensionContent::PostHandshakeAuth => extension_types::POST_HANDSHAKE_AUTH,
+            ClientHelloExtensionContent::RenegotiationInfo(_) => {
+                extension_types::RENEGOTIATION_INFO
+            }
+            ClientHelloExtensionContent::Unknown { extension_type, .. } => *extension_type,
+        }
+    }
+}
+
+impl RawSer for ClientHelloExtension {
+    fn ser(&self) -> Box<[u8]> {
+        concat_dyn!(
+            self.extension_type().to_be_bytes(),
+            self.length.to_be_bytes(),
+            self.body()
+        )
     }
 }
 
@@ -123,8 +260,8 @@ impl RawSize for ClientHelloExtension {
 
 impl RawDeser for ClientHelloExtension {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let length = u16::from_be_bytes([raw[2], raw[3]]);
-        let content = ClientHelloExtensionContent::deser(raw)?;
+        let length = u16::from_be_bytes(take(slice(raw, 2..)?)?);
+        let content = ClientHelloExtensionContent::deser(slice(raw, ..(4 + length as usize))?)?;
 
         Ok(Self { length, content })
     }
@@ -141,6 +278,26 @@ pub struct ClientHello {
     pub extensions: Box<[ClientHelloExtension]>,
 }
 
+impl ClientHello {
+    pub fn new(
+        random: &[u8; 32],
+        legacy_session_id: &[u8],
+        cipher_suites: &[CipherSuite],
+        extensions: &[ClientHelloExtension],
+    ) -> Result<Self> {
+        let mut res = Self {
+            length: 0,
+            random: Box::new(*random),
+            legacy_session_id: Box::from(legacy_session_id),
+            cipher_suites: Box::from(cipher_suites),
+            legacy_compression_methods: Box::new([0]),
+            extensions: Box::from(extensions),
+        };
+        res.length = res.ser().len().try_into()?;
+        Ok(res)
+    }
+}
+
 impl RawSize for ClientHello {
     fn size(&self) -> usize {
         self.length as usize + 3
@@ -149,31 +306,31 @@ impl RawSize for ClientHello {
 
 impl RawDeser for ClientHello {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let length = u32::from_be_bytes([0, raw[0], raw[1], raw[2]]);
+        let length = raw.len().try_into()?;
 
-        let legacy_version = u16::from_be_bytes([raw[3], raw[4]]);
+        let legacy_version = u16::from_be_bytes(take(raw)?);
         if legacy_version != 0x0303 {
             bail!("Invalid legacy version: {legacy_version} (should be equal 0x0303)");
         }
 
-        let random = Box::new(raw[5..(5 + 32)].try_into()?);
+        let random = Box::new(take(slice(raw, 2..)?)?);
 
-        let mut offset: usize = 5 + 32;
+        let mut offset: usize = 2 + 32;
 
-        let (size, legacy_session_id) = opaque_vec_8(&raw[offset..]);
+        let (size, legacy_session_id) = opaque_vec_8(slice(raw, offset..)?)?;
         offset += size;
 
-        let (size, cipher_suites_raw) = opaque_vec_16(&raw[offset..]);
+        let (size, cipher_suites_raw) = opaque_vec_16(slice(raw, offset..)?)?;
         offset += size;
         let cipher_suites = cipher_suites_raw
-            .chunks(2)
+            .chunks_exact(2)
             .map(|x| CipherSuite(u16::from_be_bytes([x[0], x[1]])))
             .collect();
 
-        let (size, legacy_compression_methods) = opaque_vec_8(&raw[offset..]);
+        let (size, legacy_compression_methods) = opaque_vec_8(slice(raw, offset..)?)?;
         offset += size;
 
-        let (_, extensions_raw) = opaque_vec_16(&raw[offset..]);
+        let (_, extensions_raw) = opaque_vec_16(slice(raw, offset..)?)?;
 
         // Parse extensions
         let total_length = extensions_raw.len();
@@ -193,7 +350,7 @@ impl RawDeser for ClientHello {
                 Err(err) => {
                     tracing::warn!("Failed to parse extension: {err:?}");
                     parsed_length +=
-                        ClientHelloExtension::size_raw(&extensions_raw[parsed_length..]);
+                        ClientHelloExtension::size_raw(&extensions_raw[parsed_length..])?;
                 }
             }
         }
@@ -212,3 +369,28 @@ impl RawDeser for ClientHello {
         })
     }
 }
+
+impl RawSer for ClientHello {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+
+        res.extend(crate::LEGACY_VERSION_BYTES);
+        res.extend(self.random.as_ref());
+
+        res.push(self.legacy_session_id.len() as u8);
+        res.extend(self.legacy_session_id.as_ref());
+
+        res.extend((self.cipher_suites.len() as u16 * 2).to_be_bytes());
+        res.extend(self.cipher_suites.iter().flat_map(|x| x.0.to_be_bytes()));
+
+        res.push(self.legacy_compression_methods.len() as u8);
+        res.extend(self.legacy_compression_methods.as_ref());
+
+        let extensions_length = self.extensions.iter().fold(0, |acc, e| acc + e.size()) as u16;
+        res.extend(extensions_length.to_be_bytes());
+        res.extend(self.extensions.iter().flat_map(ClientHelloExtension::ser));
+
+        res.into_boxed_slice()
+    }
+}
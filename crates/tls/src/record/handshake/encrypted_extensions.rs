@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use crate::{
-    parse::{DataVec16, RawDeser, RawSer, RawSize},
+    parse::{DataVec16, RawDeser, RawSer},
     record::handshake::server_hello::ServerHelloExtension,
 };
 
@@ -28,7 +28,7 @@ use crate::{
 
 #[derive(Clone, Debug)]
 pub struct EncryptedExtensions {
-    extensions: DataVec16<ServerHelloExtension>,
+    pub extensions: DataVec16<ServerHelloExtension>,
 }
 
 impl EncryptedExtensions {
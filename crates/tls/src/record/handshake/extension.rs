@@ -27,7 +27,7 @@ pub use ec_point_formats::EcPointFormats;
 pub use key_share::{KeyShareClientHello, KeyShareEntry, KeyShareServerHello};
 pub use named_group::NamedGroup;
 pub use pre_shared_key::{PreSharedKeyExtensionClientHello, PreSharedKeyExtensionServerHello};
-pub use protocol_name_list::ProtocolNameList;
+pub use protocol_name_list::{ProtocolName, ProtocolNameList};
 pub use psk_key_exchange_modes::PskKeyExchangeModes;
 pub use renegotiation_info::RenegotiationInfo;
 pub use server_name::{ServerName, ServerNameList};
@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use crate::{
     macros::auto_try_from,
-    parse::{DataVec8, RawDeser, RawSize},
+    parse::{DataVec8, RawDeser, RawSer, RawSize},
 };
 
 auto_try_from! {
@@ -22,6 +22,12 @@ impl RawDeser for CertificateCompressionAlgorithm {
     }
 }
 
+impl RawSer for CertificateCompressionAlgorithm {
+    fn ser(&self) -> Box<[u8]> {
+        (*self as u16).ser()
+    }
+}
+
 impl RawSize for CertificateCompressionAlgorithm {
     fn size(&self) -> usize {
         2
@@ -40,3 +46,9 @@ impl RawDeser for CertificateCompressionAlgorithms {
         })
     }
 }
+
+impl RawSer for CertificateCompressionAlgorithms {
+    fn ser(&self) -> Box<[u8]> {
+        self.algorithms.ser()
+    }
+}
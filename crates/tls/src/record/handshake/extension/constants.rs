@@ -12,6 +12,7 @@ pub mod extension_types {
     pub const HEARTBEAT: u16 = 15;
     pub const APPLICATION_LAYER_PROTOCOL_NEGOTIATION: u16 = 16;
     pub const STATUS_REQUEST_V2: u16 = 17;
+    pub const SIGNED_CERTIFICATE_TIMESTAMP: u16 = 18;
     pub const CLIENT_CERTIFICATE_TYPE: u16 = 19;
     pub const SERVER_CERTIFICATE_TYPE: u16 = 20;
     pub const PADDING: u16 = 21;
@@ -2,7 +2,7 @@ use anyhow::{Ok, Result};
 
 use crate::{
     macros::auto_try_from,
-    parse::{DataVec8, RawDeser, RawSize},
+    parse::{DataVec8, RawDeser, RawSer, RawSize, take},
 };
 
 auto_try_from! {
@@ -15,6 +15,12 @@ auto_try_from! {
     }
 }
 
+impl RawSer for EcPointFormat {
+    fn ser(&self) -> Box<[u8]> {
+        Box::new([*self as u8])
+    }
+}
+
 impl RawSize for EcPointFormat {
     fn size(&self) -> usize {
         1
@@ -23,7 +29,8 @@ impl RawSize for EcPointFormat {
 
 impl RawDeser for EcPointFormat {
     fn deser(raw: &[u8]) -> Result<Self> {
-        Self::try_from(raw[0])
+        let [format] = take(raw)?;
+        Self::try_from(format)
     }
 }
 
@@ -41,3 +48,13 @@ impl RawDeser for EcPointFormats {
         })
     }
 }
+
+impl RawSer for EcPointFormats {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.push(self.ec_point_format_list.len() as u8);
+        res.extend(self.ec_point_format_list.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
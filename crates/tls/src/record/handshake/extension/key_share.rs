@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use anyhow::Result;
 
 use super::named_group::NamedGroup;
-use crate::parse::{DataVec16, RawDeser, RawSer, RawSize};
+use crate::parse::{DataVec16, RawDeser, RawSer, RawSize, slice};
 
 #[derive(Clone, Debug)]
 pub struct KeyShareEntry {
@@ -47,8 +47,8 @@ impl RawSer for KeyShareEntry {
 
 impl RawDeser for KeyShareEntry {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let group = NamedGroup::deser(&raw[0..2])?;
-        let key_exchange = DataVec16::<u8>::deser(&raw[2..])?;
+        let group = NamedGroup::deser(raw)?;
+        let key_exchange = DataVec16::<u8>::deser(slice(raw, 2..)?)?;
 
         Ok(Self {
             group,
@@ -81,7 +81,27 @@ impl RawDeser for KeyShareClientHello {
     }
 }
 
+impl RawSer for KeyShareClientHello {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let length: usize = self.client_shares.iter().map(RawSize::size).sum();
+
+        let mut res = Vec::new();
+        res.extend((length as u16).to_be_bytes());
+        res.extend(self.client_shares.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct KeyShareServerHello {
     pub server_share: KeyShareEntry,
 }
+
+impl RawDeser for KeyShareServerHello {
+    fn deser(raw: &[u8]) -> Result<Self> {
+        Ok(Self {
+            server_share: KeyShareEntry::deser(raw)?,
+        })
+    }
+}
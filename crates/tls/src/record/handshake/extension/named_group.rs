@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use crate::{
     macros::auto_from,
-    parse::{RawDeser, RawSize},
+    parse::{RawDeser, RawSer, RawSize, take},
 };
 
 auto_from! {
@@ -34,6 +34,12 @@ impl RawSize for NamedGroup {
 
 impl RawDeser for NamedGroup {
     fn deser(raw: &[u8]) -> Result<Self> {
-        Ok(Self::from(u16::from_be_bytes([raw[0], raw[1]])))
+        Ok(Self::from(u16::from_be_bytes(take(raw)?)))
+    }
+}
+
+impl RawSer for NamedGroup {
+    fn ser(&self) -> Box<[u8]> {
+        u16::from(self).ser()
     }
 }
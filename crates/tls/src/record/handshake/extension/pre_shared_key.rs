@@ -1,4 +1,4 @@
-use crate::parse::{DataVec8, DataVec16, RawDeser, RawSize};
+use crate::parse::{DataVec8, DataVec16, RawDeser, RawSer, RawSize, slice, take};
 
 #[derive(Clone, Debug)]
 pub struct PskIdentity {
@@ -19,7 +19,7 @@ impl RawDeser for PskIdentity {
     fn deser(raw: &[u8]) -> anyhow::Result<Self> {
         let identity = DataVec16::<u8>::deser(raw)?;
         let size = identity.size();
-        let obfuscated_ticket_age = u32::from_be_bytes(raw[size..(size + 4)].try_into()?);
+        let obfuscated_ticket_age = u32::from_be_bytes(take(slice(raw, size..)?)?);
 
         Ok(Self {
             size: size + 4,
@@ -29,6 +29,17 @@ impl RawDeser for PskIdentity {
     }
 }
 
+impl RawSer for PskIdentity {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.extend((self.identity.len() as u16).to_be_bytes());
+        res.extend(self.identity.as_ref());
+        res.extend(self.obfuscated_ticket_age.to_be_bytes());
+        res.into_boxed_slice()
+    }
+}
+
 pub type PskBinderEntry = Box<[u8]>;
 
 #[derive(Clone, Debug)]
@@ -40,7 +51,7 @@ pub struct PreSharedKeyExtensionClientHello {
 impl RawDeser for PreSharedKeyExtensionClientHello {
     fn deser(raw: &[u8]) -> anyhow::Result<Self> {
         let identities = DataVec16::<PskIdentity>::deser(raw)?;
-        let binders = DataVec16::<DataVec8<u8>>::deser(&raw[identities.size()..])?;
+        let binders = DataVec16::<DataVec8<u8>>::deser(slice(raw, identities.size()..)?)?;
 
         Ok(Self {
             identities: identities.into_inner(),
@@ -53,6 +64,24 @@ impl RawDeser for PreSharedKeyExtensionClientHello {
     }
 }
 
+impl RawSer for PreSharedKeyExtensionClientHello {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let identities_length: usize = self.identities.iter().map(RawSize::size).sum();
+        let binders_length: usize = self.binders.iter().map(|x| x.len() + 1).sum();
+
+        let mut res = Vec::new();
+        res.extend((identities_length as u16).to_be_bytes());
+        res.extend(self.identities.iter().flat_map(RawSer::ser));
+        res.extend((binders_length as u16).to_be_bytes());
+        for binder in &self.binders {
+            res.push(binder.len() as u8);
+            res.extend(binder.as_ref());
+        }
+        res.into_boxed_slice()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PreSharedKeyExtensionServerHello {
     pub selected_identity: u16,
@@ -60,7 +89,7 @@ pub struct PreSharedKeyExtensionServerHello {
 
 impl RawDeser for PreSharedKeyExtensionServerHello {
     fn deser(raw: &[u8]) -> anyhow::Result<Self> {
-        let selected_identity = u16::from_be_bytes([raw[0], raw[1]]);
+        let selected_identity = u16::from_be_bytes(take(raw)?);
 
         Ok(Self { selected_identity })
     }
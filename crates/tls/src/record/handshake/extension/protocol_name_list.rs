@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use crate::{
-    parse::{DataVec16, RawDeser, RawSize},
+    parse::{DataVec16, RawDeser, RawSer, RawSize},
     util::opaque_vec_8,
 };
 
@@ -12,6 +12,17 @@ pub struct ProtocolName {
     pub data: Box<[u8]>,
 }
 
+impl ProtocolName {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        u8::try_from(data.len())?;
+
+        Ok(Self {
+            size: data.len() + 1,
+            data: Box::from(data),
+        })
+    }
+}
+
 impl RawSize for ProtocolName {
     fn size(&self) -> usize {
         self.size
@@ -20,11 +31,20 @@ impl RawSize for ProtocolName {
 
 impl RawDeser for ProtocolName {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let (size, data) = opaque_vec_8(raw);
+        let (size, data) = opaque_vec_8(raw)?;
         Ok(Self { size, data })
     }
 }
 
+impl RawSer for ProtocolName {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        [&[self.data.len() as u8], self.data.as_ref()]
+            .concat()
+            .into()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ProtocolNameList {
     pub protocol_name_list: Box<[ProtocolName]>,
@@ -37,3 +57,15 @@ impl RawDeser for ProtocolNameList {
         Ok(Self { protocol_name_list })
     }
 }
+
+impl RawSer for ProtocolNameList {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let length: usize = self.protocol_name_list.iter().map(RawSize::size).sum();
+
+        let mut res = Vec::new();
+        res.extend((length as u16).to_be_bytes());
+        res.extend(self.protocol_name_list.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::Result;
 
 use crate::{
     macros::auto_try_from,
-    parse::{DataVec8, RawDeser, RawSize},
+    parse::{DataVec8, RawDeser, RawSer, RawSize, take},
 };
 
 auto_try_from! {
@@ -15,6 +15,12 @@ auto_try_from! {
     }
 }
 
+impl RawSer for PskKeyExchangeMode {
+    fn ser(&self) -> Box<[u8]> {
+        Box::new([*self as u8])
+    }
+}
+
 impl RawSize for PskKeyExchangeMode {
     fn size(&self) -> usize {
         1
@@ -23,7 +29,8 @@ impl RawSize for PskKeyExchangeMode {
 
 impl RawDeser for PskKeyExchangeMode {
     fn deser(raw: &[u8]) -> Result<Self> {
-        Self::try_from(raw[0])
+        let [mode] = take(raw)?;
+        Self::try_from(mode)
     }
 }
 
@@ -39,3 +46,13 @@ impl RawDeser for PskKeyExchangeModes {
         Ok(Self { ke_modes })
     }
 }
+
+impl RawSer for PskKeyExchangeModes {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.push(self.ke_modes.len() as u8);
+        res.extend(self.ke_modes.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::Ok;
 
-use crate::{parse::RawDeser, util::opaque_vec_8};
+use crate::{
+    parse::{RawDeser, RawSer},
+    util::opaque_vec_8,
+};
 
 #[derive(Clone, Debug)]
 pub struct RenegotiationInfo {
@@ -9,10 +12,20 @@ pub struct RenegotiationInfo {
 
 impl RawDeser for RenegotiationInfo {
     fn deser(raw: &[u8]) -> anyhow::Result<Self> {
-        let (_, renegotiated_connection) = opaque_vec_8(raw);
+        let (_, renegotiated_connection) = opaque_vec_8(raw)?;
 
         Ok(Self {
             renegotiated_connection,
         })
     }
 }
+
+impl RawSer for RenegotiationInfo {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.push(self.renegotiated_connection.len() as u8);
+        res.extend(self.renegotiated_connection.as_ref());
+        res.into_boxed_slice()
+    }
+}
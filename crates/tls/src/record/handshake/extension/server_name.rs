@@ -1,6 +1,6 @@
 use anyhow::{Result, bail};
 
-use crate::parse::{DataVec16, RawDeser, RawSize};
+use crate::parse::{DataVec16, RawDeser, RawSer, RawSize, slice, take};
 
 #[derive(Clone, Debug)]
 pub enum ServerName {
@@ -10,18 +10,18 @@ pub enum ServerName {
 impl RawSize for ServerName {
     fn size(&self) -> usize {
         match self {
-            ServerName::HostName(n) => n.len() + 2,
+            ServerName::HostName(n) => n.len() + 3,
         }
     }
 }
 
 impl RawDeser for ServerName {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let name_type = raw[0];
+        let [name_type] = take(raw)?;
 
         Ok(match name_type {
             0 => {
-                let data = DataVec16::<u8>::deser(&raw[1..])?.into_inner();
+                let data = DataVec16::<u8>::deser(slice(raw, 1..)?)?.into_inner();
                 Self::HostName(data)
             }
             _ => bail!("Unknown ServerName type: {name_type}"),
@@ -29,6 +29,17 @@ impl RawDeser for ServerName {
     }
 }
 
+impl RawSer for ServerName {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        match self {
+            ServerName::HostName(n) => [&[0], (n.len() as u16).to_be_bytes().as_slice(), n]
+                .concat()
+                .into(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ServerNameList {
     pub server_name_list: Box<[ServerName]>,
@@ -37,8 +48,8 @@ pub struct ServerNameList {
 impl RawDeser for ServerNameList {
     fn deser(raw: &[u8]) -> Result<Self> {
         // TODO: Fix implementation
-        let length = u16::from_be_bytes([raw[0], raw[1]]);
-        let payload = &raw[2..];
+        let length = u16::from_be_bytes(take(raw)?);
+        let payload = slice(raw, 2..(2 + length as usize))?;
 
         let mut server_name_list = Vec::new();
         let mut offset: usize = 0;
@@ -55,3 +66,15 @@ impl RawDeser for ServerNameList {
         })
     }
 }
+
+impl RawSer for ServerNameList {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let length: usize = self.server_name_list.iter().map(RawSize::size).sum();
+
+        let mut res = Vec::new();
+        res.extend((length as u16).to_be_bytes());
+        res.extend(self.server_name_list.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
@@ -2,13 +2,13 @@ use anyhow::Result;
 
 use crate::{
     macros::auto_from,
-    parse::{RawDeser, RawSer, RawSize},
+    parse::{RawDeser, RawSer, RawSize, take},
 };
 
 auto_from! {
     #[repr(u16)]
     #[allow(non_camel_case_types)]
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub enum SignatureScheme {
         /* RSASSA-PKCS1-v1_5 algorithms */
         rsa_pkcs1_sha256 = 0x0401,
@@ -49,7 +49,7 @@ impl RawSize for SignatureScheme {
 
 impl RawDeser for SignatureScheme {
     fn deser(raw: &[u8]) -> Result<Self> {
-        Ok(Self::from(u16::from_be_bytes([raw[0], raw[1]])))
+        Ok(Self::from(u16::from_be_bytes(take(raw)?)))
     }
 }
 
@@ -1,6 +1,9 @@
 use anyhow::{Result, bail};
 
-use crate::{parse::RawDeser, util::opaque_vec_16};
+use crate::{
+    parse::{RawDeser, RawSer, slice, take},
+    util::opaque_vec_16,
+};
 
 #[derive(Clone, Debug)]
 pub struct StatusRequest {
@@ -10,17 +13,17 @@ pub struct StatusRequest {
 
 impl RawDeser for StatusRequest {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let status_type = raw[0];
+        let [status_type] = take(raw)?;
         if status_type != 1 {
             bail!("Status type is not 1");
         }
 
         let mut offset = 1;
 
-        let (size, responder_id) = opaque_vec_16(&raw[offset..]);
+        let (size, responder_id) = opaque_vec_16(slice(raw, offset..)?)?;
         offset += size;
 
-        let (_, extensions) = opaque_vec_16(&raw[offset..]);
+        let (_, extensions) = opaque_vec_16(slice(raw, offset..)?)?;
 
         Ok(Self {
             responder_id,
@@ -28,3 +31,16 @@ impl RawDeser for StatusRequest {
         })
     }
 }
+
+impl RawSer for StatusRequest {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.push(1);
+        res.extend((self.responder_id.len() as u16).to_be_bytes());
+        res.extend(self.responder_id.as_ref());
+        res.extend((self.extensions.len() as u16).to_be_bytes());
+        res.extend(self.extensions.as_ref());
+        res.into_boxed_slice()
+    }
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 
 use super::named_group::NamedGroup;
-use crate::parse::{DataVec16, RawDeser};
+use crate::parse::{DataVec16, RawDeser, RawSer};
 
 #[derive(Clone, Debug)]
 pub struct SupportedGroups {
@@ -15,3 +15,13 @@ impl RawDeser for SupportedGroups {
         Ok(Self { named_group_list })
     }
 }
+
+impl RawSer for SupportedGroups {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.extend((self.named_group_list.len() as u16 * 2).to_be_bytes());
+        res.extend(self.named_group_list.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use crate::parse::{DataVec8, RawDeser};
+use crate::parse::{DataVec8, RawDeser, RawSer, take};
 
 #[derive(Clone, Debug)]
 pub struct SupportedVersionsClientHello {
@@ -15,6 +15,16 @@ impl RawDeser for SupportedVersionsClientHello {
     }
 }
 
+impl RawSer for SupportedVersionsClientHello {
+    #[allow(clippy::cast_possible_truncation)]
+    fn ser(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+        res.push(self.versions.len() as u8 * 2);
+        res.extend(self.versions.iter().flat_map(RawSer::ser));
+        res.into_boxed_slice()
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct SupportedVersionsServerHello {
     pub selected_version: u16,
@@ -22,7 +32,7 @@ pub struct SupportedVersionsServerHello {
 
 impl RawDeser for SupportedVersionsServerHello {
     fn deser(raw: &[u8]) -> Result<Self> {
-        let selected_version = u16::from_be_bytes([raw[0], raw[1]]);
+        let selected_version = u16::from_be_bytes(take(raw)?);
 
         Ok(Self { selected_version })
     }
@@ -1,16 +1,21 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 
 use super::extension::{
-    KeyShareEntry, KeyShareServerHello, PreSharedKeyExtensionServerHello,
-    SupportedVersionsServerHello, extension_types,
+    KeyShareEntry, KeyShareServerHello, PreSharedKeyExtensionServerHello, ProtocolName,
+    ProtocolNameList, SupportedVersionsServerHello, extension_types,
 };
 use crate::{
     cipher_suite::CipherSuite,
-    parse::{RawDeser, RawSer, RawSize},
+    parse::{DataVec8, DataVec16, RawDeser, RawSer, RawSize, slice, take},
+    util::opaque_vec_16,
 };
 
 #[derive(Clone, Debug)]
 pub enum ServerHelloExtensionContent {
+    /// ID: 16
+    ///
+    /// Only sent in `EncryptedExtensions`
+    ApplicationLayerProtocolNegotiation(ProtocolNameList),
     /// ID: 23
     ExtendedMainSecret,
     /// ID: 41
@@ -19,6 +24,8 @@ pub enum ServerHelloExtensionContent {
     SupportedVersions(SupportedVersionsServerHello),
     /// ID: 51
     KeyShare(KeyShareServerHello),
+    /// Extension this implementation does not interpret, kept verbatim
+    Unknown(u16, Box<[u8]>),
 }
 
 #[derive(Clone, Debug)]
@@ -63,6 +70,20 @@ impl ServerHelloExtension {
         })
     }
 
+    /// Selected application protocol, for `EncryptedExtensions`.
+    pub fn new_alpn(protocol: &[u8]) -> Result<Self> {
+        let protocol_name = ProtocolName::new(protocol)?;
+
+        Ok(Self {
+            length: (protocol_name.size() + 2).try_into()?,
+            content: ServerHelloExtensionContent::ApplicationLayerProtocolNegotiation(
+                ProtocolNameList {
+                    protocol_name_list: Box::new([protocol_name]),
+                },
+            ),
+        })
+    }
+
     pub fn length(&self) -> u16 {
         self.length
     }
@@ -77,6 +98,15 @@ impl RawSize for ServerHelloExtension {
 impl RawSer for ServerHelloExtension {
     fn ser(&self) -> Box<[u8]> {
         match &self.content {
+            ServerHelloExtensionContent::ApplicationLayerProtocolNegotiation(e) => [
+                extension_types::APPLICATION_LAYER_PROTOCOL_NEGOTIATION
+                    .to_be_bytes()
+                    .as_slice(),
+                &self.length.to_be_bytes(),
+                &e.ser(),
+            ]
+            .concat()
+            .into(),
             ServerHelloExtensionContent::ExtendedMainSecret => {
                 [extension_types::EXTENDED_MAIN_SECRET.to_be_bytes(), [0, 0]]
                     .concat()
@@ -105,13 +135,45 @@ impl RawSer for ServerHelloExtension {
 
                 res.into_boxed_slice()
             }
+            ServerHelloExtensionContent::Unknown(extension_type, data) => [
+                extension_type.to_be_bytes().as_slice(),
+                &self.length.to_be_bytes(),
+                data,
+            ]
+            .concat()
+            .into(),
         }
     }
 }
 
 impl RawDeser for ServerHelloExtension {
     fn deser(raw: &[u8]) -> Result<Self> {
-        todo!()
+        let extension_type = u16::from_be_bytes(take(raw)?);
+        let (size, data) = opaque_vec_16(slice(raw, 2..)?)?;
+        let length = (size - 2).try_into()?;
+
+        let content = match extension_type {
+            extension_types::APPLICATION_LAYER_PROTOCOL_NEGOTIATION => {
+                ServerHelloExtensionContent::ApplicationLayerProtocolNegotiation(
+                    ProtocolNameList::deser(&data)?,
+                )
+            }
+            extension_types::EXTENDED_MAIN_SECRET => {
+                ServerHelloExtensionContent::ExtendedMainSecret
+            }
+            extension_types::PRE_SHARED_KEY => ServerHelloExtensionContent::PreSharedKey(
+                PreSharedKeyExtensionServerHello::deser(&data)?,
+            ),
+            extension_types::SUPPORTED_VERSIONS => ServerHelloExtensionContent::SupportedVersions(
+                SupportedVersionsServerHello::deser(&data)?,
+            ),
+            extension_types::KEY_SHARE => {
+                ServerHelloExtensionContent::KeyShare(KeyShareServerHello::deser(&data)?)
+            }
+            _ => ServerHelloExtensionContent::Unknown(extension_type, data),
+        };
+
+        Ok(Self { length, content })
     }
 }
 
@@ -139,6 +201,33 @@ impl ServerHello {
     }
 }
 
+impl RawDeser for ServerHello {
+    fn deser(raw: &[u8]) -> Result<Self> {
+        let legacy_version = u16::from_be_bytes(take(raw)?);
+        if legacy_version != 0x0303 {
+            bail!("Invalid legacy version: {legacy_version} (should be equal 0x0303)");
+        }
+
+        let random = Box::new(take(slice(raw, 2..)?)?);
+
+        let legacy_session_id_echo = DataVec8::<u8>::deser(slice(raw, 34..)?)?;
+        let mut offset = 34 + legacy_session_id_echo.size();
+
+        let cipher_suite = CipherSuite(u16::from_be_bytes(take(slice(raw, offset..)?)?));
+        // Legacy compression method
+        offset += 3;
+
+        let extensions = DataVec16::<ServerHelloExtension>::deser(slice(raw, offset..)?)?;
+
+        Ok(Self {
+            random,
+            legacy_session_id_echo: legacy_session_id_echo.into_inner(),
+            cipher_suite,
+            extensions: extensions.into_inner(),
+        })
+    }
+}
+
 impl RawSer for ServerHello {
     #[allow(clippy::cast_possible_truncation)]
     fn ser(&self) -> Box<[u8]> {
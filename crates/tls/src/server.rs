@@ -0,0 +1,964 @@
+use anyhow::{Result, anyhow, bail};
+use crypt::{
+    elliptic::x25519,
+    hash::{
+        Hasher,
+        sha::{Sha256, Sha384, Sha512},
+    },
+    rsa::{PrivateKey, rsassa_pss_sign},
+};
+
+use crate::{
+    VERSION,
+    cipher_suite::{CipherSuite, SUPPORTED_CIPHER_SUITES},
+    connection::{Event, HandshakeBuffer, RecordReader, next_record},
+    error::TlsAlert,
+    key_schedule::{KeySchedule, TrafficKeys, server_signed_content},
+    parse::RawDeser,
+    record::{
+        TlsCiphertext, TlsPlaintext,
+        alert::{Alert, AlertDescription, AlertLevel},
+        content_types,
+        handshake::{
+            Handshake,
+            certificate::{Certificate, CertificateEntry},
+            certificate_verify::CertificateVerify,
+            client_hello::{ClientHello, ClientHelloExtensionContent},
+            encrypted_extensions::EncryptedExtensions,
+            extension::{KeyShareEntry, NamedGroup, ServerName, SignatureScheme},
+            finished::Finished,
+            server_hello::{ServerHello, ServerHelloExtension},
+        },
+    },
+};
+
+/// Certificate and settings shared by all connections of a server.
+pub struct ServerConfig {
+    /// DER encoded certificate as sent to clients
+    pub certificate: Box<[u8]>,
+    /// Signature schemes usable with the key in order of preference, unsupported ones are skipped
+    pub signature_schemes: Vec<SignatureScheme>,
+    pub private_key: PrivateKey,
+    /// Application protocols in order of preference
+    pub alpn_protocols: Vec<String>,
+    /// Cipher suites in order of preference, unsupported ones are skipped
+    pub cipher_suites: Vec<CipherSuite>,
+}
+
+/// Signature schemes the server can sign with, all RSASSA-PSS.
+pub const SUPPORTED_SIGNATURE_SCHEMES: [SignatureScheme; 6] = [
+    SignatureScheme::rsa_pss_rsae_sha256,
+    SignatureScheme::rsa_pss_rsae_sha384,
+    SignatureScheme::rsa_pss_rsae_sha512,
+    SignatureScheme::rsa_pss_pss_sha256,
+    SignatureScheme::rsa_pss_pss_sha384,
+    SignatureScheme::rsa_pss_pss_sha512,
+];
+
+/// Sign with the hash of `scheme`, the salt is as long as the digest as RFC 8446 §4.2.3 requires.
+fn sign(key: &PrivateKey, scheme: SignatureScheme, message: &[u8]) -> Result<Box<[u8]>> {
+    match scheme {
+        SignatureScheme::rsa_pss_rsae_sha256 | SignatureScheme::rsa_pss_pss_sha256 => Ok(
+            rsassa_pss_sign::<Sha256, { Sha256::DIGEST_SIZE }>(key, message),
+        ),
+        SignatureScheme::rsa_pss_rsae_sha384 | SignatureScheme::rsa_pss_pss_sha384 => Ok(
+            rsassa_pss_sign::<Sha384, { Sha384::DIGEST_SIZE }>(key, message),
+        ),
+        SignatureScheme::rsa_pss_rsae_sha512 | SignatureScheme::rsa_pss_pss_sha512 => Ok(
+            rsassa_pss_sign::<Sha512, { Sha512::DIGEST_SIZE }>(key, message),
+        ),
+        _ => bail!(TlsAlert::InternalError),
+    }
+}
+
+/// What the client asked for during the handshake.
+#[derive(Clone, Debug)]
+pub struct HandshakeInfo {
+    pub server_name: Option<String>,
+    /// Negotiated application protocol
+    pub alpn: Option<String>,
+    pub cipher_suite: CipherSuite,
+    /// Key exchange group, `None` if the client sent no usable key share
+    pub group: Option<NamedGroup>,
+}
+
+/// Handshake progress, named after the states of RFC 8446 Appendix A.2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+    Start,
+    WaitFinished,
+    Connected,
+    Closed,
+}
+
+/// Server side of a TLS 1.3 connection without any I/O.
+///
+/// Received bytes are fed to [`ServerConnection::receive`], bytes to send are
+/// taken with [`ServerConnection::take_output`]. Protocol violations fail with
+/// a [`TlsAlert`] that is also queued for the client
+pub struct ServerConnection<'a> {
+    config: &'a ServerConfig,
+    state: State,
+
+    /// Received bytes not forming a complete record yet
+    received: Vec<u8>,
+    output: Vec<u8>,
+
+    /// Handshake messages not complete yet
+    handshake: HandshakeBuffer,
+    /// Handshake messages so far, record headers excluded
+    transcript: Vec<u8>,
+    info: Option<HandshakeInfo>,
+    read_keys: Option<TrafficKeys>,
+    write_keys: Option<TrafficKeys>,
+    /// Kept to verify the client Finished
+    schedule: Option<KeySchedule>,
+    /// Client application keys, used once the client Finished is read
+    next_read_keys: Option<TrafficKeys>,
+}
+
+impl<'a> ServerConnection<'a> {
+    pub fn new(config: &'a ServerConfig) -> Self {
+        Self {
+            config,
+            state: State::Start,
+            received: Vec::new(),
+            output: Vec::new(),
+            handshake: HandshakeBuffer::default(),
+            transcript: Vec::new(),
+            info: None,
+            read_keys: None,
+            write_keys: None,
+            schedule: None,
+            next_read_keys: None,
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn is_handshaking(&self) -> bool {
+        matches!(self.state, State::Start | State::WaitFinished)
+    }
+
+    /// Negotiated parameters, known once ClientHello was processed.
+    pub fn info(&self) -> Option<&HandshakeInfo> {
+        self.info.as_ref()
+    }
+
+    /// Bytes to send to the client.
+    pub fn take_output(&mut self) -> Box<[u8]> {
+        std::mem::take(&mut self.output).into_boxed_slice()
+    }
+
+    /// Process bytes received from the client.
+    ///
+    /// Incomplete records are kept until the rest arrives, as are records
+    /// following the end of the handshake. On error the connection is closed
+    /// and the alert, if any, queued for the client
+    pub fn receive(&mut self, data: &[u8]) -> Result<Vec<Event>> {
+        if self.state == State::Closed {
+            return Ok(Vec::new());
+        }
+        self.received.extend(data);
+
+        let mut events = Vec::new();
+        while let Some(record) = next_record(&mut self.received) {
+            if let Err(e) = record.and_then(|raw| self.process_record(&raw, &mut events)) {
+                self.state = State::Closed;
+                if let Some(alert) = e.downcast_ref::<TlsAlert>() {
+                    self.send_alert(*alert)?;
+                }
+                return Err(e);
+            }
+            // Records after the handshake are left for the reading half
+            if self.state == State::Closed || events.contains(&Event::HandshakeComplete) {
+                break;
+            }
+        }
+        Ok(events)
+    }
+
+    /// Queue application data for the client.
+    pub fn send(&mut self, data: &[u8]) -> Result<()> {
+        if self.state != State::Connected {
+            bail!("Connection is not established");
+        }
+        for chunk in data.chunks(1 << 14) {
+            self.write_record(&TlsPlaintext::new_application_data(chunk)?)?;
+        }
+        Ok(())
+    }
+
+    /// Queue `close_notify`, nothing can be sent afterwards.
+    pub fn close(&mut self) -> Result<()> {
+        if self.state != State::Closed {
+            self.write_record(&TlsPlaintext::new_alert(Alert {
+                level: AlertLevel::Warning,
+                description: AlertDescription::CloseNotify,
+            }))?;
+            self.state = State::Closed;
+        }
+        Ok(())
+    }
+
+    /// Reading half, server traffic keys and negotiated parameters.
+    ///
+    /// Records received after the handshake are passed to the reading half
+    pub fn into_parts(self) -> Result<(RecordReader, TrafficKeys, HandshakeInfo)> {
+        if self.state != State::Connected {
+            bail!("Connection is not established");
+        }
+        match (self.read_keys, self.write_keys, self.info) {
+            (Some(read_keys), Some(write_keys), Some(info)) => Ok((
+                RecordReader::with_received(read_keys, self.received),
+                write_keys,
+                info,
+            )),
+            _ => bail!("Missing traffic keys"),
+        }
+    }
+
+    fn process_record(&mut self, raw: &[u8], events: &mut Vec<Event>) -> Result<()> {
+        match (self.state, raw[0]) {
+            (State::Start, content_types::HANDSHAKE) => {
+                self.process_content(content_types::HANDSHAKE, &raw[5..], events)
+            }
+            // Sent for middlebox compatibility, RFC 8446 §5
+            (State::WaitFinished, content_types::CHANGE_CIPHER_SPEC) => {
+                if raw[5..] != [1] {
+                    bail!(TlsAlert::UnexpectedMessage);
+                }
+                Ok(())
+            }
+            (State::WaitFinished | State::Connected, content_types::APPLICATION_DATA) => {
+                let (content_type, content) = self.decrypt(raw)?;
+                self.process_content(content_type, &content, events)
+            }
+            (_, content_types::ALERT) if self.read_keys.is_none() => {
+                let record = TlsPlaintext::from_raw(raw).map_err(|_| TlsAlert::DecodeError)?;
+                self.state = State::Closed;
+                Err(anyhow!("Received alert: {:?}", record.fragment))
+            }
+            _ => bail!(TlsAlert::UnexpectedMessage),
+        }
+    }
+
+    fn decrypt(&mut self, raw: &[u8]) -> Result<(u8, Box<[u8]>)> {
+        let keys = self.read_keys.as_mut().ok_or(TlsAlert::UnexpectedMessage)?;
+        if raw.len() < 5 + 17 {
+            bail!(TlsAlert::BadRecordMac);
+        }
+        let record = TlsCiphertext::from_raw(raw).map_err(|_| TlsAlert::DecodeError)?;
+        keys.open(&record)
+            .map_err(|_| TlsAlert::BadRecordMac.into())
+    }
+
+    fn process_content(
+        &mut self,
+        content_type: u8,
+        content: &[u8],
+        events: &mut Vec<Event>,
+    ) -> Result<()> {
+        if content_type != content_types::HANDSHAKE && !self.handshake.is_empty() {
+            bail!(TlsAlert::UnexpectedMessage);
+        }
+        match (self.state, content_type) {
+            (_, content_types::HANDSHAKE) => {
+                self.handshake.push(content)?;
+                while let Some(message) = self.handshake.next_message() {
+                    self.process_handshake(&message?, events)?;
+                }
+                Ok(())
+            }
+            (State::Connected, content_types::APPLICATION_DATA) => {
+                events.push(Event::ApplicationData(Box::from(content)));
+                Ok(())
+            }
+            (_, content_types::ALERT) => {
+                let alert = Alert::deser(content).map_err(|_| TlsAlert::DecodeError)?;
+                self.state = State::Closed;
+                if matches!(alert.description, AlertDescription::CloseNotify) {
+                    events.push(Event::Closed);
+                    Ok(())
+                } else {
+                    Err(anyhow!("Received alert: {alert:?}"))
+                }
+            }
+            _ => bail!(TlsAlert::UnexpectedMessage),
+        }
+    }
+
+    /// Process one complete handshake message, `raw` includes its header.
+    fn process_handshake(&mut self, raw: &[u8], events: &mut Vec<Event>) -> Result<()> {
+        let handshake = Handshake::deser(raw).map_err(|_| TlsAlert::DecodeError)?;
+        match (self.state, handshake) {
+            (State::Start, Handshake::ClientHello(client_hello)) => {
+                self.transcript.extend(raw);
+                self.client_hello(client_hello)?;
+            }
+            (State::WaitFinished, Handshake::Finished(finished)) => {
+                let schedule = self.schedule.take().ok_or(TlsAlert::InternalError)?;
+                if finished.verify_data != schedule.client_finished(&self.transcript) {
+                    bail!(TlsAlert::DecryptError);
+                }
+                self.transcript.extend(raw);
+                self.read_keys = self.next_read_keys.take();
+                self.state = State::Connected;
+                events.push(Event::HandshakeComplete);
+            }
+            _ => bail!(TlsAlert::UnexpectedMessage),
+        }
+        // Both messages are followed by a key change, which must happen on a
+        // record boundary, RFC 8446 §5.1
+        if !self.handshake.is_empty() {
+            bail!(TlsAlert::UnexpectedMessage);
+        }
+        Ok(())
+    }
+
+    fn client_hello(&mut self, client_hello: ClientHello) -> Result<()> {
+        let mut server_name = None;
+        let mut alpn = None;
+        let mut supported_versions = None;
+        let mut key_share = None;
+        let mut signature_algorithms = None;
+        for extension in client_hello.extensions {
+            match extension.content {
+                ClientHelloExtensionContent::ServerName(list) => {
+                    server_name =
+                        list.server_name_list
+                            .iter()
+                            .find_map(|ServerName::HostName(name)| {
+                                String::from_utf8(name.to_vec())
+                                    .ok()
+                                    .map(|x| x.to_lowercase())
+                            });
+                }
+                ClientHelloExtensionContent::ApplicationLayerProtocolNegotiation(list) => {
+                    alpn = self
+                        .config
+                        .alpn_protocols
+                        .iter()
+                        .find(|protocol| {
+                            list.protocol_name_list
+                                .iter()
+                                .any(|x| *x.data == *protocol.as_bytes())
+                        })
+                        .cloned();
+                }
+                ClientHelloExtensionContent::SupportedVersions(e) => {
+                    supported_versions = Some(e.versions);
+                }
+                ClientHelloExtensionContent::KeyShare(e) => key_share = Some(e),
+                ClientHelloExtensionContent::SignatureAlgorithms(e) => {
+                    signature_algorithms = Some(e.supported_signature_algorithms.into_inner());
+                }
+                _ => {}
+            }
+        }
+
+        if !supported_versions.is_some_and(|x| x.contains(&VERSION)) {
+            bail!(TlsAlert::ProtocolVersion);
+        }
+        // Our preference wins over the client's order
+        let cipher_suite = self
+            .config
+            .cipher_suites
+            .iter()
+            .copied()
+            .filter(|x| SUPPORTED_CIPHER_SUITES.contains(x))
+            .find(|x| client_hello.cipher_suites.contains(x))
+            .ok_or(TlsAlert::HandshakeFailure)?;
+        // Required for certificate authentication, RFC 8446 §4.2.3
+        let signature_algorithms = signature_algorithms.ok_or(TlsAlert::MissingExtension)?;
+        let signature_scheme = self
+            .config
+            .signature_schemes
+            .iter()
+            .copied()
+            .filter(|x| SUPPORTED_SIGNATURE_SCHEMES.contains(x))
+            .find(|x| signature_algorithms.contains(x))
+            .ok_or(TlsAlert::HandshakeFailure)?;
+        let key_share = key_share.ok_or(TlsAlert::MissingExtension)?;
+        // No HelloRetryRequest, the client has to offer x25519 right away
+        let client_share = key_share
+            .client_shares
+            .iter()
+            .find(|x| x.group == NamedGroup::x25519)
+            .ok_or(TlsAlert::HandshakeFailure)?;
+        let client_share: [u8; 32] = client_share
+            .key_exchange
+            .as_ref()
+            .try_into()
+            .map_err(|_| TlsAlert::IllegalParameter)?;
+
+        let (public, private) = x25519::get_keypair();
+        let shared = x25519::get_shared_key(private, client_share);
+
+        // ServerHello
+
+        let server_hello = Handshake::ServerHello(ServerHello::new(
+            &rand::random(),
+            &client_hello.legacy_session_id,
+            cipher_suite,
+            &[
+                ServerHelloExtension::new_supported_versions(VERSION),
+                ServerHelloExtension::new_key_share(KeyShareEntry::new(
+                    NamedGroup::x25519,
+                    &public,
+                ))?,
+            ],
+        ));
+        let record = TlsPlaintext::new_handshake(server_hello)?.to_raw();
+        self.transcript.extend(&record[5..]);
+        self.output.extend(record);
+
+        let schedule = KeySchedule::new(cipher_suite, &shared, &self.transcript);
+        self.write_keys = Some(schedule.server_handshake_keys()?);
+        self.read_keys = Some(schedule.client_handshake_keys()?);
+
+        // The rest of the flight is coalesced into as few records as possible
+        let mut flight = Vec::new();
+
+        // EncryptedExtensions
+
+        let ee_extensions = match &alpn {
+            Some(protocol) => vec![ServerHelloExtension::new_alpn(protocol.as_bytes())?],
+            None => Vec::new(),
+        };
+        self.write_handshake(
+            &mut flight,
+            Handshake::EncryptedExtensions(EncryptedExtensions::new(&ee_extensions)?),
+        )?;
+
+        // Certificate
+
+        self.write_handshake(
+            &mut flight,
+            Handshake::Certificate(Certificate::new(
+                &[],
+                &[CertificateEntry::new(&self.config.certificate)?],
+            )?),
+        )?;
+
+        // CertificateVerify
+
+        let signature = sign(
+            &self.config.private_key,
+            signature_scheme,
+            &server_signed_content(cipher_suite, &self.transcript),
+        )?;
+        self.write_handshake(
+            &mut flight,
+            Handshake::CertificateVerify(CertificateVerify::new(signature_scheme, &signature)?),
+        )?;
+
+        // Finished
+
+        let verify_data = schedule.server_finished(&self.transcript);
+        self.write_handshake(&mut flight, Handshake::Finished(Finished { verify_data }))?;
+        self.write_flight(&flight)?;
+
+        let (client_keys, server_keys) = schedule.application_keys(&self.transcript)?;
+        self.write_keys = Some(server_keys);
+        self.next_read_keys = Some(client_keys);
+        self.schedule = Some(schedule);
+
+        self.info = Some(HandshakeInfo {
+            server_name,
+            alpn,
+            cipher_suite,
+            group: Some(NamedGroup::x25519),
+        });
+        self.state = State::WaitFinished;
+        Ok(())
+    }
+
+    fn write_handshake(&mut self, flight: &mut Vec<u8>, handshake: Handshake) -> Result<()> {
+        let raw = TlsPlaintext::new_handshake(handshake)?.to_raw();
+        self.transcript.extend(&raw[5..]);
+        flight.extend(&raw[5..]);
+        Ok(())
+    }
+
+    /// Encrypt handshake messages, fragmented across records if needed.
+    fn write_flight(&mut self, flight: &[u8]) -> Result<()> {
+        let keys = self.write_keys.as_mut().ok_or(TlsAlert::InternalError)?;
+        for fragment in flight.chunks(1 << 14) {
+            self.output
+                .extend(keys.seal(content_types::HANDSHAKE, fragment)?.to_raw());
+        }
+        Ok(())
+    }
+
+    /// Protect the record once keys are known.
+    fn write_record(&mut self, record: &TlsPlaintext) -> Result<()> {
+        match &mut self.write_keys {
+            Some(keys) => self.output.extend(keys.encrypt(record)?.to_raw()),
+            None => self.output.extend(record.to_raw()),
+        }
+        Ok(())
+    }
+
+    fn send_alert(&mut self, alert: TlsAlert) -> Result<()> {
+        let description = AlertDescription::try_from(alert.code())?;
+        self.write_record(&TlsPlaintext::new_alert(Alert {
+            level: AlertLevel::Fatal,
+            description,
+        }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crypt::rsa::{PublicKey, rsassa_pss_verify};
+    use hex_literal::hex;
+    use num_bigint::BigUint;
+
+    use super::*;
+    use crate::{
+        cipher_suite::{
+            TLS_AES_128_GCM_SHA256, TLS_AES_256_GCM_SHA384, TLS_CHACHA20_POLY1305_SHA256,
+        },
+        record::{
+            TlsContent,
+            handshake::{
+                client_hello::ClientHelloExtension, server_hello::ServerHelloExtensionContent,
+            },
+        },
+    };
+
+    /// 1024 bit test key, its public exponent is 65537
+    pub(crate) const MODULUS: [u8; 128] = hex!(
+        "bcb47b2e0dafcba81ff2a2b5cb115ca7e757184c9d72bcdcda707a146b3b4e29
+         989ddc660bd694865b932b71ca24a335cf4d339c719183e6222e4c9ea6875acd
+         528a49ba21863fe08147c3a47e41990b51a03f77d22137f8d74c43a5a45f4e9e
+         18a2d15db051dc89385db9cf8374b63a8cc88113710e6d8179075b7dc79ee76b"
+    );
+
+    pub(crate) fn config() -> ServerConfig {
+        ServerConfig {
+            certificate: Box::new([0x30, 0x00]),
+            signature_schemes: vec![
+                SignatureScheme::rsa_pss_rsae_sha256,
+                SignatureScheme::rsa_pss_rsae_sha384,
+            ],
+            private_key: PrivateKey {
+                modulus: BigUint::from_bytes_be(&MODULUS),
+                exponent: BigUint::from_bytes_be(&hex!(
+                    "383a6f19e1ea27fd08c7fbc3bfa684bd6329888c0bbe4c98625e7181f411cfd0
+                     853144a3039404dda41bce2e31d588ec57c0e148146f0fa65b39008ba5835f82
+                     9ba35ae2f155d61b8a12581b99c927fd2f22252c5e73cba4a610db3973e019ee
+                     0f95130d4319ed413432f2e5e20d5215cdd27c2164206b3f80edee51938a25c1"
+                )),
+            },
+            alpn_protocols: vec![String::from("h2"), String::from("http/1.1")],
+            cipher_suites: SUPPORTED_CIPHER_SUITES.to_vec(),
+        }
+    }
+
+    fn client_hello(public: &[u8; 32]) -> Result<Box<[u8]>> {
+        client_hello_with(
+            &[TLS_AES_256_GCM_SHA384],
+            &[KeyShareEntry::new(NamedGroup::x25519, public)],
+        )
+    }
+
+    fn client_hello_with(
+        cipher_suites: &[CipherSuite],
+        shares: &[KeyShareEntry],
+    ) -> Result<Box<[u8]>> {
+        client_hello_signing(
+            cipher_suites,
+            shares,
+            &[SignatureScheme::rsa_pss_rsae_sha256],
+        )
+    }
+
+    /// ClientHello offering `signature_schemes`, the extension is left out if there are none.
+    fn client_hello_signing(
+        cipher_suites: &[CipherSuite],
+        shares: &[KeyShareEntry],
+        signature_schemes: &[SignatureScheme],
+    ) -> Result<Box<[u8]>> {
+        let mut extensions = vec![
+            ClientHelloExtension::new_server_name(b"Example.com")?,
+            ClientHelloExtension::new_supported_versions(&[VERSION])?,
+            ClientHelloExtension::new_key_share(shares)?,
+            ClientHelloExtension::new_alpn(&[b"http/1.1"])?,
+        ];
+        if !signature_schemes.is_empty() {
+            extensions.push(ClientHelloExtension::new_signature_algorithms(
+                signature_schemes,
+            )?);
+        }
+        let client_hello = Handshake::ClientHello(ClientHello::new(
+            &[1; 32],
+            &[2; 32],
+            cipher_suites,
+            &extensions,
+        )?);
+        Ok(TlsPlaintext::new_handshake(client_hello)?.to_raw())
+    }
+
+    fn records(mut raw: &[u8]) -> Vec<&[u8]> {
+        let mut res = Vec::new();
+        while raw.len() >= 5 {
+            let (record, rest) = raw.split_at(5 + u16::from_be_bytes([raw[3], raw[4]]) as usize);
+            res.push(record);
+            raw = rest;
+        }
+        res
+    }
+
+    /// Check a CertificateVerify signature of the test key.
+    fn verify(scheme: SignatureScheme, message: &[u8], signature: &[u8]) -> Result<()> {
+        let key = PublicKey {
+            modulus: BigUint::from_bytes_be(&MODULUS),
+            exponent: BigUint::from(65537u32),
+        };
+        match scheme {
+            SignatureScheme::rsa_pss_rsae_sha256 => {
+                rsassa_pss_verify::<Sha256, { Sha256::DIGEST_SIZE }>(&key, message, signature)
+            }
+            SignatureScheme::rsa_pss_rsae_sha384 => {
+                rsassa_pss_verify::<Sha384, { Sha384::DIGEST_SIZE }>(&key, message, signature)
+            }
+            _ => bail!("Unexpected signature scheme {scheme:?}"),
+        }
+    }
+
+    /// Process the server flight as a client, returning the key schedule and signature scheme.
+    ///
+    /// `transcript` runs up to ClientHello and is extended up to server Finished
+    fn read_flight(
+        output: &[u8],
+        private: [u8; 32],
+        transcript: &mut Vec<u8>,
+    ) -> Result<(KeySchedule, SignatureScheme)> {
+        let flight = records(output);
+        // ServerHello, then the encrypted messages coalesced into one record
+        assert_eq!(flight.len(), 2);
+
+        let TlsContent::Handshake(Handshake::ServerHello(server_hello)) =
+            TlsPlaintext::from_raw(flight[0])?.fragment
+        else {
+            panic!("Not server hello");
+        };
+        transcript.extend(&flight[0][5..]);
+        let share = server_hello
+            .extensions
+            .iter()
+            .find_map(|x| match &x.content {
+                ServerHelloExtensionContent::KeyShare(e) => {
+                    Some(e.server_share.key_exchange.clone())
+                }
+                _ => None,
+            })
+            .ok_or(anyhow!("No key share"))?;
+        let shared = x25519::get_shared_key(private, share.as_ref().try_into()?);
+
+        let schedule = KeySchedule::new(server_hello.cipher_suite, &shared, transcript);
+        let (content_type, content) = schedule
+            .server_handshake_keys()?
+            .open(&TlsCiphertext::from_raw(flight[1])?)?;
+        assert_eq!(content_type, content_types::HANDSHAKE);
+        let mut messages = HandshakeBuffer::default();
+        messages.push(&content)?;
+        let mut scheme = None;
+        while let Some(message) = messages.next_message() {
+            let message = message?;
+            match Handshake::deser(&message)? {
+                Handshake::CertificateVerify(certificate_verify) => {
+                    let content = server_signed_content(server_hello.cipher_suite, transcript);
+                    verify(
+                        certificate_verify.algorithm,
+                        &content,
+                        certificate_verify.signature.as_slice(),
+                    )?;
+                    scheme = Some(certificate_verify.algorithm);
+                }
+                Handshake::Finished(finished) => {
+                    assert_eq!(finished.verify_data, schedule.server_finished(transcript));
+                }
+                _ => {}
+            }
+            transcript.extend(message);
+        }
+
+        Ok((schedule, scheme.ok_or(anyhow!("No CertificateVerify"))?))
+    }
+
+    #[test]
+    fn test_handshake() -> Result<()> {
+        let config = config();
+        let mut server = ServerConnection::new(&config);
+
+        let (public, private) = x25519::get_keypair();
+        let ch = client_hello(&public)?;
+        let mut transcript = ch[5..].to_vec();
+
+        // Records may arrive in pieces
+        assert!(server.receive(&ch[..10])?.is_empty());
+        assert_eq!(server.state(), State::Start);
+        assert!(server.receive(&ch[10..])?.is_empty());
+        assert_eq!(server.state(), State::WaitFinished);
+
+        let info = server.info().ok_or(anyhow!("No info"))?;
+        assert_eq!(info.server_name.as_deref(), Some("example.com"));
+        assert_eq!(info.alpn.as_deref(), Some("http/1.1"));
+
+        let (schedule, _) = read_flight(&server.take_output(), private, &mut transcript)?;
+
+        let (mut client_keys, mut server_keys) = schedule.application_keys(&transcript)?;
+        let finished = TlsPlaintext::new_handshake(Handshake::Finished(Finished {
+            verify_data: schedule.client_finished(&transcript),
+        }))?;
+        let finished = schedule.client_handshake_keys()?.encrypt(&finished)?;
+        assert_eq!(
+            server.receive(&finished.to_raw())?,
+            [Event::HandshakeComplete]
+        );
+
+        // Two records in one read
+        let mut encrypt = |data: &[u8]| -> Result<Box<[u8]>> {
+            let record = TlsPlaintext::new_application_data(data)?;
+            Ok(client_keys.encrypt(&record)?.to_raw())
+        };
+        let data = [encrypt(b"ping")?, encrypt(b"pong")?].concat();
+        assert_eq!(
+            server.receive(&data)?,
+            [
+                Event::ApplicationData(Box::from(*b"ping")),
+                Event::ApplicationData(Box::from(*b"pong"))
+            ]
+        );
+
+        // Sequence numbers are per direction, the server's start from zero
+        server.send(b"hello")?;
+        let output = server.take_output();
+        let record = server_keys.decrypt(&TlsCiphertext::from_raw(&output)?)?;
+        assert!(matches!(record.fragment, TlsContent::ApplicationData(x) if *x == *b"hello"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unexpected_message() -> Result<()> {
+        let config = config();
+
+        // Finished before ClientHello
+        let mut server = ServerConnection::new(&config);
+        let finished = TlsPlaintext::new_handshake(Handshake::Finished(Finished {
+            verify_data: Box::new([0; 48]),
+        }))?;
+        let e = server.receive(&finished.to_raw()).err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::UnexpectedMessage)
+        );
+        assert_eq!(*server.take_output(), [21, 3, 3, 0, 2, 2, 10]);
+        assert_eq!(server.state(), State::Closed);
+
+        // Second ClientHello in place of Finished
+        let mut server = ServerConnection::new(&config);
+        let ch = client_hello(&x25519::get_keypair().0)?;
+        server.receive(&ch)?;
+        let e = server.receive(&ch).err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::UnexpectedMessage)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let config = config();
+
+        let inputs: [&[u8]; 5] = [
+            // Unknown handshake type
+            &[22, 3, 3, 0, 4, 3, 0, 0, 0],
+            // Empty ClientHello
+            &[22, 3, 3, 0, 4, 1, 0, 0, 0],
+            // ClientHello cut after the legacy version
+            &[22, 3, 3, 0, 6, 1, 0, 0, 2, 3, 3],
+            // ClientHello cut inside the session id
+            &[&[22, 3, 3, 0, 39, 1, 0, 0, 35, 3, 3][..], &[0; 32], &[32]].concat(),
+            // Empty alert
+            &[21, 3, 3, 0, 0],
+        ];
+        for raw in inputs {
+            let mut server = ServerConnection::new(&config);
+            let e = server.receive(raw).err();
+            assert_eq!(
+                e.and_then(|x| x.downcast().ok()),
+                Some(TlsAlert::DecodeError),
+                "{raw:02x?}"
+            );
+            assert_eq!(server.state(), State::Closed);
+        }
+    }
+
+    #[test]
+    fn test_fragmented_client_hello() -> Result<()> {
+        let config = config();
+
+        // Post-quantum hybrid share as sent by browsers, 0x11ec is X25519MLKEM768
+        let ch = client_hello_with(
+            &[TLS_AES_256_GCM_SHA384],
+            &[
+                KeyShareEntry::new(NamedGroup::Other(0x11ec), &[7; 1216]),
+                KeyShareEntry::new(NamedGroup::x25519, &x25519::get_keypair().0),
+            ],
+        )?;
+        let mut data = Vec::new();
+        for fragment in ch[5..].chunks(500) {
+            data.extend([content_types::HANDSHAKE, 3, 3]);
+            data.extend(u16::try_from(fragment.len())?.to_be_bytes());
+            data.extend(fragment);
+        }
+        // Split at odd places, several records per read
+        let mut server = ServerConnection::new(&config);
+        for chunk in data.chunks(700) {
+            assert!(server.receive(chunk)?.is_empty());
+        }
+        assert_eq!(server.state(), State::WaitFinished);
+
+        // Nothing may follow ClientHello in its record, keys change after it
+        let mut server = ServerConnection::new(&config);
+        let mut ch = ch.to_vec();
+        ch.extend([20, 0, 0, 0]);
+        let length = u16::try_from(ch.len() - 5)?;
+        ch[3..5].copy_from_slice(&length.to_be_bytes());
+        let e = server.receive(&ch).err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::UnexpectedMessage)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_finished() -> Result<()> {
+        let config = config();
+        let mut server = ServerConnection::new(&config);
+
+        let (public, private) = x25519::get_keypair();
+        let ch = client_hello(&public)?;
+        let mut transcript = ch[5..].to_vec();
+        server.receive(&ch)?;
+        let (schedule, _) = read_flight(&server.take_output(), private, &mut transcript)?;
+
+        // Server Finished echoed back instead of the client one
+        let finished = TlsPlaintext::new_handshake(Handshake::Finished(Finished {
+            verify_data: schedule.server_finished(&transcript),
+        }))?;
+        let finished = schedule.client_handshake_keys()?.encrypt(&finished)?;
+        let e = server.receive(&finished.to_raw()).err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::DecryptError)
+        );
+        assert_eq!(server.state(), State::Closed);
+
+        let (_, mut server_keys) = schedule.application_keys(&transcript)?;
+        let record = server_keys.decrypt(&TlsCiphertext::from_raw(&server.take_output())?)?;
+        assert!(matches!(
+            record.fragment,
+            TlsContent::Alert(Alert {
+                description: AlertDescription::DecryptError,
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cipher_suite_selection() -> Result<()> {
+        let config = config();
+        let shares = [KeyShareEntry::new(
+            NamedGroup::x25519,
+            &x25519::get_keypair().0,
+        )];
+
+        // Server preference wins over the client's order
+        let mut server = ServerConnection::new(&config);
+        server.receive(&client_hello_with(
+            &[TLS_AES_128_GCM_SHA256, TLS_CHACHA20_POLY1305_SHA256],
+            &shares,
+        )?)?;
+        let info = server.info().ok_or(anyhow!("No info"))?;
+        assert_eq!(info.cipher_suite, TLS_CHACHA20_POLY1305_SHA256);
+
+        // No suite in common
+        let mut server = ServerConnection::new(&config);
+        let e = server
+            .receive(&client_hello_with(&[CipherSuite(0x13_04)], &shares)?)
+            .err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::HandshakeFailure)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_signature_scheme_selection() -> Result<()> {
+        let config = config();
+        let (public, private) = x25519::get_keypair();
+        let shares = [KeyShareEntry::new(NamedGroup::x25519, &public)];
+
+        // Only a scheme the key is configured for is used, signed with its hash
+        let mut server = ServerConnection::new(&config);
+        let ch = client_hello_signing(
+            &[TLS_AES_256_GCM_SHA384],
+            &shares,
+            &[
+                SignatureScheme::rsa_pss_pss_sha256,
+                SignatureScheme::rsa_pss_rsae_sha384,
+            ],
+        )?;
+        let mut transcript = ch[5..].to_vec();
+        server.receive(&ch)?;
+        let (_, scheme) = read_flight(&server.take_output(), private, &mut transcript)?;
+        assert_eq!(scheme, SignatureScheme::rsa_pss_rsae_sha384);
+
+        // No scheme in common
+        let mut server = ServerConnection::new(&config);
+        let e = server
+            .receive(&client_hello_signing(
+                &[TLS_AES_256_GCM_SHA384],
+                &shares,
+                &[SignatureScheme::ed25519, SignatureScheme::rsa_pkcs1_sha256],
+            )?)
+            .err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::HandshakeFailure)
+        );
+        assert_eq!(*server.take_output(), [21, 3, 3, 0, 2, 2, 40]);
+
+        // Certificate authentication needs signature_algorithms
+        let mut server = ServerConnection::new(&config);
+        let e = server
+            .receive(&client_hello_signing(
+                &[TLS_AES_256_GCM_SHA384],
+                &shares,
+                &[],
+            )?)
+            .err();
+        assert_eq!(
+            e.and_then(|x| x.downcast().ok()),
+            Some(TlsAlert::MissingExtension)
+        );
+
+        Ok(())
+    }
+}
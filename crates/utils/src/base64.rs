@@ -0,0 +1,95 @@
+//! Base64 encoding, RFC 4648.
+
+const STANDARD: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const URL_SAFE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn encode_with(data: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut res = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let block = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, x)| acc | (u32::from(*x) << (16 - i * 8)));
+
+        for i in 0..=chunk.len() {
+            res.push(char::from(
+                alphabet[(block >> (18 - i * 6)) as usize & 0x3F],
+            ));
+        }
+        if pad {
+            for _ in chunk.len()..3 {
+                res.push('=');
+            }
+        }
+    }
+
+    res
+}
+
+/// Standard alphabet with padding.
+pub fn encode(data: &[u8]) -> String {
+    encode_with(data, STANDARD, true)
+}
+
+/// URL and filename safe alphabet without padding.
+pub fn encode_url(data: &[u8]) -> String {
+    encode_with(data, URL_SAFE, false)
+}
+
+/// Decode either alphabet, padding is optional.
+///
+/// Returns `None` on invalid input
+pub fn decode(encoded: &str) -> Option<Box<[u8]>> {
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    if encoded.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut res = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.chunks(4) {
+        let mut block = 0u32;
+        for (i, x) in chunk.iter().enumerate() {
+            let value = match x {
+                b'A'..=b'Z' => x - b'A',
+                b'a'..=b'z' => x - b'a' + 26,
+                b'0'..=b'9' => x - b'0' + 52,
+                b'+' | b'-' => 62,
+                b'/' | b'_' => 63,
+                _ => return None,
+            };
+            block |= u32::from(value) << (18 - i * 6);
+        }
+
+        res.extend(&block.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Some(res.into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        let vectors: [(&[u8], &str); 7] = [
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+        ];
+        for (data, encoded) in vectors {
+            assert_eq!(encode(data), encoded);
+            assert_eq!(*decode(encoded).unwrap(), *data);
+        }
+
+        assert_eq!(encode_url(&[0xFB, 0xFF]), "-_8");
+        assert_eq!(*decode("-_8").unwrap(), [0xFB, 0xFF]);
+        assert!(decode("Zm9v!").is_none());
+        assert!(decode("Z").is_none());
+    }
+}
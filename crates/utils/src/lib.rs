@@ -1 +1,2 @@
+pub mod base64;
 mod macros;
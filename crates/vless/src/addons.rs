@@ -0,0 +1,90 @@
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    protobuf::{wire_types, write_length_delimited},
+    reader::Reader,
+};
+
+mod field_numbers {
+    pub const FLOW: u64 = 1;
+    pub const SEED: u64 = 2;
+}
+
+fn read_length_delimited<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8]> {
+    let length = usize::try_from(reader.varint()?)?;
+    Ok(reader.take(length)?)
+}
+
+/// Protobuf-encoded request/response addons.
+///
+/// ```protobuf
+/// message Addons {
+///     string Flow = 1;
+///     bytes Seed = 2;
+/// }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Addons {
+    pub flow: Option<String>,
+    pub seed: Option<Box<[u8]>>,
+}
+
+impl Addons {
+    pub fn from_raw(raw: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(raw);
+        let mut res = Self::default();
+
+        while !reader.is_empty() {
+            let key = reader.varint()?;
+            #[allow(clippy::cast_possible_truncation)]
+            let wire_type = (key & 0x07) as u8;
+            let field_number = key >> 3;
+
+            match (field_number, wire_type) {
+                (field_numbers::FLOW, wire_types::LENGTH_DELIMITED) => {
+                    let flow = read_length_delimited(&mut reader)?;
+                    res.flow = Some(
+                        String::from_utf8(flow.to_vec()).map_err(|_| anyhow!("Invalid flow"))?,
+                    );
+                }
+                (field_numbers::SEED, wire_types::LENGTH_DELIMITED) => {
+                    res.seed = Some(Box::from(read_length_delimited(&mut reader)?));
+                }
+
+                // Unknown fields are skipped
+                (_, wire_types::VARINT) => {
+                    reader.varint()?;
+                }
+                (_, wire_types::FIXED_64) => {
+                    reader.take(8)?;
+                }
+                (_, wire_types::LENGTH_DELIMITED) => {
+                    read_length_delimited(&mut reader)?;
+                }
+                (_, wire_types::FIXED_32) => {
+                    reader.take(4)?;
+                }
+                (_, _) => bail!("Unsupported protobuf wire type: {wire_type}"),
+            }
+        }
+
+        Ok(res)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flow.is_none() && self.seed.is_none()
+    }
+
+    pub fn to_raw(&self) -> Box<[u8]> {
+        let mut res = Vec::new();
+
+        if let Some(flow) = &self.flow {
+            write_length_delimited(&mut res, field_numbers::FLOW, flow.as_bytes());
+        }
+        if let Some(seed) = &self.seed {
+            write_length_delimited(&mut res, field_numbers::SEED, seed);
+        }
+
+        res.into_boxed_slice()
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::{Result, anyhow};
+
+use crate::{AddrType, reader::Reader};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Address {
+    Ipv4(Ipv4Addr),
+    Domain(String),
+    Ipv6(Ipv6Addr),
+}
+
+impl Address {
+    pub fn addr_type(&self) -> AddrType {
+        match self {
+            Self::Ipv4(_) => AddrType::Ipv4,
+            Self::Domain(_) => AddrType::Domain,
+            Self::Ipv6(_) => AddrType::Ipv6,
+        }
+    }
+
+    pub fn to_raw(&self) -> Result<Box<[u8]>> {
+        let mut res = vec![u8::from(self.addr_type())];
+
+        match self {
+            Self::Ipv4(addr) => res.extend(addr.octets()),
+            Self::Domain(domain) => {
+                let length = u8::try_from(domain.len())
+                    .ok()
+                    .filter(|x| *x != 0)
+                    .ok_or(anyhow!("Invalid domain length: {}", domain.len()))?;
+                res.push(length);
+                res.extend(domain.as_bytes());
+            }
+            Self::Ipv6(addr) => res.extend(addr.octets()),
+        }
+
+        Ok(res.into_boxed_slice())
+    }
+
+    pub(crate) fn read(reader: &mut Reader, addr_type: AddrType) -> Result<Self> {
+        Ok(match addr_type {
+            AddrType::Ipv4 => Self::Ipv4(Ipv4Addr::from(reader.take_array::<4>()?)),
+            AddrType::Domain => {
+                let length = reader.u8()?;
+                if length == 0 {
+                    return Err(anyhow!("Empty domain address"));
+                }
+                let domain = reader.take(length as usize)?;
+                Self::Domain(
+                    String::from_utf8(domain.to_vec()).map_err(|_| anyhow!("Invalid domain"))?,
+                )
+            }
+            AddrType::Ipv6 => Self::Ipv6(Ipv6Addr::from(reader.take_array::<16>()?)),
+        })
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ipv4(addr) => addr.fmt(f),
+            Self::Domain(domain) => domain.fmt(f),
+            Self::Ipv6(addr) => write!(f, "[{addr}]"),
+        }
+    }
+}
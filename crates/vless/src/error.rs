@@ -0,0 +1,7 @@
+#[derive(thiserror::Error, Debug)]
+pub enum VlessError {
+    #[error("Unexpected end of input")]
+    Truncated,
+    #[error("Unsupported protocol version: {0}")]
+    UnsupportedVersion(u8),
+}
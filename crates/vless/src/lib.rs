@@ -1,9 +1,34 @@
+mod addons;
+mod address;
+mod error;
+pub mod mux;
+pub mod protobuf;
+mod reader;
+pub mod udp;
+mod uuid;
+pub mod vision;
+
+pub use addons::Addons;
+pub use address::Address;
+pub use error::VlessError;
+pub use uuid::{format_uuid, parse_uuid};
+
 use anyhow::{Result, anyhow};
+use utils::concat_dyn;
+
+use crate::reader::Reader;
+
+pub const VERSION: u8 = 0;
+
+/// Placeholder destination of [`Command::Mux`] requests, which carry no address.
+pub const MUX_COOL_DOMAIN: &str = "v1.mux.cool";
 
 // u8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Command {
     TCP,
     UDP,
+    Mux,
 }
 
 impl TryFrom<u8> for Command {
@@ -13,12 +38,24 @@ impl TryFrom<u8> for Command {
         match value {
             0x01 => Ok(Self::TCP),
             0x02 => Ok(Self::UDP),
+            0x03 => Ok(Self::Mux),
             _ => Err(anyhow!("Unknown `Command` value: 0x{value:02X}")),
         }
     }
 }
 
+impl From<Command> for u8 {
+    fn from(value: Command) -> Self {
+        match value {
+            Command::TCP => 0x01,
+            Command::UDP => 0x02,
+            Command::Mux => 0x03,
+        }
+    }
+}
+
 // u8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum AddrType {
     Ipv4,
     Domain,
@@ -37,31 +74,296 @@ impl TryFrom<u8> for AddrType {
         }
     }
 }
+
+impl From<AddrType> for u8 {
+    fn from(value: AddrType) -> Self {
+        match value {
+            AddrType::Ipv4 => 0x01,
+            AddrType::Domain => 0x02,
+            AddrType::Ipv6 => 0x03,
+        }
+    }
+}
+
+fn addons_to_raw(addons: &Addons) -> Result<Box<[u8]>> {
+    let raw = addons.to_raw();
+    let length = u8::try_from(raw.len()).map_err(|_| anyhow!("Addons are too long"))?;
+    Ok(concat_dyn!([length], raw))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct VlessRequestHeader {
     pub version: u8,
     pub uuid: u128,
-    add_info_length: u8,
-    pub add_info: (),
+    pub addons: Addons,
     pub command: Command,
     pub port: u16,
-    pub addr_type: AddrType,
-    pub addr: (),
+    pub addr: Address,
 }
 
 impl VlessRequestHeader {
-    pub fn from_raw(raw: &[u8]) -> Result<Self> {
-        let add_info_length = raw[16];
-        let offset = add_info_length as usize;
-
-        Ok(Self {
-            version: raw[0],
-            uuid: u128::from_be_bytes(raw[1..16].try_into()?),
-            add_info_length,
-            add_info: (),
-            command: Command::try_from(raw[17 + offset])?,
-            port: u16::from_be_bytes(raw[(18 + offset)..(20 + offset)].try_into()?),
-            addr_type: AddrType::try_from(raw[20 + offset])?,
-            addr: (),
-        })
+    /// Parse request header from the beginning of the buffer.
+    ///
+    /// Returns the header and number of bytes consumed,
+    /// the rest of the buffer is the request payload
+    pub fn from_raw(raw: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader::new(raw);
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(VlessError::UnsupportedVersion(version).into());
+        }
+
+        let uuid = u128::from_be_bytes(reader.take_array()?);
+
+        let addons_length = reader.u8()?;
+        let addons = Addons::from_raw(reader.take(addons_length as usize)?)?;
+
+        let command = Command::try_from(reader.u8()?)?;
+        let (port, addr) = if command == Command::Mux {
+            (0, Address::Domain(String::from(MUX_COOL_DOMAIN)))
+        } else {
+            let port = reader.u16()?;
+            let addr_type = AddrType::try_from(reader.u8()?)?;
+            (port, Address::read(&mut reader, addr_type)?)
+        };
+
+        Ok((
+            Self {
+                version,
+                uuid,
+                addons,
+                command,
+                port,
+                addr,
+            },
+            reader.offset(),
+        ))
+    }
+
+    pub fn new(uuid: u128, addons: Addons, command: Command, port: u16, addr: Address) -> Self {
+        Self {
+            version: VERSION,
+            uuid,
+            addons,
+            command,
+            port,
+            addr,
+        }
+    }
+
+    pub fn to_raw(&self) -> Result<Box<[u8]>> {
+        let destination = if self.command == Command::Mux {
+            Box::from([])
+        } else {
+            concat_dyn!(self.port.to_be_bytes(), self.addr.to_raw()?)
+        };
+
+        Ok(concat_dyn!(
+            [self.version],
+            self.uuid.to_be_bytes(),
+            addons_to_raw(&self.addons)?,
+            [u8::from(self.command)],
+            destination,
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VlessResponseHeader {
+    pub version: u8,
+    pub addons: Addons,
+}
+
+impl VlessResponseHeader {
+    pub fn new(addons: Addons) -> Self {
+        Self {
+            version: VERSION,
+            addons,
+        }
+    }
+
+    /// Parse response header from the beginning of the buffer.
+    ///
+    /// Returns the header and number of bytes consumed
+    pub fn from_raw(raw: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader::new(raw);
+
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(VlessError::UnsupportedVersion(version).into());
+        }
+
+        let addons_length = reader.u8()?;
+        let addons = Addons::from_raw(reader.take(addons_length as usize)?)?;
+
+        Ok((Self { version, addons }, reader.offset()))
+    }
+
+    pub fn to_raw(&self) -> Result<Box<[u8]>> {
+        Ok(concat_dyn!([self.version], addons_to_raw(&self.addons)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    const UUID: [u8; 16] = [
+        0x27, 0x84, 0x8a, 0x7b, 0x5e, 0x0d, 0x4b, 0x5a, 0x9a, 0x5c, 0x0f, 0x1e, 0x3d, 0x2b, 0x4c,
+        0x6d,
+    ];
+
+    fn request(addons: &[u8], command: u8, addr: &[u8]) -> Vec<u8> {
+        let mut res = vec![VERSION];
+        res.extend(UUID);
+        res.push(addons.len() as u8);
+        res.extend(addons);
+        res.push(command);
+        res.extend(443u16.to_be_bytes());
+        res.extend(addr);
+        res
+    }
+
+    #[test]
+    fn test_request_ipv4() {
+        let mut raw = request(&[], 0x01, &[0x01, 127, 0, 0, 1]);
+        raw.extend(b"payload");
+
+        let (header, size) = VlessRequestHeader::from_raw(&raw).unwrap();
+
+        assert_eq!(header.uuid, u128::from_be_bytes(UUID));
+        assert_eq!(header.command, Command::TCP);
+        assert_eq!(header.port, 443);
+        assert_eq!(header.addr, Address::Ipv4(Ipv4Addr::LOCALHOST));
+        assert_eq!(&raw[size..], b"payload");
+    }
+
+    #[test]
+    fn test_request_domain() {
+        let raw = request(&[], 0x02, b"\x02\x0bexample.com");
+
+        let (header, size) = VlessRequestHeader::from_raw(&raw).unwrap();
+
+        assert_eq!(header.command, Command::UDP);
+        assert_eq!(header.addr, Address::Domain(String::from("example.com")));
+        assert_eq!(size, raw.len());
+    }
+
+    #[test]
+    fn test_request_ipv6() {
+        let mut addr = vec![0x03];
+        addr.extend(Ipv6Addr::LOCALHOST.octets());
+        let raw = request(&[], 0x01, &addr);
+
+        let (header, _) = VlessRequestHeader::from_raw(&raw).unwrap();
+
+        assert_eq!(header.addr, Address::Ipv6(Ipv6Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn test_request_addons() {
+        let mut addons = vec![0x0a, 16];
+        addons.extend(b"xtls-rprx-vision");
+        addons.extend([0x12, 3, 1, 2, 3]);
+        let raw = request(&addons, 0x01, &[0x01, 10, 0, 0, 1]);
+
+        let (header, _) = VlessRequestHeader::from_raw(&raw).unwrap();
+
+        assert_eq!(header.addons.flow.as_deref(), Some("xtls-rprx-vision"));
+        assert_eq!(header.addons.seed.as_deref(), Some([1, 2, 3].as_slice()));
+    }
+
+    #[test]
+    fn test_request_round_trip() {
+        let addons = Addons {
+            flow: Some(String::from("xtls-rprx-vision")),
+            seed: None,
+        };
+        let addrs = [
+            Address::Ipv4(Ipv4Addr::new(1, 1, 1, 1)),
+            Address::Domain(String::from("example.com")),
+            Address::Ipv6(Ipv6Addr::LOCALHOST),
+        ];
+
+        for addr in addrs {
+            let header = VlessRequestHeader::new(
+                u128::from_be_bytes(UUID),
+                addons.clone(),
+                Command::TCP,
+                8080,
+                addr,
+            );
+            let raw = header.to_raw().unwrap();
+
+            let (parsed, size) = VlessRequestHeader::from_raw(&raw).unwrap();
+
+            assert_eq!(parsed, header);
+            assert_eq!(size, raw.len());
+        }
+    }
+
+    #[test]
+    fn test_request_encode() {
+        let header = VlessRequestHeader::new(
+            u128::from_be_bytes(UUID),
+            Addons::default(),
+            Command::UDP,
+            443,
+            Address::Domain(String::from("example.com")),
+        );
+
+        assert_eq!(
+            *header.to_raw().unwrap(),
+            *request(&[], 0x02, b"\x02\x0bexample.com")
+        );
+    }
+
+    #[test]
+    fn test_request_mux() {
+        let mut raw = vec![VERSION];
+        raw.extend(UUID);
+        raw.extend([0, 0x03]);
+
+        let (header, size) = VlessRequestHeader::from_raw(&raw).unwrap();
+
+        assert_eq!(header.command, Command::Mux);
+        assert_eq!(header.addr, Address::Domain(String::from(MUX_COOL_DOMAIN)));
+        assert_eq!(size, raw.len());
+        assert_eq!(*header.to_raw().unwrap(), *raw);
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let header = VlessResponseHeader::new(Addons::default());
+        let raw = header.to_raw().unwrap();
+        assert_eq!(*raw, [VERSION, 0]);
+
+        let header = VlessResponseHeader::new(Addons {
+            flow: None,
+            seed: Some(Box::new([0xAA; 4])),
+        });
+        let mut raw = header.to_raw().unwrap().into_vec();
+        raw.extend(b"data");
+
+        let (parsed, size) = VlessResponseHeader::from_raw(&raw).unwrap();
+
+        assert_eq!(parsed, header);
+        assert_eq!(&raw[size..], b"data");
+    }
+
+    #[test]
+    fn test_request_truncated() {
+        let raw = request(&[], 0x01, b"\x02\x0bexample.com");
+
+        for i in 0..raw.len() {
+            let err = VlessRequestHeader::from_raw(&raw[..i]).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<VlessError>(),
+                Some(VlessError::Truncated)
+            ));
+        }
     }
 }
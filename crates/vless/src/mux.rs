@@ -0,0 +1,334 @@
+use anyhow::{Result, anyhow, bail};
+use utils::concat_dyn;
+
+use crate::{AddrType, Address, error::VlessError, reader::Reader};
+
+pub mod options {
+    pub const DATA: u8 = 0x01;
+    pub const ERROR: u8 = 0x02;
+}
+
+// u8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionStatus {
+    New,
+    Keep,
+    End,
+    KeepAlive,
+}
+
+impl TryFrom<u8> for SessionStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Self::New),
+            0x02 => Ok(Self::Keep),
+            0x03 => Ok(Self::End),
+            0x04 => Ok(Self::KeepAlive),
+            _ => Err(anyhow!("Unknown `SessionStatus` value: 0x{value:02X}")),
+        }
+    }
+}
+
+impl From<SessionStatus> for u8 {
+    fn from(value: SessionStatus) -> Self {
+        match value {
+            SessionStatus::New => 0x01,
+            SessionStatus::Keep => 0x02,
+            SessionStatus::End => 0x03,
+            SessionStatus::KeepAlive => 0x04,
+        }
+    }
+}
+
+// u8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Network {
+    TCP,
+    UDP,
+}
+
+impl TryFrom<u8> for Network {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(Self::TCP),
+            0x02 => Ok(Self::UDP),
+            _ => Err(anyhow!("Unknown `Network` value: 0x{value:02X}")),
+        }
+    }
+}
+
+impl From<Network> for u8 {
+    fn from(value: Network) -> Self {
+        match value {
+            Network::TCP => 0x01,
+            Network::UDP => 0x02,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub network: Network,
+    pub port: u16,
+    pub addr: Address,
+}
+
+impl Target {
+    fn read(reader: &mut Reader) -> Result<Self> {
+        let network = Network::try_from(reader.u8()?)?;
+        let port = reader.u16()?;
+        let addr_type = AddrType::try_from(reader.u8()?)?;
+        let addr = Address::read(reader, addr_type)?;
+
+        Ok(Self {
+            network,
+            port,
+            addr,
+        })
+    }
+
+    fn to_raw(&self) -> Result<Box<[u8]>> {
+        Ok(concat_dyn!(
+            [u8::from(self.network)],
+            self.port.to_be_bytes(),
+            self.addr.to_raw()?,
+        ))
+    }
+}
+
+/// Single Mux.Cool frame: metadata followed by optional data.
+///
+/// <https://xtls.github.io/en/development/protocols/muxcool.html>
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub session_id: u16,
+    pub status: SessionStatus,
+    pub option: u8,
+    /// Present in `New` frames and in `Keep` frames of UDP sessions
+    pub target: Option<Target>,
+    /// XUDP global ID of `New` UDP sessions
+    pub global_id: Option<[u8; 8]>,
+    pub data: Option<Box<[u8]>>,
+}
+
+impl Frame {
+    fn new(
+        session_id: u16,
+        status: SessionStatus,
+        target: Option<Target>,
+        data: Option<&[u8]>,
+    ) -> Self {
+        Self {
+            session_id,
+            status,
+            option: if data.is_some() { options::DATA } else { 0 },
+            target,
+            global_id: None,
+            data: data.map(Box::from),
+        }
+    }
+
+    pub fn new_session(session_id: u16, target: Target, data: Option<&[u8]>) -> Self {
+        Self::new(session_id, SessionStatus::New, Some(target), data)
+    }
+
+    pub fn keep(session_id: u16, data: &[u8]) -> Self {
+        Self::new(session_id, SessionStatus::Keep, None, Some(data))
+    }
+
+    /// `Keep` frame of a UDP session carrying the datagram source.
+    pub fn keep_udp(session_id: u16, target: Target, data: &[u8]) -> Self {
+        Self::new(session_id, SessionStatus::Keep, Some(target), Some(data))
+    }
+
+    pub fn end(session_id: u16, error: bool) -> Self {
+        let mut res = Self::new(session_id, SessionStatus::End, None, None);
+        if error {
+            res.option |= options::ERROR;
+        }
+        res
+    }
+
+    pub fn keep_alive() -> Self {
+        Self::new(0, SessionStatus::KeepAlive, None, None)
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.option & options::ERROR != 0
+    }
+
+    /// Parse frame from the beginning of the buffer.
+    ///
+    /// Returns the frame and number of bytes consumed
+    pub fn from_raw(raw: &[u8]) -> Result<(Self, usize)> {
+        let mut reader = Reader::new(raw);
+
+        let metadata_length = reader.u16()?;
+        let mut metadata = Reader::new(reader.take(metadata_length as usize)?);
+
+        let session_id = metadata.u16()?;
+        let status = SessionStatus::try_from(metadata.u8()?)?;
+        let option = metadata.u8()?;
+
+        let mut target = None;
+        let mut global_id = None;
+        match status {
+            SessionStatus::New => {
+                let new_target = Target::read(&mut metadata)?;
+                if new_target.network == Network::UDP && metadata.remaining() >= 8 {
+                    global_id = Some(metadata.take_array()?);
+                }
+                target = Some(new_target);
+            }
+            SessionStatus::Keep if metadata.remaining() > 0 => {
+                target = Some(Target::read(&mut metadata)?);
+            }
+            _ => {}
+        }
+
+        let data = if option & options::DATA != 0 {
+            let length = reader.u16()?;
+            Some(Box::from(reader.take(length as usize)?))
+        } else {
+            None
+        };
+
+        Ok((
+            Self {
+                session_id,
+                status,
+                option,
+                target,
+                global_id,
+                data,
+            },
+            reader.offset(),
+        ))
+    }
+
+    pub fn to_raw(&self) -> Result<Box<[u8]>> {
+        let target = match &self.target {
+            Some(target) => target.to_raw()?,
+            None if self.status == SessionStatus::New => bail!("New frame without target"),
+            None => Box::from([]),
+        };
+        let global_id = self.global_id.map(Box::<[u8]>::from).unwrap_or_default();
+
+        let metadata = concat_dyn!(
+            self.session_id.to_be_bytes(),
+            [u8::from(self.status), self.option],
+            target,
+            global_id,
+        );
+        let metadata_length = u16::try_from(metadata.len())?;
+
+        let data = match &self.data {
+            Some(data) => {
+                let length = u16::try_from(data.len())
+                    .map_err(|_| anyhow!("Frame data is too long: {} bytes", data.len()))?;
+                concat_dyn!(length.to_be_bytes(), data)
+            }
+            None => Box::from([]),
+        };
+
+        Ok(concat_dyn!(metadata_length.to_be_bytes(), metadata, data))
+    }
+}
+
+/// Reassembles frames from an arbitrarily chunked stream.
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+    }
+
+    /// Take the next complete frame, if one has been fully received.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>> {
+        match Frame::from_raw(&self.buf) {
+            Ok((frame, size)) => {
+                self.buf.drain(..size);
+                Ok(Some(frame))
+            }
+            Err(e) if matches!(e.downcast_ref(), Some(VlessError::Truncated)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_frame_new() {
+        // Session 1, New, with data, TCP to example.com:443, data "hi"
+        let raw = [
+            0x00, 0x14, 0x00, 0x01, 0x01, 0x01, 0x01, 0x01, 0xbb, 0x02, 0x0b, b'e', b'x', b'a',
+            b'm', b'p', b'l', b'e', b'.', b'c', b'o', b'm', 0x00, 0x02, b'h', b'i',
+        ];
+
+        let (frame, size) = Frame::from_raw(&raw).unwrap();
+
+        assert_eq!(size, raw.len());
+        assert_eq!(frame.session_id, 1);
+        assert_eq!(frame.status, SessionStatus::New);
+        assert_eq!(
+            frame.target,
+            Some(Target {
+                network: Network::TCP,
+                port: 443,
+                addr: Address::Domain(String::from("example.com")),
+            })
+        );
+        assert_eq!(frame.data.as_deref(), Some(b"hi".as_slice()));
+        assert_eq!(*frame.to_raw().unwrap(), raw);
+    }
+
+    #[test]
+    fn test_frame_round_trip() {
+        let target = Target {
+            network: Network::UDP,
+            port: 53,
+            addr: Address::Ipv4(Ipv4Addr::new(8, 8, 8, 8)),
+        };
+        let frames = [
+            Frame::new_session(7, target.clone(), None),
+            Frame::keep(7, b"query"),
+            Frame::keep_udp(7, target, b"answer"),
+            Frame::end(7, true),
+            Frame::keep_alive(),
+        ];
+
+        let stream = frames
+            .iter()
+            .map(|f| f.to_raw().unwrap())
+            .collect::<Vec<_>>()
+            .concat();
+
+        let mut decoder = FrameDecoder::new();
+        let mut decoded = Vec::new();
+        for chunk in stream.chunks(5) {
+            decoder.push(chunk);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                decoded.push(frame);
+            }
+        }
+
+        assert_eq!(decoded, frames);
+        assert!(decoded[3].has_error());
+    }
+}
@@ -0,0 +1,70 @@
+//! Protobuf wire format primitives, shared by addons and gRPC transport messages.
+//!
+//! <https://protobuf.dev/programming-guides/encoding/>
+
+use anyhow::{Result, anyhow, bail};
+
+pub mod wire_types {
+    pub const VARINT: u8 = 0;
+    pub const FIXED_64: u8 = 1;
+    pub const LENGTH_DELIMITED: u8 = 2;
+    pub const FIXED_32: u8 = 5;
+}
+
+/// Read a base 128 varint, advancing `data` past it.
+pub fn read_varint(data: &mut &[u8]) -> Result<u64> {
+    let mut res = 0u64;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first().ok_or(anyhow!("Truncated varint"))?;
+        *data = rest;
+        res |= u64::from(byte & 0x7F) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(res);
+        }
+    }
+
+    bail!("Varint is too long")
+}
+
+pub fn write_varint(res: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        #[allow(clippy::cast_possible_truncation)]
+        res.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    res.push(value as u8);
+}
+
+/// Key and length followed by `data`.
+pub fn write_length_delimited(res: &mut Vec<u8>, field_number: u64, data: &[u8]) {
+    write_varint(
+        res,
+        field_number << 3 | u64::from(wire_types::LENGTH_DELIMITED),
+    );
+    write_varint(res, data.len() as u64);
+    res.extend(data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_varint() -> Result<()> {
+        for value in [0, 1, 0x7F, 0x80, 300, u64::MAX] {
+            let mut raw = Vec::new();
+            write_varint(&mut raw, value);
+            raw.push(0xAA);
+
+            let mut data = &raw[..];
+            assert_eq!(read_varint(&mut data)?, value);
+            assert_eq!(data, [0xAA]);
+        }
+        assert!(read_varint(&mut &[0x80][..]).is_err());
+        assert!(read_varint(&mut &[0xFF; 11][..]).is_err());
+        Ok(())
+    }
+}
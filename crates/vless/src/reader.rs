@@ -0,0 +1,57 @@
+use crate::{error::VlessError, protobuf};
+
+/// Bounds-checked cursor over a raw buffer.
+pub(crate) struct Reader<'a> {
+    raw: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(raw: &'a [u8]) -> Self {
+        Self { raw, offset: 0 }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.raw.len().saturating_sub(self.offset)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset >= self.raw.len()
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], VlessError> {
+        let end = self.offset.checked_add(n).ok_or(VlessError::Truncated)?;
+        let data = self
+            .raw
+            .get(self.offset..end)
+            .ok_or(VlessError::Truncated)?;
+        self.offset = end;
+        Ok(data)
+    }
+
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], VlessError> {
+        let mut res = [0; N];
+        res.copy_from_slice(self.take(N)?);
+        Ok(res)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, VlessError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, VlessError> {
+        Ok(u16::from_be_bytes(self.take_array()?))
+    }
+
+    /// Protobuf varint.
+    pub fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut rest = self.raw.get(self.offset..).unwrap_or_default();
+        let value = protobuf::read_varint(&mut rest)?;
+        self.offset = self.raw.len() - rest.len();
+        Ok(value)
+    }
+}
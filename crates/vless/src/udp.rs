@@ -0,0 +1,73 @@
+use anyhow::{Result, anyhow};
+use utils::concat_dyn;
+
+/// Frame a datagram for the UDP command stream (2-byte big-endian length prefix).
+pub fn encode_packet(payload: &[u8]) -> Result<Box<[u8]>> {
+    let length = u16::try_from(payload.len())
+        .map_err(|_| anyhow!("Packet is too long: {} bytes", payload.len()))?;
+    Ok(concat_dyn!(length.to_be_bytes(), payload))
+}
+
+/// Reassembles length-prefixed datagrams from an arbitrarily chunked stream.
+#[derive(Clone, Debug, Default)]
+pub struct PacketDecoder {
+    buf: Vec<u8>,
+}
+
+impl PacketDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend(data);
+    }
+
+    /// Take the next complete packet, if one has been fully received.
+    pub fn next_packet(&mut self) -> Option<Box<[u8]>> {
+        let header = self.buf.get(..2)?;
+        let length = u16::from_be_bytes([header[0], header[1]]) as usize;
+        let packet = Box::from(self.buf.get(2..(2 + length))?);
+        self.buf.drain(..(2 + length));
+        Some(packet)
+    }
+
+    /// Number of buffered bytes not yet returned as packets.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_round_trip() {
+        let stream = concat_dyn!(
+            encode_packet(b"first").unwrap(),
+            encode_packet(b"").unwrap(),
+            encode_packet(&[0xAB; 300]).unwrap(),
+        );
+
+        let mut decoder = PacketDecoder::new();
+        let mut packets = Vec::new();
+        for chunk in stream.chunks(7) {
+            decoder.push(chunk);
+            while let Some(packet) = decoder.next_packet() {
+                packets.push(packet);
+            }
+        }
+
+        assert_eq!(packets.len(), 3);
+        assert_eq!(*packets[0], *b"first");
+        assert!(packets[1].is_empty());
+        assert_eq!(*packets[2], [0xAB; 300]);
+        assert_eq!(decoder.pending(), 0);
+    }
+
+    #[test]
+    fn test_packet_too_long() {
+        assert!(encode_packet(&[0; 0x1_0000]).is_err());
+    }
+}
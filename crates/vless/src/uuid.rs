@@ -0,0 +1,38 @@
+use anyhow::{Result, anyhow};
+
+/// Parse UUID in its canonical textual form (`xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`).
+pub fn parse_uuid(value: &str) -> Result<u128> {
+    let digits = value.replace('-', "");
+    if digits.len() != 32 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid UUID: {value:?}"));
+    }
+
+    Ok(u128::from_str_radix(&digits, 16)?)
+}
+
+pub fn format_uuid(value: u128) -> String {
+    let hex = format!("{value:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uuid() {
+        let text = "27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d";
+        let value = parse_uuid(text).unwrap();
+
+        assert_eq!(value, 0x2784_8a7b_5e0d_4b5a_9a5c_0f1e_3d2b_4c6d);
+        assert_eq!(format_uuid(value), text);
+        assert!(parse_uuid("27848a7b-5e0d-4b5a-9a5c").is_err());
+    }
+}
@@ -0,0 +1,423 @@
+//! XTLS Vision flow.
+//!
+//! The first records of a connection are wrapped into padded blocks to hide
+//! the lengths of the inner TLS handshake. Once the inner TLS 1.3 connection
+//! starts exchanging application data, both sides stop padding and, if safe,
+//! switch to copying inner records directly over the raw connection.
+//!
+//! <https://github.com/XTLS/Xray-core/discussions/1295>
+
+use anyhow::{Result, bail};
+use utils::concat_dyn;
+
+pub const FLOW: &str = "xtls-rprx-vision";
+
+pub mod commands {
+    /// More padded blocks follow
+    pub const CONTINUE: u8 = 0x00;
+    /// Last padded block, the rest of the stream is unpadded
+    pub const END: u8 = 0x01;
+    /// Last padded block, the rest of the stream bypasses the outer TLS layer
+    pub const DIRECT: u8 = 0x02;
+}
+
+/// Size of the buffers padded blocks are built in.
+const BUFFER_SIZE: usize = 8192;
+/// UUID, command, content length and padding length.
+const MAX_BLOCK_HEADER_SIZE: usize = 16 + 5;
+/// Maximum amount of data carried by a single padded block.
+pub const MAX_CONTENT_SIZE: usize = BUFFER_SIZE - MAX_BLOCK_HEADER_SIZE;
+
+/// Number of records inspected before giving up on finding an inner TLS handshake.
+const PACKETS_TO_FILTER: u32 = 8;
+
+const TLS_CLIENT_HANDSHAKE_START: [u8; 2] = [0x16, 0x03];
+const TLS_SERVER_HANDSHAKE_START: [u8; 3] = [0x16, 0x03, 0x03];
+const TLS_APPLICATION_DATA_START: [u8; 3] = [0x17, 0x03, 0x03];
+const TLS_HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 0x01;
+const TLS_HANDSHAKE_TYPE_SERVER_HELLO: u8 = 0x02;
+/// `supported_versions` extension selecting TLS 1.3
+const TLS13_SUPPORTED_VERSIONS: [u8; 6] = [0x00, 0x2b, 0x00, 0x02, 0x03, 0x04];
+/// Direct copy leaks record lengths for this suite, whose tags are shorter
+const TLS_AES_128_CCM_8_SHA256: u16 = 0x1305;
+
+/// What is known about the inner connection, shared by both directions.
+#[derive(Clone, Debug)]
+pub struct TrafficState {
+    packets_to_filter: u32,
+    remaining_server_hello: usize,
+
+    pub is_tls: bool,
+    pub is_tls12_or_above: bool,
+    /// Inner connection is TLS 1.3 and may be copied directly
+    pub enable_xtls: bool,
+    pub cipher: u16,
+}
+
+impl Default for TrafficState {
+    fn default() -> Self {
+        Self {
+            packets_to_filter: PACKETS_TO_FILTER,
+            remaining_server_hello: 0,
+            is_tls: false,
+            is_tls12_or_above: false,
+            enable_xtls: false,
+            cipher: 0,
+        }
+    }
+}
+
+impl TrafficState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_filtering(&self) -> bool {
+        self.packets_to_filter > 0
+    }
+
+    /// Look for inner ClientHello/ServerHello in unpadded data.
+    pub fn filter_tls(&mut self, data: &[u8]) {
+        if !self.is_filtering() {
+            return;
+        }
+        self.packets_to_filter -= 1;
+
+        if data.len() >= 6 {
+            if data[..3] == TLS_SERVER_HANDSHAKE_START && data[5] == TLS_HANDSHAKE_TYPE_SERVER_HELLO
+            {
+                self.remaining_server_hello = u16::from_be_bytes([data[3], data[4]]) as usize + 5;
+                self.is_tls12_or_above = true;
+                self.is_tls = true;
+
+                // Record header, handshake header, version and random precede the session ID
+                if data.len() >= 79 && self.remaining_server_hello >= 79 {
+                    let session_id_length = data[43] as usize;
+                    if let Some(cipher) =
+                        data.get((44 + session_id_length)..(46 + session_id_length))
+                    {
+                        self.cipher = u16::from_be_bytes([cipher[0], cipher[1]]);
+                    }
+                }
+            } else if data[..2] == TLS_CLIENT_HANDSHAKE_START
+                && data[5] == TLS_HANDSHAKE_TYPE_CLIENT_HELLO
+            {
+                self.is_tls = true;
+            }
+        }
+
+        if self.remaining_server_hello > 0 {
+            let end = self.remaining_server_hello.min(data.len());
+            self.remaining_server_hello -= end;
+
+            if data[..end]
+                .windows(TLS13_SUPPORTED_VERSIONS.len())
+                .any(|w| w == TLS13_SUPPORTED_VERSIONS)
+            {
+                self.enable_xtls = (0x1301..=0x1305).contains(&self.cipher)
+                    && self.cipher != TLS_AES_128_CCM_8_SHA256;
+                self.packets_to_filter = 0;
+            } else if self.remaining_server_hello == 0 {
+                self.packets_to_filter = 0;
+            }
+        }
+    }
+}
+
+/// Random padding length for a block carrying `content_length` bytes.
+///
+/// Long padding hides the lengths of inner handshake messages
+pub fn padding_length(content_length: usize, long_padding: bool) -> usize {
+    let length = if content_length < 900 && long_padding {
+        rand::random_range(0..500) + 900 - content_length
+    } else {
+        rand::random_range(0..256)
+    };
+    length.min(MAX_CONTENT_SIZE.saturating_sub(content_length))
+}
+
+/// Encode a padded block, the user UUID is only prepended to the first block.
+pub fn encode_block(
+    uuid: Option<u128>,
+    command: u8,
+    content: &[u8],
+    padding_length: usize,
+) -> Result<Box<[u8]>> {
+    if content.len() + padding_length > MAX_CONTENT_SIZE {
+        bail!(
+            "Padded block is too long: {} bytes",
+            content.len() + padding_length
+        );
+    }
+
+    let uuid = uuid
+        .map(|x| Box::<[u8]>::from(x.to_be_bytes()))
+        .unwrap_or_default();
+
+    Ok(concat_dyn!(
+        uuid,
+        [command],
+        (content.len() as u16).to_be_bytes(),
+        (padding_length as u16).to_be_bytes(),
+        content,
+        vec![0; padding_length],
+    ))
+}
+
+/// Outgoing direction of a Vision connection.
+#[derive(Clone, Debug)]
+pub struct Padder {
+    /// Taken by the first block
+    uuid: Option<u128>,
+    is_padding: bool,
+}
+
+impl Padder {
+    pub fn new(uuid: u128) -> Self {
+        Self {
+            uuid: Some(uuid),
+            is_padding: true,
+        }
+    }
+
+    /// Wrap a chunk of at most [`MAX_CONTENT_SIZE`] bytes for sending.
+    ///
+    /// Returns the bytes to send and whether the rest of the stream
+    /// should be written directly to the raw connection
+    pub fn pad(&mut self, data: &[u8], state: &mut TrafficState) -> Result<(Box<[u8]>, bool)> {
+        state.filter_tls(data);
+
+        if !self.is_padding {
+            return Ok((Box::from(data), false));
+        }
+
+        let (command, long_padding) = if state.is_tls
+            && data.starts_with(&TLS_APPLICATION_DATA_START)
+            && is_complete_record(data)
+        {
+            // Inner handshake is done, application data needs no padding
+            self.is_padding = false;
+            let command = if state.enable_xtls {
+                commands::DIRECT
+            } else {
+                commands::END
+            };
+            (command, false)
+        } else if !state.is_tls12_or_above && state.packets_to_filter <= 1 {
+            // Not TLS 1.2+, padding would never end otherwise
+            self.is_padding = false;
+            (commands::END, state.is_tls)
+        } else {
+            (commands::CONTINUE, state.is_tls)
+        };
+
+        let block = encode_block(
+            self.uuid.take(),
+            command,
+            data,
+            padding_length(data.len(), long_padding),
+        )?;
+        Ok((block, command == commands::DIRECT))
+    }
+}
+
+/// Whether the data consists of whole TLS application data records.
+fn is_complete_record(mut data: &[u8]) -> bool {
+    while !data.is_empty() {
+        let Some(header) = data.get(..5) else {
+            return false;
+        };
+        if header[..3] != TLS_APPLICATION_DATA_START {
+            return false;
+        }
+        let length = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let Some(rest) = data.get((5 + length)..) else {
+            return false;
+        };
+        data = rest;
+    }
+    true
+}
+
+/// Incoming direction of a Vision connection.
+#[derive(Clone, Debug)]
+pub struct Unpadder {
+    uuid: [u8; 16],
+
+    within_padding: bool,
+    /// Inside a sequence of blocks started by the UUID
+    in_blocks: bool,
+    header: Vec<u8>,
+    command: u8,
+    remaining_content: usize,
+    remaining_padding: usize,
+}
+
+impl Unpadder {
+    pub fn new(uuid: u128) -> Self {
+        Self {
+            uuid: uuid.to_be_bytes(),
+            within_padding: true,
+            in_blocks: false,
+            header: Vec::with_capacity(5),
+            command: commands::CONTINUE,
+            remaining_content: 0,
+            remaining_padding: 0,
+        }
+    }
+
+    /// Strip padding from received data.
+    ///
+    /// The first chunk has to contain at least the UUID and the first block header.
+    ///
+    /// Returns the content and whether the rest of the stream
+    /// should be read directly from the raw connection
+    pub fn unpad(&mut self, data: &[u8], state: &mut TrafficState) -> Result<(Box<[u8]>, bool)> {
+        if !self.within_padding && !state.is_filtering() {
+            return Ok((Box::from(data), false));
+        }
+
+        let content = self.strip(data);
+
+        let mut direct = false;
+        if self.in_blocks || self.command == commands::CONTINUE {
+            self.within_padding = true;
+        } else {
+            self.within_padding = false;
+            match self.command {
+                commands::END => {}
+                commands::DIRECT => direct = true,
+                command => bail!("Unknown Vision command: 0x{command:02X}"),
+            }
+        }
+
+        state.filter_tls(&content);
+
+        Ok((content, direct))
+    }
+
+    fn strip(&mut self, mut data: &[u8]) -> Box<[u8]> {
+        if !self.in_blocks {
+            if data.len() >= MAX_BLOCK_HEADER_SIZE && data[..16] == self.uuid {
+                data = &data[16..];
+                self.in_blocks = true;
+                self.header.clear();
+            } else {
+                return Box::from(data);
+            }
+        }
+
+        let mut res = Vec::with_capacity(data.len());
+        while !data.is_empty() {
+            if self.header.len() < 5 {
+                let n = (5 - self.header.len()).min(data.len());
+                self.header.extend(&data[..n]);
+                data = &data[n..];
+
+                if let [command, c0, c1, p0, p1] = self.header[..] {
+                    self.command = command;
+                    self.remaining_content = u16::from_be_bytes([c0, c1]) as usize;
+                    self.remaining_padding = u16::from_be_bytes([p0, p1]) as usize;
+                }
+            } else if self.remaining_content > 0 {
+                let n = self.remaining_content.min(data.len());
+                res.extend(&data[..n]);
+                data = &data[n..];
+                self.remaining_content -= n;
+            } else {
+                let n = self.remaining_padding.min(data.len());
+                data = &data[n..];
+                self.remaining_padding -= n;
+            }
+
+            if self.header.len() == 5 && self.remaining_content == 0 && self.remaining_padding == 0
+            {
+                if self.command == commands::CONTINUE {
+                    self.header.clear();
+                } else {
+                    // Anything after the last block is not padded
+                    self.in_blocks = false;
+                    res.extend(data);
+                    break;
+                }
+            }
+        }
+
+        res.into_boxed_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: u128 = 0x27848a7b_5e0d_4b5a_9a5c_0f1e3d2b4c6d;
+
+    #[test]
+    fn test_unpad_chunked() {
+        let stream = [
+            encode_block(Some(UUID), commands::CONTINUE, b"hello", 3).unwrap(),
+            encode_block(None, commands::END, b" world", 0).unwrap(),
+            Box::from(b"!".as_slice()),
+        ]
+        .concat();
+
+        let mut state = TrafficState::new();
+        let mut unpadder = Unpadder::new(UUID);
+        // The UUID and the first block header are expected to arrive together
+        let (head, tail) = stream.split_at(MAX_BLOCK_HEADER_SIZE);
+        let mut res = Vec::new();
+        for chunk in std::iter::once(head).chain(tail.chunks(4)) {
+            let (data, direct) = unpadder.unpad(chunk, &mut state).unwrap();
+            assert!(!direct);
+            res.extend(data);
+        }
+
+        assert_eq!(res, b"hello world!");
+    }
+
+    #[test]
+    fn test_unpad_without_uuid() {
+        let mut state = TrafficState::new();
+        let mut unpadder = Unpadder::new(UUID);
+
+        let (data, _) = unpadder.unpad(b"plain data", &mut state).unwrap();
+
+        assert_eq!(*data, *b"plain data");
+    }
+
+    #[test]
+    fn test_pad_switches_to_direct() {
+        let mut state = TrafficState::new();
+        let mut padder = Padder::new(UUID);
+        let mut unpadder = Unpadder::new(UUID);
+        let mut peer_state = TrafficState::new();
+
+        // ServerHello with TLS_AES_128_GCM_SHA256 and supported_versions 1.3
+        let mut server_hello = vec![
+            0x16, 0x03, 0x03, 0x00, 0x56, 0x02, 0x00, 0x00, 0x52, 0x03, 0x03,
+        ];
+        server_hello.extend([0; 32]);
+        server_hello.extend([0x00, 0x13, 0x01, 0x00, 0x00, 0x2e]);
+        server_hello.extend(TLS13_SUPPORTED_VERSIONS);
+        server_hello.resize(91, 0);
+        let application_data = [0x17, 0x03, 0x03, 0x00, 0x02, 0xAA, 0xBB];
+
+        let (first, direct) = padder.pad(&server_hello, &mut state).unwrap();
+        assert!(!direct);
+        assert!(state.enable_xtls);
+        assert_eq!(state.cipher, 0x1301);
+        assert!(first.len() >= 16 + 5 + 900);
+
+        let (second, direct) = padder.pad(&application_data, &mut state).unwrap();
+        assert!(direct);
+
+        let (data, direct) = unpadder.unpad(&first, &mut peer_state).unwrap();
+        assert_eq!(*data, *server_hello);
+        assert!(!direct);
+
+        let (data, direct) = unpadder.unpad(&second, &mut peer_state).unwrap();
+        assert_eq!(*data, application_data);
+        assert!(direct);
+    }
+}
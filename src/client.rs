@@ -0,0 +1,142 @@
+use std::{
+    io::Write,
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use vless::Command;
+
+use crate::{
+    http_proxy::{self, ProxyRequest},
+    inbound::relay,
+    outbound::{ResponseReader, VlessOutbound},
+    socks::{self, replies},
+    tls_client::CertificatePin,
+};
+
+const USAGE: &str = "Usage: rs-vless client <SOCKS5 listen address> <server address> <uuid> \
+                     [HTTP proxy listen address] (--pin <SHA-256 fingerprint> | --insecure)";
+
+pub struct ClientConfig {
+    /// Local SOCKS5 listener
+    pub listen: String,
+    /// Local HTTP proxy listener
+    pub http_listen: Option<String>,
+    pub server: VlessOutbound,
+}
+
+impl ClientConfig {
+    /// The server certificate fingerprint is required unless `--insecure` is given.
+    pub fn from_args(args: &[String]) -> Result<Self> {
+        let mut fingerprint = None;
+        let mut insecure = false;
+        let mut positional = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--pin" => fingerprint = Some(args.next().ok_or(anyhow!(USAGE))?.as_str()),
+                "--insecure" => insecure = true,
+                _ => positional.push(arg),
+            }
+        }
+        let pin = CertificatePin::new(fingerprint, insecure).context(USAGE)?;
+
+        let (listen, server, uuid, http_listen) = match positional[..] {
+            [listen, server, uuid] => (listen, server, uuid, None),
+            [listen, server, uuid, http_listen] => (listen, server, uuid, Some(http_listen)),
+            _ => bail!(USAGE),
+        };
+
+        Ok(Self {
+            listen: listen.clone(),
+            http_listen: http_listen.cloned(),
+            server: VlessOutbound::new(server, None, uuid, pin)?,
+        })
+    }
+}
+
+fn handle_socks(mut local: TcpStream, config: &ClientConfig) -> Result<()> {
+    let (addr, port) = socks::accept(&mut local)?;
+    tracing::debug!("SOCKS request to {addr}:{port}");
+
+    let stream = match config.server.open(Command::TCP, addr, port) {
+        Ok(stream) => stream,
+        Err(e) => {
+            socks::reply(&mut local, replies::GENERAL_FAILURE)?;
+            return Err(e);
+        }
+    };
+    socks::reply(&mut local, replies::SUCCEEDED)?;
+
+    // Server data goes to the local client and vice versa
+    let (reader, writer) = stream.split();
+    relay(ResponseReader::new(reader), writer, local)
+}
+
+fn handle_http(mut local: TcpStream, config: &ClientConfig) -> Result<()> {
+    let ProxyRequest {
+        addr,
+        port,
+        tunnel,
+        payload,
+    } = http_proxy::accept(&mut local)?;
+    tracing::debug!("HTTP proxy request to {addr}:{port}");
+
+    let mut stream = match config.server.open(Command::TCP, addr, port) {
+        Ok(stream) => stream,
+        Err(e) => {
+            local.write_all(http_proxy::BAD_GATEWAY)?;
+            return Err(e);
+        }
+    };
+    if tunnel {
+        local.write_all(http_proxy::ESTABLISHED)?;
+    }
+    if !payload.is_empty() {
+        stream.write_all(&payload)?;
+    }
+
+    let (reader, writer) = stream.split();
+    relay(ResponseReader::new(reader), writer, local)
+}
+
+fn serve(
+    listener: &TcpListener,
+    config: &Arc<ClientConfig>,
+    handle: fn(TcpStream, &ClientConfig) -> Result<()>,
+) {
+    for conn in listener.incoming().filter_map(Result::ok) {
+        let config = config.clone();
+        thread::spawn(move || {
+            _ = handle(conn, &config)
+                .inspect_err(|e| tracing::error!("Proxy connection handle error: {e:?}"));
+        });
+    }
+}
+
+/// Accept local proxy connections and tunnel them to the server.
+pub fn run(config: ClientConfig) -> Result<()> {
+    let socks_listener = TcpListener::bind(&config.listen)?;
+    tracing::info!("SOCKS5 proxy on {}", config.listen);
+
+    let http_listener = config
+        .http_listen
+        .as_ref()
+        .map(TcpListener::bind)
+        .transpose()?;
+    if let Some(addr) = &config.http_listen {
+        tracing::info!("HTTP proxy on {addr}");
+    }
+    tracing::info!("Forwarding to {}", config.server.server);
+
+    let config = Arc::new(config);
+    if let Some(listener) = http_listener {
+        let config = config.clone();
+        thread::spawn(move || serve(&listener, &config, handle_http));
+    }
+    serve(&socks_listener, &config, handle_socks);
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, de::DeserializeOwned};
+use tls::cipher_suite::CipherSuite;
+
+use crate::{
+    credentials,
+    dns::{DnsFile, Resolver},
+    fallback::{FallbackEntry, Fallbacks},
+    inbound::{self, Inbound},
+    routing::{OutboundEntry, Router, RuleEntry},
+    sniff::Sniffing,
+    stats::{Stats, StatsConfig},
+    transport::Transport,
+    users::{UserEntry, Users},
+};
+
+pub const CONFIG_FILE: &str = "config.toml";
+
+const USAGE: &str = "Usage: rs-vless [--config <path>]\n       rs-vless client ...";
+
+/// Separate settings files of older versions, now sections of the configuration file.
+const LEGACY_FILES: [&str; 7] = [
+    "transport.toml",
+    "users.toml",
+    "fallbacks.toml",
+    "sniffing.toml",
+    "routing.toml",
+    "dns.toml",
+    "stats.toml",
+];
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TlsEntry {
+    /// DER certificate
+    certificate: String,
+    /// DER PKCS#8 RSA key
+    key: String,
+    /// IANA names in order of preference, all supported suites if unset
+    cipher_suites: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct InboundEntry {
+    tag: Option<String>,
+    /// `"host:port"`
+    listen: String,
+    tls: TlsEntry,
+    #[serde(default)]
+    transport: Transport,
+    #[serde(default)]
+    sniffing: Sniffing,
+    #[serde(default, rename = "user")]
+    users: Vec<UserEntry>,
+    #[serde(default, rename = "fallback")]
+    fallbacks: Vec<FallbackEntry>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct RoutingEntry {
+    /// Outbound used when no rule matches
+    default: Option<String>,
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleEntry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(rename = "inbound")]
+    inbounds: Vec<InboundEntry>,
+    #[serde(default, rename = "outbound")]
+    outbounds: Vec<OutboundEntry>,
+    #[serde(default)]
+    routing: RoutingEntry,
+    #[serde(default)]
+    dns: DnsFile,
+    #[serde(default)]
+    stats: StatsConfig,
+}
+
+/// Everything needed to start the server.
+pub struct Config {
+    /// Listen address and settings of each inbound
+    pub inbounds: Vec<(String, Inbound)>,
+    pub stats: StatsConfig,
+    /// Files the configuration was read from, certificates and keys included
+    pub files: Vec<PathBuf>,
+}
+
+fn path_error<E: Display>(e: serde_path_to_error::Error<E>) -> anyhow::Error {
+    match e.path().to_string().as_str() {
+        "." => anyhow!("{}", e.inner()),
+        path => anyhow!("{path}: {}", e.inner()),
+    }
+}
+
+/// Deserialize TOML or JSON, errors name the path of the offending field.
+fn deserialize<T: DeserializeOwned>(raw: &str, json: bool) -> Result<T> {
+    if json {
+        let mut deserializer = serde_json::Deserializer::from_str(raw);
+        let value = serde_path_to_error::deserialize(&mut deserializer).map_err(path_error)?;
+        deserializer.end()?;
+        Ok(value)
+    } else {
+        let deserializer = toml::Deserializer::parse(raw)?;
+        serde_path_to_error::deserialize(deserializer).map_err(path_error)
+    }
+}
+
+fn cipher_suites(names: &[String]) -> Result<Vec<CipherSuite>> {
+    if names.is_empty() {
+        bail!("At least one cipher suite is required");
+    }
+    names
+        .iter()
+        .map(|x| CipherSuite::from_name(x).ok_or(anyhow!("Unsupported cipher suite {x}")))
+        .collect()
+}
+
+impl Config {
+    /// Path given with `--config`, if any.
+    pub fn path_from_args(args: &[String]) -> Result<Option<String>> {
+        match args {
+            [] => Ok(None),
+            [flag, path] if flag == "--config" || flag == "-c" => Ok(Some(path.clone())),
+            _ => bail!(USAGE),
+        }
+    }
+
+    /// Load the configuration file, `.json` files are JSON and anything else TOML.
+    ///
+    /// Without a path `config.toml` is used
+    pub fn load(path: Option<&str>, stats: &Arc<Stats>) -> Result<Self> {
+        let path = path.unwrap_or(CONFIG_FILE);
+        let raw = match fs::read_to_string(path) {
+            Err(e) if e.kind() == ErrorKind::NotFound && path == CONFIG_FILE => {
+                let legacy = LEGACY_FILES
+                    .into_iter()
+                    .filter(|x| Path::new(x).exists())
+                    .collect::<Vec<_>>();
+                if !legacy.is_empty() {
+                    bail!(
+                        "{CONFIG_FILE} not found, settings in {} are no longer read, \
+                         move them into {CONFIG_FILE}",
+                        legacy.join(", ")
+                    );
+                }
+                Err(e)
+            }
+            raw => raw,
+        };
+        let raw = raw.with_context(|| format!("Failed to read {path}"))?;
+        let json = Path::new(path).extension().is_some_and(|x| x == "json");
+
+        let mut config = Self::parse(&raw, json, stats)
+            .with_context(|| format!("Invalid configuration {path}"))?;
+        config.files.push(PathBuf::from(path));
+        Ok(config)
+    }
+
+    fn parse(raw: &str, json: bool, stats: &Arc<Stats>) -> Result<Self> {
+        let file: ConfigFile = deserialize(raw, json)?;
+        if file.inbounds.is_empty() {
+            bail!("inbound: At least one inbound is required");
+        }
+
+        let router = Arc::new(
+            Router::new(file.outbounds, file.routing.rules, file.routing.default)
+                .context("routing")?,
+        );
+        let resolver = Arc::new(Resolver::from_settings(file.dns).context("dns")?);
+
+        let mut inbounds = Vec::<(String, Inbound)>::new();
+        let mut files = Vec::new();
+        for (i, entry) in file.inbounds.into_iter().enumerate() {
+            let tag = entry
+                .tag
+                .unwrap_or_else(|| String::from(inbound::DEFAULT_TAG));
+            if inbounds.iter().any(|(_, x)| x.tag == tag) {
+                bail!("inbound[{i}].tag: Duplicate inbound tag {tag}");
+            }
+
+            let mut tls = credentials::load(&entry.tls.certificate, &entry.tls.key)
+                .with_context(|| format!("inbound[{i}].tls"))?;
+            if let Some(names) = &entry.tls.cipher_suites {
+                tls.cipher_suites = cipher_suites(names)
+                    .with_context(|| format!("inbound[{i}].tls.cipher_suites"))?;
+            }
+
+            let inbound = Inbound {
+                tag,
+                tls,
+                transport: entry.transport,
+                users: Users::from_entries(entry.users).with_context(|| format!("inbound[{i}]"))?,
+                fallbacks: Fallbacks::from_entries(entry.fallbacks),
+                sniffing: entry.sniffing,
+                router: router.clone(),
+                resolver: resolver.clone(),
+                stats: stats.clone(),
+            };
+            inbounds.push((entry.listen, inbound));
+            files.extend([entry.tls.certificate, entry.tls.key].map(PathBuf::from));
+        }
+
+        Ok(Self {
+            inbounds,
+            stats: file.stats,
+            files,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(raw: &str, json: bool) -> String {
+        let stats = Arc::new(Stats::default());
+        format!("{:#}", Config::parse(raw, json, &stats).err().unwrap())
+    }
+
+    #[test]
+    fn test_errors() {
+        let toml = r#"
+            [[inbound]]
+            listen = "127.0.0.1:443"
+            tls = { certificate = "cert.cer", key = "key.der" }
+
+            [[inbound.user]]
+            uuid = "27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d"
+            email = "alice@example.com"
+            quota = "lots"
+        "#;
+        let e = error(toml, false);
+        assert!(e.starts_with("inbound[0].user[0].quota: TOML parse error at line 9"));
+        assert!(e.contains("invalid type"));
+
+        let json = r#"{
+            "inbound": [{
+                "listen": "127.0.0.1:443",
+                "tls": { "certificate": "cert.cer", "key": "key.der" },
+                "transport": { "type": "ws", "path": "/ws", "port": 80 }
+            }]
+        }"#;
+        assert!(error(json, true).starts_with("inbound[0].transport: unknown field `port`"));
+
+        let routing = r#"
+            inbound = []
+
+            [[routing.rule]]
+            outbound = "missing"
+        "#;
+        assert_eq!(
+            error(routing, false),
+            "inbound: At least one inbound is required"
+        );
+        let routing = routing.replace("inbound = []", "");
+        assert!(error(&routing, false).contains("missing field `inbound`"));
+
+        let inbound = r#"
+            [[inbound]]
+            listen = "127.0.0.1:443"
+            tls = { certificate = "/nonexistent.cer", key = "key.der" }
+        "#;
+        assert!(
+            error(inbound, false).starts_with("inbound[0].tls: Failed to read /nonexistent.cer")
+        );
+        assert_eq!(
+            error(&format!("{inbound}\n{routing}"), false),
+            "routing: rule[0].outbound: Unknown outbound missing"
+        );
+        assert_eq!(
+            error(
+                &format!("{inbound}\n[routing]\ndefault = \"missing\""),
+                false
+            ),
+            "routing: default: Unknown outbound missing"
+        );
+    }
+
+    #[test]
+    fn test_cipher_suites() -> Result<()> {
+        let names = ["TLS_CHACHA20_POLY1305_SHA256", "TLS_AES_128_GCM_SHA256"].map(String::from);
+        assert_eq!(
+            cipher_suites(&names)?,
+            [
+                tls::cipher_suite::TLS_CHACHA20_POLY1305_SHA256,
+                tls::cipher_suite::TLS_AES_128_GCM_SHA256
+            ]
+        );
+        let e = cipher_suites(&[String::from("TLS_AES_128_CCM_SHA256")]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Unsupported cipher suite TLS_AES_128_CCM_SHA256"
+        );
+        assert!(cipher_suites(&[]).is_err());
+        Ok(())
+    }
+}
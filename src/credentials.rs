@@ -0,0 +1,93 @@
+use std::{fs, panic};
+
+use anyhow::{Context, Result, anyhow, bail};
+use asn1::{
+    DataElement, X509CertificateV3,
+    object_identifiers::{rsassaPss, sha256WithRSAEncryption},
+    parse_der,
+};
+use crypt::rsa::{PrivateKey, PublicKey};
+use tls::{
+    cipher_suite::SUPPORTED_CIPHER_SUITES, record::handshake::extension::SignatureScheme,
+    server::ServerConfig,
+};
+
+use crate::ALPN_PROTOCOLS;
+
+/// Run the DER parser, which panics on malformed input.
+fn parse<T>(f: impl FnOnce() -> T + panic::UnwindSafe) -> Result<T> {
+    panic::catch_unwind(f).map_err(|_| anyhow!("Malformed DER"))
+}
+
+fn parse_rsa_key(encoded: &[u8]) -> Result<PrivateKey> {
+    // PKCS#8 wrapping a PKCS#1 RSAPrivateKey
+    if let DataElement::Sequence(seq) = parse(|| parse_der(encoded))?
+        && let Some(DataElement::OctetString(octets)) = seq.get(2)
+        && let DataElement::Sequence(numbers) = parse(|| parse_der(octets))?
+        && let Some(DataElement::Integer(modulus)) = numbers.get(1)
+        && let Some(DataElement::Integer(private_exponent)) = numbers.get(3)
+    {
+        Ok(PrivateKey {
+            modulus: modulus.0.clone(),
+            exponent: private_exponent.0.clone(),
+        })
+    } else {
+        bail!("Not a PKCS#8 RSA private key")
+    }
+}
+
+/// RSA key of a DER certificate.
+pub fn public_key(certificate: &[u8]) -> Result<PublicKey> {
+    // TBSCertificate.subjectPublicKeyInfo wrapping a PKCS#1 RSAPublicKey
+    if let DataElement::Sequence(cert) = parse(|| parse_der(certificate))?
+        && let Some(DataElement::Sequence(tbs)) = cert.first()
+        && let Some(DataElement::Sequence(info)) = tbs.get(6)
+        && let Some(DataElement::BitString(bits)) = info.get(1)
+        && let Some(encoded) = bits.get(1..)
+        && let DataElement::Sequence(numbers) = parse(|| parse_der(encoded))?
+        && let Some(DataElement::Integer(modulus)) = numbers.first()
+        && let Some(DataElement::Integer(exponent)) = numbers.get(1)
+    {
+        Ok(PublicKey {
+            modulus: modulus.0.clone(),
+            exponent: exponent.0.clone(),
+        })
+    } else {
+        bail!("Not an RSA certificate")
+    }
+}
+
+/// Load a DER certificate and a DER PKCS#8 RSA key.
+pub fn load(certificate_path: &str, key_path: &str) -> Result<ServerConfig> {
+    let certificate =
+        fs::read(certificate_path).with_context(|| format!("Failed to read {certificate_path}"))?;
+    let cert = parse(|| X509CertificateV3::from_data_element(&parse_der(&certificate)))
+        .with_context(|| format!("Invalid certificate {certificate_path}"))?;
+
+    let signature_schemes = if cert.signature_algorithm.is(sha256WithRSAEncryption) {
+        vec![
+            SignatureScheme::rsa_pss_rsae_sha256,
+            SignatureScheme::rsa_pss_rsae_sha384,
+            SignatureScheme::rsa_pss_rsae_sha512,
+        ]
+    } else if cert.signature_algorithm.is(rsassaPss) {
+        vec![
+            SignatureScheme::rsa_pss_pss_sha256,
+            SignatureScheme::rsa_pss_pss_sha384,
+            SignatureScheme::rsa_pss_pss_sha512,
+        ]
+    } else {
+        bail!("Unsupported certificate signature algorithm in {certificate_path}");
+    };
+
+    let key = fs::read(key_path).with_context(|| format!("Failed to read {key_path}"))?;
+    let private_key = parse_rsa_key(&key).with_context(|| format!("Invalid key {key_path}"))?;
+
+    Ok(ServerConfig {
+        certificate: certificate.into_boxed_slice(),
+        signature_schemes,
+        private_key,
+        alpn_protocols: ALPN_PROTOCOLS.map(String::from).into(),
+        cipher_suites: SUPPORTED_CIPHER_SUITES.to_vec(),
+    })
+}
@@ -0,0 +1,473 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use vless::Address;
+
+use wire::{Response, rcodes, record_types};
+
+mod wire;
+
+/// Used when no upstream servers are configured.
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+/// Static addresses of the system, configured hosts take precedence.
+const HOSTS_FILE: &str = "/etc/hosts";
+
+const DNS_PORT: u16 = 53;
+
+/// How long to wait for each upstream server.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long names without addresses are remembered.
+const NEGATIVE_TTL: Duration = Duration::from_secs(30);
+
+const MAX_CACHE_ENTRIES: usize = 4096;
+
+/// Largest response accepted over UDP.
+const MAX_UDP_SIZE: usize = 4096;
+
+/// Which address families to look up and which one to try first.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    #[default]
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl Strategy {
+    /// Record types in order of preference.
+    fn record_types(self) -> &'static [u16] {
+        match self {
+            Self::PreferIpv4 => &[record_types::A, record_types::AAAA],
+            Self::PreferIpv6 => &[record_types::AAAA, record_types::A],
+            Self::Ipv4Only => &[record_types::A],
+            Self::Ipv6Only => &[record_types::AAAA],
+        }
+    }
+
+    /// Drop excluded families and put the preferred one first.
+    fn order(self, addrs: &[IpAddr]) -> Vec<IpAddr> {
+        let mut res = addrs
+            .iter()
+            .copied()
+            .filter(|x| match self {
+                Self::Ipv4Only => x.is_ipv4(),
+                Self::Ipv6Only => x.is_ipv6(),
+                _ => true,
+            })
+            .collect::<Vec<_>>();
+        res.sort_by_key(|x| x.is_ipv4() == (self == Self::PreferIpv6));
+        res
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Upstream {
+    Udp(SocketAddr),
+    Tcp(SocketAddr),
+}
+
+impl Upstream {
+    /// `"[udp://|tcp://]ip[:port]"`
+    fn parse(value: &str) -> Result<Self> {
+        let (tcp, addr) = match value.split_once("://") {
+            Some(("udp", addr)) => (false, addr),
+            Some(("tcp", addr)) => (true, addr),
+            Some((scheme, _)) => bail!("Unsupported DNS server scheme `{scheme}`"),
+            None => (false, value),
+        };
+        let addr = match addr.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => SocketAddr::new(addr.trim_matches(['[', ']']).parse()?, DNS_PORT),
+        };
+        Ok(if tcp {
+            Self::Tcp(addr)
+        } else {
+            Self::Udp(addr)
+        })
+    }
+
+    fn exchange(self, query: &[u8]) -> Result<Box<[u8]>> {
+        match self {
+            Self::Udp(addr) => {
+                let socket = UdpSocket::bind(match addr {
+                    SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                })?;
+                socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+                socket.connect(addr)?;
+                socket.send(query)?;
+
+                // Ignore stray datagrams, the ID is checked by the caller
+                let deadline = Instant::now() + QUERY_TIMEOUT;
+                let mut buf = [0; MAX_UDP_SIZE];
+                loop {
+                    let n = socket.recv(&mut buf)?;
+                    if buf.get(..2) == query.get(..2) || Instant::now() >= deadline {
+                        return Ok(Box::from(&buf[..n]));
+                    }
+                }
+            }
+            Self::Tcp(addr) => {
+                let mut stream = TcpStream::connect_timeout(&addr, QUERY_TIMEOUT)?;
+                stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+                let length = u16::try_from(query.len())?;
+                stream.write_all(&[&length.to_be_bytes()[..], query].concat())?;
+
+                let mut length = [0; 2];
+                stream.read_exact(&mut length)?;
+                let mut res = vec![0; usize::from(u16::from_be_bytes(length))];
+                stream.read_exact(&mut res)?;
+                Ok(res.into_boxed_slice())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum HostsEntry {
+    One(IpAddr),
+    Many(Vec<IpAddr>),
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct DnsFile {
+    #[serde(default)]
+    servers: Vec<String>,
+    #[serde(default)]
+    strategy: Strategy,
+    /// Static addresses, looked up before any server is asked
+    #[serde(default)]
+    hosts: HashMap<String, HostsEntry>,
+}
+
+struct CacheEntry {
+    addrs: Box<[IpAddr]>,
+    expires: Instant,
+}
+
+/// Caching stub resolver asking the configured servers in order.
+pub struct Resolver {
+    servers: Vec<Upstream>,
+    strategy: Strategy,
+    hosts: HashMap<String, Box<[IpAddr]>>,
+    cache: Mutex<HashMap<(String, u16), CacheEntry>>,
+}
+
+/// Normalized form of a domain name.
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// Nameservers of the system configuration.
+fn system_servers() -> Vec<Upstream> {
+    let raw = fs::read_to_string(RESOLV_CONF).unwrap_or_default();
+    raw.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|ip| Upstream::Udp(SocketAddr::new(ip, DNS_PORT)))
+        .collect()
+}
+
+/// Entries of the system hosts file.
+fn system_hosts() -> HashMap<String, Vec<IpAddr>> {
+    let raw = fs::read_to_string(HOSTS_FILE).unwrap_or_default();
+    let mut res = HashMap::<_, Vec<_>>::new();
+    for line in raw.lines() {
+        let mut fields = line
+            .split('#')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace();
+        let Some(Ok(ip)) = fields.next().map(str::parse::<IpAddr>) else {
+            continue;
+        };
+        for name in fields {
+            res.entry(normalize(name)).or_default().push(ip);
+        }
+    }
+    res
+}
+
+impl Resolver {
+    fn new(
+        servers: Vec<Upstream>,
+        strategy: Strategy,
+        hosts: HashMap<String, Box<[IpAddr]>>,
+    ) -> Self {
+        Self {
+            servers,
+            strategy,
+            hosts,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_settings(file: DnsFile) -> Result<Self> {
+        let mut servers = file
+            .servers
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                Upstream::parse(x).with_context(|| format!("servers[{i}]: Invalid DNS server {x}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if servers.is_empty() {
+            servers = system_servers();
+        }
+        let mut hosts = system_hosts();
+        for (name, entry) in file.hosts {
+            let addrs = match entry {
+                HostsEntry::One(addr) => Vec::from([addr]),
+                HostsEntry::Many(addrs) => addrs,
+            };
+            hosts.insert(normalize(&name), addrs);
+        }
+        let hosts = hosts
+            .into_iter()
+            .map(|(name, addrs)| (name, addrs.into_boxed_slice()))
+            .collect();
+
+        Ok(Self::new(servers, file.strategy, hosts))
+    }
+
+    pub fn len(&self) -> usize {
+        self.servers.len()
+    }
+
+    /// Addresses of a name, preferred family first.
+    pub fn lookup(&self, name: &str) -> io::Result<Vec<IpAddr>> {
+        if let Ok(ip) = name.trim_matches(['[', ']']).parse::<IpAddr>() {
+            return Ok(Vec::from([ip]));
+        }
+        let name = normalize(name);
+
+        if let Some(addrs) = self.hosts.get(&name) {
+            return Ok(self.strategy.order(addrs));
+        }
+
+        // Other families are only asked for when the preferred one has nothing
+        for &record_type in self.strategy.record_types() {
+            let addrs = self.lookup_type(&name, record_type).map_err(|e| {
+                io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("Failed to resolve {name}: {e}"),
+                )
+            })?;
+            if !addrs.is_empty() {
+                return Ok(addrs.into_vec());
+            }
+        }
+        Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("No addresses for {name}"),
+        ))
+    }
+
+    /// Socket addresses of a destination, preferred family first.
+    pub fn resolve(&self, addr: &Address, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let ips = match addr {
+            Address::Ipv4(ip) => Vec::from([IpAddr::V4(*ip)]),
+            Address::Ipv6(ip) => Vec::from([IpAddr::V6(*ip)]),
+            Address::Domain(domain) => self.lookup(domain)?,
+        };
+        Ok(ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect())
+    }
+
+    fn cache(&self) -> Result<MutexGuard<'_, HashMap<(String, u16), CacheEntry>>> {
+        self.cache.lock().map_err(|_| anyhow!("Poisoned lock"))
+    }
+
+    fn lookup_type(&self, name: &str, record_type: u16) -> Result<Box<[IpAddr]>> {
+        let key = (String::from(name), record_type);
+        if let Some(entry) = self.cache()?.get(&key)
+            && entry.expires > Instant::now()
+        {
+            return Ok(entry.addrs.clone());
+        }
+
+        let (addrs, ttl) = self.query(name, record_type)?;
+        tracing::debug!("Resolved {name} type {record_type}: {addrs:?}, TTL {ttl:?}");
+
+        let mut cache = self.cache()?;
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            let now = Instant::now();
+            cache.retain(|_, x| x.expires > now);
+        }
+        if cache.len() < MAX_CACHE_ENTRIES {
+            cache.insert(
+                key,
+                CacheEntry {
+                    addrs: addrs.clone(),
+                    expires: Instant::now() + ttl,
+                },
+            );
+        }
+        Ok(addrs)
+    }
+
+    /// Ask the servers in order until one answers.
+    ///
+    /// Returns the addresses and how long they can be cached
+    fn query(&self, name: &str, record_type: u16) -> Result<(Box<[IpAddr]>, Duration)> {
+        let mut last_error = anyhow!("No DNS servers configured");
+
+        for &server in &self.servers {
+            let id = rand::random();
+            let query = wire::encode_query(id, name, record_type)?;
+
+            let res = server.exchange(&query).and_then(|raw| {
+                let response = wire::parse_response(&raw)?;
+                // Truncated UDP answers are asked again over TCP
+                match server {
+                    Upstream::Udp(addr) if response.truncated => {
+                        wire::parse_response(&Upstream::Tcp(addr).exchange(&query)?)
+                    }
+                    _ => Ok(response),
+                }
+            });
+            match res.and_then(|response| answer(&response, id, name, record_type)) {
+                Ok(answer) => return Ok(answer),
+                Err(e) => {
+                    tracing::debug!("DNS server {server:?} failed for {name}: {e}");
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+}
+
+/// Addresses and the smallest TTL of a response to our query.
+fn answer(
+    response: &Response,
+    id: u16,
+    name: &str,
+    record_type: u16,
+) -> Result<(Box<[IpAddr]>, Duration)> {
+    if response.id != id
+        || response
+            .question
+            .as_ref()
+            .is_some_and(|(x, y)| *x != name || *y != record_type)
+    {
+        bail!("Response does not match the query");
+    }
+
+    match response.rcode {
+        rcodes::NO_ERROR => {}
+        rcodes::NX_DOMAIN => return Ok((Box::new([]), NEGATIVE_TTL)),
+        rcode => bail!("Server returned error {rcode}"),
+    }
+
+    let records = response
+        .answers
+        .iter()
+        .filter(|x| x.record_type == record_type)
+        .collect::<Vec<_>>();
+    let ttl = records
+        .iter()
+        .map(|x| Duration::from_secs(x.ttl.into()))
+        .min()
+        .unwrap_or(NEGATIVE_TTL);
+
+    Ok((records.iter().filter_map(|x| x.addr()).collect(), ttl))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        thread,
+    };
+
+    use super::*;
+
+    /// Loopback server answering every A query with 10.0.0.1, AAAA queries with no records.
+    fn stand_in_server() -> (SocketAddr, Arc<AtomicUsize>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(AtomicUsize::new(0));
+
+        let counter = queries.clone();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf) {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let mut response = Vec::from(&buf[..n]);
+                response[2] |= 0x80;
+                if response[n - 3] == record_types::A as u8 {
+                    response[7] = 1;
+                    response.extend([0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+                }
+                _ = socket.send_to(&response, peer);
+            }
+        });
+
+        (addr, queries)
+    }
+
+    #[test]
+    fn test_resolver() {
+        let (addr, queries) = stand_in_server();
+        let hosts = HashMap::from([(
+            String::from("static.test"),
+            Box::from([
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ]),
+        )]);
+        let resolver = Resolver::new(
+            Vec::from([Upstream::Udp(addr)]),
+            Strategy::PreferIpv6,
+            hosts,
+        );
+
+        let expected = Vec::from([IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+        assert_eq!(resolver.lookup("Example.COM.").unwrap(), expected);
+        // AAAA first, then A
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+        assert_eq!(resolver.lookup("example.com").unwrap(), expected);
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+
+        assert_eq!(
+            resolver.lookup("static.test").unwrap(),
+            [
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))
+            ]
+        );
+        assert_eq!(
+            resolver
+                .resolve(&Address::Domain(String::from("::1")), 53)
+                .unwrap(),
+            [SocketAddr::from((Ipv6Addr::LOCALHOST, 53))]
+        );
+        assert_eq!(queries.load(Ordering::Relaxed), 2);
+
+        assert_eq!(
+            Upstream::parse("tcp://[::1]").unwrap(),
+            Upstream::Tcp(SocketAddr::from((Ipv6Addr::LOCALHOST, 53)))
+        );
+        assert!(Upstream::parse("https://1.1.1.1").is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use anyhow::{Result, anyhow, bail};
+
+/// Size of the fixed message header.
+const HEADER_SIZE: usize = 12;
+
+/// Compression pointers followed while reading a single name.
+const MAX_POINTERS: usize = 16;
+
+pub mod record_types {
+    pub const A: u16 = 1;
+    pub const AAAA: u16 = 28;
+}
+
+pub mod rcodes {
+    pub const NO_ERROR: u8 = 0;
+    pub const NX_DOMAIN: u8 = 3;
+}
+
+const CLASS_IN: u16 = 1;
+
+mod flags {
+    pub const RESPONSE: u16 = 0x8000;
+    pub const TRUNCATED: u16 = 0x0200;
+    pub const RECURSION_DESIRED: u16 = 0x0100;
+}
+
+/// Standard recursive query for a single name, RFC 1035 section 4.
+pub fn encode_query(id: u16, name: &str, record_type: u16) -> Result<Box<[u8]>> {
+    let mut res = Vec::with_capacity(HEADER_SIZE + name.len() + 6);
+    res.extend(id.to_be_bytes());
+    res.extend(flags::RECURSION_DESIRED.to_be_bytes());
+    // One question, no records
+    res.extend([0, 1, 0, 0, 0, 0, 0, 0]);
+
+    for label in name.trim_end_matches('.').split('.') {
+        let length = u8::try_from(label.len())
+            .ok()
+            .filter(|&x| (1..64).contains(&x))
+            .ok_or(anyhow!("Invalid label in {name}"))?;
+        res.push(length);
+        res.extend(label.as_bytes());
+    }
+    res.push(0);
+    if res.len() - HEADER_SIZE > 255 {
+        bail!("Name is too long: {name}");
+    }
+
+    res.extend(record_type.to_be_bytes());
+    res.extend(CLASS_IN.to_be_bytes());
+    Ok(res.into_boxed_slice())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub record_type: u16,
+    pub ttl: u32,
+    pub data: Box<[u8]>,
+}
+
+impl Record {
+    /// Address of an A or AAAA record.
+    pub fn addr(&self) -> Option<IpAddr> {
+        match self.record_type {
+            record_types::A => <[u8; 4]>::try_from(&*self.data)
+                .ok()
+                .map(|x| IpAddr::V4(Ipv4Addr::from(x))),
+            record_types::AAAA => <[u8; 16]>::try_from(&*self.data)
+                .ok()
+                .map(|x| IpAddr::V6(Ipv6Addr::from(x))),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Response {
+    pub id: u16,
+    pub rcode: u8,
+    pub truncated: bool,
+    /// Name and type of the question
+    pub question: Option<(String, u16)>,
+    pub answers: Vec<Record>,
+}
+
+fn read_u16(message: &[u8], pos: &mut usize) -> Result<u16> {
+    let raw = message
+        .get(*pos..(*pos + 2))
+        .ok_or(anyhow!("Truncated message"))?;
+    *pos += 2;
+    Ok(u16::from_be_bytes([raw[0], raw[1]]))
+}
+
+fn read_u32(message: &[u8], pos: &mut usize) -> Result<u32> {
+    Ok(u32::from(read_u16(message, pos)?) << 16 | u32::from(read_u16(message, pos)?))
+}
+
+/// Read a possibly compressed name, lowercased and without the trailing dot.
+fn read_name(message: &[u8], pos: &mut usize) -> Result<String> {
+    let mut labels = Vec::new();
+    let mut cursor = *pos;
+    let mut pointers = 0;
+
+    loop {
+        let length = *message.get(cursor).ok_or(anyhow!("Truncated name"))?;
+        match length {
+            0 => {
+                cursor += 1;
+                break;
+            }
+            0xC0.. => {
+                let offset = usize::from(read_u16(message, &mut cursor)? & 0x3FFF);
+                if pointers == 0 {
+                    *pos = cursor;
+                }
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    bail!("Too many compression pointers");
+                }
+                cursor = offset;
+            }
+            0x40.. => bail!("Unsupported label type 0x{length:02X}"),
+            _ => {
+                let start = cursor + 1;
+                let label = message
+                    .get(start..(start + usize::from(length)))
+                    .ok_or(anyhow!("Truncated label"))?;
+                labels.push(String::from_utf8_lossy(label).to_lowercase());
+                cursor = start + usize::from(length);
+            }
+        }
+    }
+
+    if pointers == 0 {
+        *pos = cursor;
+    }
+    Ok(labels.join("."))
+}
+
+fn read_record(message: &[u8], pos: &mut usize) -> Result<Record> {
+    let name = read_name(message, pos)?;
+    let record_type = read_u16(message, pos)?;
+    let _class = read_u16(message, pos)?;
+    let ttl = read_u32(message, pos)?;
+    let length = usize::from(read_u16(message, pos)?);
+    let data = message
+        .get(*pos..(*pos + length))
+        .ok_or(anyhow!("Truncated record data"))?;
+    *pos += length;
+
+    Ok(Record {
+        name,
+        record_type,
+        ttl,
+        data: Box::from(data),
+    })
+}
+
+/// Parse a response, only the answer section is kept.
+pub fn parse_response(message: &[u8]) -> Result<Response> {
+    let mut pos = 0;
+    let id = read_u16(message, &mut pos)?;
+    let flags = read_u16(message, &mut pos)?;
+    if flags & flags::RESPONSE == 0 {
+        bail!("Not a response");
+    }
+    let question_count = read_u16(message, &mut pos)?;
+    let answer_count = read_u16(message, &mut pos)?;
+    pos = HEADER_SIZE;
+
+    let mut question = None;
+    for _ in 0..question_count {
+        let name = read_name(message, &mut pos)?;
+        let record_type = read_u16(message, &mut pos)?;
+        let _class = read_u16(message, &mut pos)?;
+        question.get_or_insert((name, record_type));
+    }
+
+    let answers = (0..answer_count)
+        .map(|_| read_record(message, &mut pos))
+        .collect::<Result<_>>()?;
+
+    Ok(Response {
+        id,
+        #[allow(clippy::cast_possible_truncation)]
+        rcode: (flags & 0x000F) as u8,
+        truncated: flags & flags::TRUNCATED != 0,
+        question,
+        answers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message() {
+        let query = encode_query(0x1234, "Example.com.", record_types::A).unwrap();
+        assert_eq!(
+            *query,
+            [
+                0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0, 7, b'E', b'x', b'a', b'm', b'p',
+                b'l', b'e', 3, b'c', b'o', b'm', 0, 0, 1, 0, 1
+            ]
+        );
+        assert!(encode_query(1, "a..b", record_types::A).is_err());
+
+        // Response with a CNAME and an A record, names compressed
+        let mut response = Vec::from(&query[..]);
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        response.extend([
+            0xC0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 6, 3, b'w', b'w', b'w', 0xC0, 12,
+        ]);
+        response.extend([0xC0, 41, 0, 1, 0, 1, 0, 0, 1, 0, 0, 4, 10, 0, 0, 1]);
+
+        let parsed = parse_response(&response).unwrap();
+        assert_eq!(parsed.id, 0x1234);
+        assert_eq!(parsed.rcode, rcodes::NO_ERROR);
+        assert_eq!(
+            parsed.question,
+            Some((String::from("example.com"), record_types::A))
+        );
+        // CNAME
+        assert_eq!(parsed.answers[0].record_type, 5);
+        assert_eq!(parsed.answers[1].name, "www.example.com");
+        assert_eq!(parsed.answers[1].ttl, 256);
+        assert_eq!(
+            parsed.answers[1].addr(),
+            Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+
+        // Pointer loop
+        let mut looped = Vec::from(&response[..HEADER_SIZE]);
+        looped[7] = 0;
+        looped.extend([0xC0, 12]);
+        assert!(parse_response(&looped).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use std::{fmt::Display, io::Write, net::TcpStream, os::unix::net::UnixStream, path::PathBuf};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::{
+    inbound::relay,
+    stream::{HandshakeInfo, TlsStream},
+};
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum DestSpec {
+    /// Local port
+    Port(u16),
+    /// `"host:port"` or Unix socket path
+    Addr(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Dest {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl From<DestSpec> for Dest {
+    fn from(value: DestSpec) -> Self {
+        match value {
+            DestSpec::Port(port) => Self::Tcp(format!("127.0.0.1:{port}")),
+            DestSpec::Addr(addr) if addr.starts_with('/') => Self::Unix(PathBuf::from(addr)),
+            DestSpec::Addr(addr) => Self::Tcp(addr),
+        }
+    }
+}
+
+impl Display for Dest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FallbackEntry {
+    name: Option<String>,
+    alpn: Option<String>,
+    path: Option<String>,
+    dest: DestSpec,
+}
+
+/// Where to forward connections that are not valid VLESS requests.
+///
+/// Unset conditions match anything
+#[derive(Clone, Debug)]
+pub struct Fallback {
+    /// SNI, also matches subdomains
+    pub name: Option<String>,
+    /// Negotiated ALPN protocol
+    pub alpn: Option<String>,
+    /// HTTP/1 request path
+    pub path: Option<String>,
+    pub dest: Dest,
+}
+
+impl From<FallbackEntry> for Fallback {
+    fn from(value: FallbackEntry) -> Self {
+        Self {
+            name: value.name.map(|x| x.to_lowercase()),
+            alpn: value.alpn,
+            path: value.path,
+            dest: value.dest.into(),
+        }
+    }
+}
+
+impl Fallback {
+    fn matches(&self, info: &HandshakeInfo, path: Option<&str>) -> bool {
+        let name_matches = self.name.as_deref().is_none_or(|name| {
+            info.server_name.as_deref().is_some_and(|server_name| {
+                server_name == name
+                    || server_name
+                        .strip_suffix(name)
+                        .is_some_and(|x| x.ends_with('.'))
+            })
+        });
+
+        name_matches
+            && self
+                .alpn
+                .as_ref()
+                .is_none_or(|x| info.alpn.as_ref() == Some(x))
+            && self.path.as_deref().is_none_or(|x| path == Some(x))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Fallbacks {
+    fallbacks: Vec<Fallback>,
+}
+
+impl Fallbacks {
+    pub fn from_entries(entries: Vec<FallbackEntry>) -> Self {
+        Self {
+            fallbacks: entries.into_iter().map(Fallback::from).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.fallbacks.len()
+    }
+
+    /// Pick the most specific fallback for the connection.
+    ///
+    /// `data` is the beginning of what the client sent
+    pub fn select(&self, info: &HandshakeInfo, data: &[u8]) -> Option<&Fallback> {
+        let path = request_path(data);
+
+        self.fallbacks
+            .iter()
+            .rev()
+            .filter(|x| x.matches(info, path))
+            .max_by_key(|x| {
+                (
+                    x.name.as_ref().map_or(0, String::len),
+                    x.alpn.is_some(),
+                    x.path.is_some(),
+                )
+            })
+    }
+}
+
+/// Path of an HTTP/1 request, without the query.
+fn request_path(data: &[u8]) -> Option<&str> {
+    let line_end = data.iter().position(|&x| x == b'\r' || x == b'\n')?;
+    let line = str::from_utf8(&data[..line_end]).ok()?;
+
+    let mut parts = line.split(' ');
+    let (_method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+    if !version.starts_with("HTTP/") || !target.starts_with('/') {
+        return None;
+    }
+
+    target.split('?').next()
+}
+
+/// Forward the connection verbatim, starting with already received data.
+pub fn forward(stream: TlsStream, fallback: &Fallback, data: &[u8]) -> Result<()> {
+    tracing::debug!("Falling back to {}", fallback.dest);
+
+    let (reader, writer) = stream.split();
+    match &fallback.dest {
+        Dest::Tcp(addr) => {
+            let mut target = TcpStream::connect(addr.as_str())?;
+            target.write_all(data)?;
+            relay(reader, writer, target)
+        }
+        Dest::Unix(path) => {
+            let mut target = UnixStream::connect(path)?;
+            target.write_all(data)?;
+            relay(reader, writer, target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tls::cipher_suite::TLS_AES_256_GCM_SHA384;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct FallbacksFile {
+        #[serde(default, rename = "fallback")]
+        fallbacks: Vec<FallbackEntry>,
+    }
+
+    const FALLBACKS: &str = r#"
+        [[fallback]]
+        dest = 80
+
+        [[fallback]]
+        alpn = "h2"
+        dest = "/run/h2c.sock"
+
+        [[fallback]]
+        path = "/ws"
+        dest = "127.0.0.1:2001"
+
+        [[fallback]]
+        name = "example.com"
+        dest = 8080
+    "#;
+
+    fn select(server_name: Option<&str>, alpn: Option<&str>, data: &[u8]) -> Dest {
+        let file: FallbacksFile = toml::from_str(FALLBACKS).unwrap();
+        let fallbacks = Fallbacks {
+            fallbacks: file.fallbacks.into_iter().map(Fallback::from).collect(),
+        };
+        let info = HandshakeInfo {
+            server_name: server_name.map(String::from),
+            alpn: alpn.map(String::from),
+            cipher_suite: TLS_AES_256_GCM_SHA384,
+            group: None,
+        };
+
+        fallbacks.select(&info, data).unwrap().dest.clone()
+    }
+
+    #[test]
+    fn test_select() {
+        let get_ws = b"GET /ws?ed=2048 HTTP/1.1\r\nHost: example.org\r\n\r\n";
+
+        assert_eq!(
+            select(None, None, b"\x16\x03\x01"),
+            Dest::Tcp(String::from("127.0.0.1:80"))
+        );
+        assert_eq!(
+            select(None, Some("h2"), b"PRI * HTTP/2.0\r\n"),
+            Dest::Unix(PathBuf::from("/run/h2c.sock"))
+        );
+        assert_eq!(
+            select(None, None, get_ws),
+            Dest::Tcp(String::from("127.0.0.1:2001"))
+        );
+        assert_eq!(
+            select(Some("www.example.com"), None, get_ws),
+            Dest::Tcp(String::from("127.0.0.1:8080"))
+        );
+        assert_eq!(
+            select(Some("notexample.com"), None, b"GET / HTTP/1.1\r\n"),
+            Dest::Tcp(String::from("127.0.0.1:80"))
+        );
+    }
+}
@@ -0,0 +1,202 @@
+use std::io::{self, Read, Write};
+
+use anyhow::{Result, bail};
+use vless::protobuf::{read_varint, wire_types, write_length_delimited};
+
+use crate::{
+    h2::{self, Request, StreamReader, StreamWriter, error_codes},
+    inbound::{self, Inbound},
+    stream::{CloseWrite, HandshakeInfo, TlsStream},
+    transport::ClientStream,
+};
+
+pub const CONTENT_TYPE: &str = "application/grpc";
+
+/// Largest message accepted from the client.
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+/// Largest `Hunk` data sent to the client.
+const HUNK_DATA_SIZE: usize = 1 << 14;
+
+/// Length prefix of every message, compression flag and size.
+const MESSAGE_HEADER_SIZE: usize = 5;
+
+/// Field number of `Hunk.data`.
+const HUNK_DATA_FIELD: u64 = 1;
+
+/// `data` of a protobuf `Hunk`, unknown fields are skipped.
+fn decode_hunk(mut message: &[u8]) -> Result<Box<[u8]>> {
+    let mut res: &[u8] = &[];
+
+    while !message.is_empty() {
+        let key = read_varint(&mut message)?;
+        #[allow(clippy::cast_possible_truncation)]
+        let size = match (key & 0x07) as u8 {
+            wire_types::VARINT => {
+                read_varint(&mut message)?;
+                0
+            }
+            wire_types::FIXED_64 => 8,
+            wire_types::LENGTH_DELIMITED => usize::try_from(read_varint(&mut message)?)?,
+            wire_types::FIXED_32 => 4,
+            wire_type => bail!("Unsupported wire type {wire_type}"),
+        };
+        if message.len() < size {
+            bail!("Truncated field");
+        }
+        let (value, rest) = message.split_at(size);
+        message = rest;
+
+        if key == (HUNK_DATA_FIELD << 3 | u64::from(wire_types::LENGTH_DELIMITED)) {
+            res = value;
+        }
+    }
+
+    Ok(Box::from(res))
+}
+
+/// Length prefixed gRPC message holding a `Hunk`.
+#[allow(clippy::cast_possible_truncation)]
+fn encode_hunk(data: &[u8]) -> Box<[u8]> {
+    let mut hunk = Vec::with_capacity(data.len() + 4);
+    write_length_delimited(&mut hunk, HUNK_DATA_FIELD, data);
+
+    let mut res = Vec::with_capacity(MESSAGE_HEADER_SIZE + hunk.len());
+    res.push(0);
+    res.extend((hunk.len() as u32).to_be_bytes());
+    res.extend(hunk);
+
+    res.into_boxed_slice()
+}
+
+/// Data of the `Hunk` messages sent by the client.
+pub struct HunkReader {
+    inner: StreamReader,
+
+    buf: Box<[u8]>,
+    pos: usize,
+}
+
+impl Read for HunkReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() {
+            let mut header = [0; MESSAGE_HEADER_SIZE];
+            match self.inner.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(0),
+                Err(e) => return Err(e),
+            }
+            if header[0] != 0 {
+                return Err(io::Error::other("Compressed messages are not supported"));
+            }
+            let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+            if length > MAX_MESSAGE_SIZE {
+                return Err(io::Error::other(format!("Message is too large: {length}")));
+            }
+
+            let mut message = vec![0; length];
+            self.inner.read_exact(&mut message)?;
+            self.buf = decode_hunk(&message).map_err(io::Error::other)?;
+            self.pos = 0;
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Sends data to the client as `Hunk` messages.
+pub struct HunkWriter {
+    inner: StreamWriter,
+}
+
+impl Write for HunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(HUNK_DATA_SIZE);
+        self.inner.write_all(&encode_hunk(&buf[..n]))?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl CloseWrite for HunkWriter {
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.send_headers(&[("grpc-status", "0")], true)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.inner.reset(error_codes::CANCEL)
+    }
+}
+
+fn handle_stream(
+    request: &Request,
+    reader: StreamReader,
+    writer: StreamWriter,
+    service_name: &str,
+    info: &HandshakeInfo,
+    inbound: &Inbound,
+) -> Result<()> {
+    if request.method != "POST" || request.path != format!("/{service_name}/Tun") {
+        writer.send_headers(&[(":status", "404")], true)?;
+        bail!("Unexpected request {} {}", request.method, request.path);
+    }
+    if !request
+        .header("content-type")
+        .is_some_and(|x| x.starts_with(CONTENT_TYPE))
+    {
+        writer.send_headers(&[(":status", "415")], true)?;
+        bail!(
+            "Unexpected content type {:?}",
+            request.header("content-type")
+        );
+    }
+    writer.send_headers(&[(":status", "200"), ("content-type", CONTENT_TYPE)], false)?;
+
+    let stream = ClientStream::Wrapped {
+        reader: Box::new(HunkReader {
+            inner: reader,
+            buf: Box::new([]),
+            pos: 0,
+        }),
+        writer: Box::new(HunkWriter { inner: writer }),
+    };
+    inbound::handle(stream, info, inbound)
+}
+
+/// Handle every `Tun` stream of the connection as a VLESS connection.
+pub fn serve(
+    stream: TlsStream,
+    info: &HandshakeInfo,
+    service_name: &str,
+    inbound: &Inbound,
+) -> Result<()> {
+    h2::serve(stream, &|request, reader, writer| {
+        _ = handle_stream(&request, reader, writer, service_name, info, inbound)
+            .inspect_err(|e| tracing::error!("gRPC stream handle error: {e:?}"));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hunk() {
+        let data = [7; 300];
+        let raw = encode_hunk(&data);
+        assert_eq!(raw[..8], [0, 0, 0, 1, 47, 0x0A, 0xAC, 0x02]);
+        assert_eq!(*decode_hunk(&raw[MESSAGE_HEADER_SIZE..]).unwrap(), data);
+
+        // Unknown varint and fixed32 fields around the data
+        let message = [0x10, 0x96, 0x01, 0x0A, 0x02, 1, 2, 0x1D, 0, 0, 0, 0];
+        assert_eq!(*decode_hunk(&message).unwrap(), [1, 2]);
+        assert!(decode_hunk(&[0x0A, 0x05, 1]).is_err());
+    }
+}
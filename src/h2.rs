@@ -0,0 +1,845 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Read, Write},
+    sync::{
+        Arc, Condvar, Mutex, MutexGuard,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+};
+
+use anyhow::{Result, bail};
+
+use crate::stream::{TlsReader, TlsStream, TlsWriter};
+
+pub use hpack::Header;
+
+mod hpack;
+mod huffman;
+
+/// Sent by the client before any frames.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_SIZE: usize = 9;
+
+/// Largest frame payload accepted, the initial value we never raise.
+const MAX_FRAME_SIZE: usize = 1 << 14;
+
+const MAX_HEADER_BLOCK_SIZE: usize = 1 << 16;
+
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+
+/// Receive windows we advertise.
+const STREAM_WINDOW_SIZE: u32 = 1 << 20;
+const CONNECTION_WINDOW_SIZE: u32 = 1 << 24;
+
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+mod frame_types {
+    pub const DATA: u8 = 0x0;
+    pub const HEADERS: u8 = 0x1;
+    pub const RST_STREAM: u8 = 0x3;
+    pub const SETTINGS: u8 = 0x4;
+    pub const PUSH_PROMISE: u8 = 0x5;
+    pub const PING: u8 = 0x6;
+    pub const GOAWAY: u8 = 0x7;
+    pub const WINDOW_UPDATE: u8 = 0x8;
+    pub const CONTINUATION: u8 = 0x9;
+}
+
+mod flags {
+    pub const END_STREAM: u8 = 0x1;
+    pub const ACK: u8 = 0x1;
+    pub const END_HEADERS: u8 = 0x4;
+    pub const PADDED: u8 = 0x8;
+    pub const PRIORITY: u8 = 0x20;
+}
+
+mod settings {
+    pub const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+    pub const INITIAL_WINDOW_SIZE: u16 = 0x4;
+    pub const MAX_FRAME_SIZE: u16 = 0x5;
+}
+
+pub mod error_codes {
+    pub const PROTOCOL_ERROR: u32 = 0x1;
+    pub const FLOW_CONTROL_ERROR: u32 = 0x3;
+    pub const FRAME_SIZE_ERROR: u32 = 0x6;
+    pub const REFUSED_STREAM: u32 = 0x7;
+    pub const CANCEL: u32 = 0x8;
+    pub const COMPRESSION_ERROR: u32 = 0x9;
+}
+
+/// Connection error, answered with `GOAWAY`.
+#[derive(Debug)]
+pub struct H2Error {
+    pub code: u32,
+    pub reason: String,
+}
+
+impl H2Error {
+    fn new(code: u32, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for H2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP/2 error {}: {}", self.code, self.reason)
+    }
+}
+
+impl std::error::Error for H2Error {}
+
+struct Frame {
+    frame_type: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: Box<[u8]>,
+}
+
+impl Frame {
+    /// Payload without padding, and the priority fields of `HEADERS`.
+    fn data(&self) -> Result<&[u8]> {
+        let mut data = &*self.payload;
+        let mut padding = 0;
+
+        if self.flags & flags::PADDED != 0 {
+            let Some((&length, rest)) = data.split_first() else {
+                bail!(H2Error::new(error_codes::PROTOCOL_ERROR, "Missing padding"));
+            };
+            data = rest;
+            padding = length as usize;
+        }
+        if self.frame_type == frame_types::HEADERS && self.flags & flags::PRIORITY != 0 {
+            data = data.get(5..).unwrap_or_default();
+        }
+        if padding > data.len() {
+            bail!(H2Error::new(
+                error_codes::PROTOCOL_ERROR,
+                "Padding exceeds payload"
+            ));
+        }
+
+        Ok(&data[..(data.len() - padding)])
+    }
+}
+
+/// Read until the client preface is complete or the data can not be one.
+///
+/// Returns everything read, which is the preface only if it matched
+pub fn read_preface(stream: &mut impl Read) -> io::Result<Box<[u8]>> {
+    let mut buf = vec![0; PREFACE.len()];
+    let mut filled = 0;
+
+    while filled < buf.len() && buf[..filled] == PREFACE[..filled] {
+        let n = stream.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    buf.truncate(filled);
+
+    Ok(buf.into_boxed_slice())
+}
+
+/// Read a single frame, returns `None` on end of stream.
+fn read_frame(reader: &mut impl Read) -> Result<Option<Frame>> {
+    let mut header = [0; FRAME_HEADER_SIZE];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+    if length > MAX_FRAME_SIZE {
+        bail!(H2Error::new(
+            error_codes::FRAME_SIZE_ERROR,
+            format!("Frame is too large: {length}")
+        ));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(Frame {
+        frame_type: header[3],
+        flags: header[4],
+        stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF,
+        payload: payload.into_boxed_slice(),
+    }))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn encode_frame(frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Box<[u8]> {
+    let length = (payload.len() as u32).to_be_bytes();
+    let mut res = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    res.extend(&length[1..]);
+    res.extend([frame_type, flags]);
+    res.extend(stream_id.to_be_bytes());
+    res.extend(payload);
+
+    res.into_boxed_slice()
+}
+
+/// Request headers of a new stream.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<Header>,
+}
+
+impl Request {
+    fn from_headers(headers: Vec<Header>) -> Result<Self> {
+        let pseudo = |name| {
+            headers
+                .iter()
+                .find(|(x, _)| x == name)
+                .map(|(_, value)| value.clone())
+        };
+        let (Some(method), Some(path)) = (pseudo(":method"), pseudo(":path")) else {
+            bail!("Missing :method or :path");
+        };
+
+        Ok(Self {
+            method,
+            path,
+            headers,
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct StreamState {
+    send_window: i64,
+    /// Delivers `DATA` to the reader, dropped once the client ends the stream
+    sender: Option<Sender<Box<[u8]>>>,
+    /// We ended the stream
+    local_closed: bool,
+}
+
+struct ConnState {
+    writer: TlsWriter,
+    send_window: i64,
+    /// Client `SETTINGS_INITIAL_WINDOW_SIZE`
+    initial_window: i64,
+    /// Client `SETTINGS_MAX_FRAME_SIZE`
+    max_frame_size: usize,
+    streams: HashMap<u32, StreamState>,
+    closed: bool,
+}
+
+impl ConnState {
+    fn write_frame(
+        &mut self,
+        frame_type: u8,
+        flags: u8,
+        stream_id: u32,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        if self.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Connection is closed",
+            ));
+        }
+        self.writer
+            .write_all(&encode_frame(frame_type, flags, stream_id, payload))
+    }
+
+    fn window_update(&mut self, stream_id: u32, increment: usize) -> io::Result<()> {
+        if increment == 0 {
+            return Ok(());
+        }
+        #[allow(clippy::cast_possible_truncation)]
+        let increment = (increment as u32).to_be_bytes();
+        self.write_frame(frame_types::WINDOW_UPDATE, 0, stream_id, &increment)
+    }
+
+    fn reset(&mut self, stream_id: u32, error_code: u32) -> io::Result<()> {
+        self.streams.remove(&stream_id);
+        self.write_frame(
+            frame_types::RST_STREAM,
+            0,
+            stream_id,
+            &error_code.to_be_bytes(),
+        )
+    }
+
+    /// Forget streams both sides have ended.
+    fn remove_if_done(&mut self, stream_id: u32) {
+        if self
+            .streams
+            .get(&stream_id)
+            .is_some_and(|x| x.local_closed && x.sender.is_none())
+        {
+            self.streams.remove(&stream_id);
+        }
+    }
+}
+
+struct Shared {
+    state: Mutex<ConnState>,
+    /// Notified when send windows grow or streams go away
+    window: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> io::Result<MutexGuard<'_, ConnState>> {
+        self.state
+            .lock()
+            .map_err(|_| io::Error::other("Poisoned lock"))
+    }
+}
+
+/// Request body of a stream.
+pub struct StreamReader {
+    id: u32,
+    receiver: Receiver<Box<[u8]>>,
+    shared: Arc<Shared>,
+
+    buf: Box<[u8]>,
+    pos: usize,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            let Ok(data) = self.receiver.recv() else {
+                return Ok(0);
+            };
+            // The client may send more once the data is taken
+            let mut state = self.shared.lock()?;
+            if state.streams.contains_key(&self.id) {
+                state.window_update(self.id, data.len())?;
+            }
+            drop(state);
+
+            self.buf = data;
+            self.pos = 0;
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Response of a stream, the stream is reset if dropped before it is ended.
+pub struct StreamWriter {
+    id: u32,
+    shared: Arc<Shared>,
+}
+
+impl StreamWriter {
+    /// Send response headers or trailers.
+    pub fn send_headers(&self, headers: &[(&str, &str)], end_stream: bool) -> io::Result<()> {
+        let block = hpack::encode(headers);
+
+        let mut state = self.shared.lock()?;
+        if state.streams.get(&self.id).is_none_or(|x| x.local_closed) {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Stream is closed",
+            ));
+        }
+
+        let mut chunks = block.chunks(state.max_frame_size).peekable();
+        let mut frame_type = frame_types::HEADERS;
+        let mut frame_flags = if end_stream { flags::END_STREAM } else { 0 };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                frame_flags |= flags::END_HEADERS;
+            }
+            state.write_frame(frame_type, frame_flags, self.id, chunk)?;
+            frame_type = frame_types::CONTINUATION;
+            frame_flags = 0;
+        }
+
+        if end_stream && let Some(stream) = state.streams.get_mut(&self.id) {
+            stream.local_closed = true;
+            state.remove_if_done(self.id);
+        }
+        Ok(())
+    }
+
+    /// End the response body without trailers.
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.shared.lock()?;
+        let Some(stream) = state.streams.get_mut(&self.id) else {
+            return Ok(());
+        };
+        if stream.local_closed {
+            return Ok(());
+        }
+        stream.local_closed = true;
+
+        state.write_frame(frame_types::DATA, flags::END_STREAM, self.id, &[])?;
+        state.remove_if_done(self.id);
+        Ok(())
+    }
+
+    /// Abort the stream in both directions.
+    pub fn reset(&self, error_code: u32) -> io::Result<()> {
+        let mut state = self.shared.lock()?;
+        if state.streams.get(&self.id).is_none_or(|x| x.local_closed) {
+            return Ok(());
+        }
+        let res = state.reset(self.id, error_code);
+        self.shared.window.notify_all();
+        res
+    }
+}
+
+impl Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.shared.lock()?;
+
+        let n = loop {
+            let stream = match state.streams.get(&self.id) {
+                Some(stream) if !state.closed && !stream.local_closed => stream,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "Stream is closed",
+                    ));
+                }
+            };
+
+            let window = state.send_window.min(stream.send_window);
+            if window > 0 {
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                break buf.len().min(window as usize).min(state.max_frame_size);
+            }
+            state = self
+                .shared
+                .window
+                .wait(state)
+                .map_err(|_| io::Error::other("Poisoned lock"))?;
+        };
+
+        state.write_frame(frame_types::DATA, 0, self.id, &buf[..n])?;
+        state.send_window -= n as i64;
+        if let Some(stream) = state.streams.get_mut(&self.id) {
+            stream.send_window -= n as i64;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.shared.lock()?.writer.flush()
+    }
+}
+
+impl Drop for StreamWriter {
+    fn drop(&mut self) {
+        _ = self.reset(error_codes::CANCEL);
+    }
+}
+
+pub type Handler<'a> = dyn Fn(Request, StreamReader, StreamWriter) + Sync + 'a;
+
+struct Connection<'a> {
+    reader: TlsReader,
+    shared: Arc<Shared>,
+    decoder: hpack::Decoder,
+    last_stream_id: u32,
+    handler: &'a Handler<'a>,
+}
+
+impl<'a> Connection<'a> {
+    /// Whole header block of a `HEADERS` frame and its `CONTINUATION` frames.
+    fn read_header_block(&mut self, frame: &Frame) -> Result<Box<[u8]>> {
+        let mut block = Vec::from(frame.data()?);
+        let mut end_headers = frame.flags & flags::END_HEADERS != 0;
+
+        while !end_headers {
+            let Some(next) = read_frame(&mut self.reader)? else {
+                bail!("Connection closed inside header block");
+            };
+            if next.frame_type != frame_types::CONTINUATION || next.stream_id != frame.stream_id {
+                bail!(H2Error::new(
+                    error_codes::PROTOCOL_ERROR,
+                    "Expected CONTINUATION"
+                ));
+            }
+            block.extend(&next.payload);
+            if block.len() > MAX_HEADER_BLOCK_SIZE {
+                bail!(H2Error::new(
+                    error_codes::PROTOCOL_ERROR,
+                    "Header block is too large"
+                ));
+            }
+            end_headers = next.flags & flags::END_HEADERS != 0;
+        }
+
+        Ok(block.into_boxed_slice())
+    }
+
+    fn on_headers<'scope>(
+        &mut self,
+        frame: &Frame,
+        scope: &'scope thread::Scope<'scope, '_>,
+    ) -> Result<()>
+    where
+        'a: 'scope,
+    {
+        let id = frame.stream_id;
+        let end_stream = frame.flags & flags::END_STREAM != 0;
+
+        let block = self.read_header_block(frame)?;
+        // Decoded even for refused streams to keep the table in sync
+        let headers = self
+            .decoder
+            .decode(&block)
+            .map_err(|e| H2Error::new(error_codes::COMPRESSION_ERROR, e.to_string()))?;
+
+        let mut state = self.shared.lock()?;
+        if let Some(stream) = state.streams.get_mut(&id) {
+            // Trailers
+            if end_stream {
+                stream.sender = None;
+                state.remove_if_done(id);
+            }
+            return Ok(());
+        }
+        if id.is_multiple_of(2) || id <= self.last_stream_id {
+            bail!(H2Error::new(
+                error_codes::PROTOCOL_ERROR,
+                format!("Unexpected stream id {id}")
+            ));
+        }
+        self.last_stream_id = id;
+
+        let request = match Request::from_headers(headers) {
+            Ok(request) => request,
+            Err(e) => {
+                tracing::debug!("Invalid HTTP/2 request: {e}");
+                state.reset(id, error_codes::PROTOCOL_ERROR)?;
+                return Ok(());
+            }
+        };
+        if state.streams.len() >= MAX_CONCURRENT_STREAMS as usize {
+            state.reset(id, error_codes::REFUSED_STREAM)?;
+            return Ok(());
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let send_window = state.initial_window;
+        state.streams.insert(
+            id,
+            StreamState {
+                send_window,
+                sender: (!end_stream).then_some(sender),
+                local_closed: false,
+            },
+        );
+        drop(state);
+
+        let reader = StreamReader {
+            id,
+            receiver,
+            shared: self.shared.clone(),
+            buf: Box::new([]),
+            pos: 0,
+        };
+        let writer = StreamWriter {
+            id,
+            shared: self.shared.clone(),
+        };
+        let handler = self.handler;
+        scope.spawn(move || handler(request, reader, writer));
+
+        Ok(())
+    }
+
+    fn on_data(&mut self, frame: &Frame) -> Result<()> {
+        // RFC 9113 §6.1
+        if frame.stream_id == 0 {
+            bail!(H2Error::new(
+                error_codes::PROTOCOL_ERROR,
+                "DATA frame on stream 0"
+            ));
+        }
+        let data = frame.data()?;
+        let mut state = self.shared.lock()?;
+
+        // Connection window is returned right away, streams are limited by their own
+        state.window_update(0, frame.payload.len())?;
+
+        let Some(stream) = state.streams.get_mut(&frame.stream_id) else {
+            return Ok(());
+        };
+        if let Some(sender) = &stream.sender
+            && !data.is_empty()
+        {
+            _ = sender.send(Box::from(data));
+        }
+        if frame.flags & flags::END_STREAM != 0 {
+            stream.sender = None;
+            state.remove_if_done(frame.stream_id);
+        }
+        state.window_update(frame.stream_id, frame.payload.len() - data.len())?;
+
+        Ok(())
+    }
+
+    fn on_settings(&mut self, frame: &Frame) -> Result<()> {
+        if frame.flags & flags::ACK != 0 {
+            return Ok(());
+        }
+        if !frame.payload.len().is_multiple_of(6) {
+            bail!(H2Error::new(
+                error_codes::FRAME_SIZE_ERROR,
+                "Invalid SETTINGS length"
+            ));
+        }
+
+        let mut state = self.shared.lock()?;
+        for setting in frame.payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+
+            match id {
+                settings::INITIAL_WINDOW_SIZE => {
+                    let value = i64::from(value);
+                    if value > MAX_WINDOW_SIZE {
+                        bail!(H2Error::new(
+                            error_codes::FLOW_CONTROL_ERROR,
+                            "Initial window is too large"
+                        ));
+                    }
+                    let delta = value - state.initial_window;
+                    state.initial_window = value;
+                    for stream in state.streams.values_mut() {
+                        stream.send_window += delta;
+                    }
+                }
+                settings::MAX_FRAME_SIZE => {
+                    if !(MAX_FRAME_SIZE..(1 << 24)).contains(&(value as usize)) {
+                        bail!(H2Error::new(
+                            error_codes::PROTOCOL_ERROR,
+                            "Invalid max frame size"
+                        ));
+                    }
+                    state.max_frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+        self.shared.window.notify_all();
+
+        state.write_frame(frame_types::SETTINGS, flags::ACK, 0, &[])?;
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, frame: &Frame) -> Result<()> {
+        let Ok(raw) = <[u8; 4]>::try_from(&*frame.payload) else {
+            bail!(H2Error::new(
+                error_codes::FRAME_SIZE_ERROR,
+                "Invalid WINDOW_UPDATE length"
+            ));
+        };
+        let increment = i64::from(u32::from_be_bytes(raw) & 0x7FFF_FFFF);
+
+        let mut state = self.shared.lock()?;
+        let window = if frame.stream_id == 0 {
+            &mut state.send_window
+        } else if let Some(stream) = state.streams.get_mut(&frame.stream_id) {
+            &mut stream.send_window
+        } else {
+            return Ok(());
+        };
+        *window += increment;
+        if increment == 0 || *window > MAX_WINDOW_SIZE {
+            bail!(H2Error::new(
+                error_codes::FLOW_CONTROL_ERROR,
+                "Invalid window increment"
+            ));
+        }
+        self.shared.window.notify_all();
+
+        Ok(())
+    }
+
+    fn run<'scope>(&mut self, scope: &'scope thread::Scope<'scope, '_>) -> Result<()>
+    where
+        'a: 'scope,
+    {
+        while let Some(frame) = read_frame(&mut self.reader)? {
+            match frame.frame_type {
+                frame_types::DATA => self.on_data(&frame)?,
+                frame_types::HEADERS => self.on_headers(&frame, scope)?,
+                frame_types::RST_STREAM => {
+                    self.shared.lock()?.streams.remove(&frame.stream_id);
+                    self.shared.window.notify_all();
+                }
+                frame_types::SETTINGS => self.on_settings(&frame)?,
+                frame_types::PUSH_PROMISE => {
+                    bail!(H2Error::new(
+                        error_codes::PROTOCOL_ERROR,
+                        "Client sent PUSH_PROMISE"
+                    ));
+                }
+                frame_types::PING if frame.flags & flags::ACK == 0 => {
+                    self.shared.lock()?.write_frame(
+                        frame_types::PING,
+                        flags::ACK,
+                        0,
+                        &frame.payload,
+                    )?;
+                }
+                frame_types::GOAWAY => tracing::debug!("HTTP/2 client is going away"),
+                frame_types::WINDOW_UPDATE => self.on_window_update(&frame)?,
+                // Priorities and unknown frames are ignored
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Serve HTTP/2 on a connection the preface was already read from.
+///
+/// Each stream is handled on its own thread, returns once all of them finish
+pub fn serve(stream: TlsStream, handler: &Handler<'_>) -> Result<()> {
+    let (reader, writer) = stream.split();
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(ConnState {
+            writer,
+            send_window: DEFAULT_WINDOW_SIZE,
+            initial_window: DEFAULT_WINDOW_SIZE,
+            max_frame_size: MAX_FRAME_SIZE,
+            streams: HashMap::new(),
+            closed: false,
+        }),
+        window: Condvar::new(),
+    });
+
+    let mut settings = Vec::new();
+    for (id, value) in [
+        (settings::MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+        (settings::INITIAL_WINDOW_SIZE, STREAM_WINDOW_SIZE),
+    ] {
+        settings.extend(id.to_be_bytes());
+        settings.extend(value.to_be_bytes());
+    }
+    {
+        let mut state = shared.lock()?;
+        state.write_frame(frame_types::SETTINGS, 0, 0, &settings)?;
+        state.window_update(
+            0,
+            (CONNECTION_WINDOW_SIZE - DEFAULT_WINDOW_SIZE as u32) as usize,
+        )?;
+    }
+
+    let mut connection = Connection {
+        reader,
+        shared: shared.clone(),
+        decoder: hpack::Decoder::default(),
+        last_stream_id: 0,
+        handler,
+    };
+
+    thread::scope(|scope| {
+        let res = connection.run(scope);
+
+        let mut state = shared.lock()?;
+        if let Err(e) = &res {
+            let code = e
+                .downcast_ref::<H2Error>()
+                .map_or(error_codes::PROTOCOL_ERROR, |x| x.code);
+            let mut payload = connection.last_stream_id.to_be_bytes().to_vec();
+            payload.extend(code.to_be_bytes());
+            _ = state.write_frame(frame_types::GOAWAY, 0, 0, &payload);
+        }
+        // Unblock stream threads
+        state.closed = true;
+        state.streams.clear();
+        _ = state.writer.close();
+        shared.window.notify_all();
+
+        res
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+    use tls::{
+        cipher_suite::TLS_AES_128_GCM_SHA256, connection::RecordReader, key_schedule::TrafficKeys,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_frames() {
+        let raw = encode_frame(
+            frame_types::HEADERS,
+            flags::PADDED | flags::PRIORITY | flags::END_HEADERS,
+            3,
+            &[2, 0, 0, 0, 1, 16, 0x82, 0, 0],
+        );
+        assert_eq!(raw[..FRAME_HEADER_SIZE], [0, 0, 9, 1, 0x2C, 0, 0, 0, 3]);
+
+        let frame = read_frame(&mut &*raw).unwrap().unwrap();
+        assert_eq!(frame.frame_type, frame_types::HEADERS);
+        assert_eq!(frame.stream_id, 3);
+        assert_eq!(frame.data().unwrap(), [0x82]);
+
+        let raw = encode_frame(frame_types::DATA, 0, 1, &[0; MAX_FRAME_SIZE + 1]);
+        let e = read_frame(&mut &*raw).err().unwrap();
+        assert_eq!(
+            e.downcast_ref::<H2Error>().unwrap().code,
+            error_codes::FRAME_SIZE_ERROR
+        );
+        assert!(read_frame(&mut &[][..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_data_on_stream_0() -> Result<()> {
+        let keys = |secret| TrafficKeys::from_secret(TLS_AES_128_GCM_SHA256, &[secret; 32]);
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let client_conn = TcpStream::connect(listener.local_addr()?)?;
+        let (server_conn, _) = listener.accept()?;
+        let mut client = TlsStream::new(
+            client_conn.try_clone()?,
+            RecordReader::new(keys(2)?),
+            keys(1)?,
+        )?;
+        let server = TlsStream::new(server_conn, RecordReader::new(keys(1)?), keys(2)?)?;
+
+        client.write_all(&encode_frame(frame_types::DATA, 0, 0, b"data"))?;
+        client_conn.shutdown(std::net::Shutdown::Write)?;
+        assert!(serve(server, &|_, _, _| {}).is_err());
+
+        let mut goaway = None;
+        while let Some(frame) = read_frame(&mut client)? {
+            if frame.frame_type == frame_types::GOAWAY {
+                goaway = Some(frame.payload);
+            }
+        }
+        let payload = goaway.ok_or(anyhow::anyhow!("No GOAWAY"))?;
+        assert_eq!(payload[4..], error_codes::PROTOCOL_ERROR.to_be_bytes());
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::VecDeque;
+
+use anyhow::{Result, anyhow, bail};
+
+use super::huffman;
+
+/// Dynamic table size allowed by default, also what we advertise.
+pub const HEADER_TABLE_SIZE: usize = 4096;
+
+/// Per entry overhead counted towards the table size.
+const ENTRY_OVERHEAD: usize = 32;
+
+pub type Header = (String, String);
+
+/// RFC 7541 appendix A.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Read an integer with an `prefix` bit prefix, RFC 7541 section 5.1.
+fn read_integer(data: &mut &[u8], prefix: u8) -> Result<usize> {
+    let (&first, rest) = data.split_first().ok_or(anyhow!("Truncated integer"))?;
+    *data = rest;
+
+    let max_prefix = (1 << prefix) - 1;
+    let mut value = (first & max_prefix) as usize;
+    if value < max_prefix as usize {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = data.split_first().ok_or(anyhow!("Truncated integer"))?;
+        *data = rest;
+        if shift > 28 {
+            bail!("Integer overflow");
+        }
+        value += ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn write_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let max_prefix = (1 << prefix) - 1;
+    if value < max_prefix as usize {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max_prefix);
+    value -= max_prefix as usize;
+    while value >= 0x80 {
+        out.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_string(data: &mut &[u8]) -> Result<String> {
+    let huffman = data.first().is_some_and(|x| x & 0x80 != 0);
+    let length = read_integer(data, 7)?;
+    if data.len() < length {
+        bail!("Truncated string");
+    }
+    let (raw, rest) = data.split_at(length);
+    *data = rest;
+
+    let raw = if huffman {
+        huffman::decode(raw)?
+    } else {
+        Box::from(raw)
+    };
+    Ok(String::from_utf8(raw.into_vec())?)
+}
+
+/// Plain string literal, we never use Huffman coding.
+fn write_string(out: &mut Vec<u8>, value: &str) {
+    write_integer(out, 0, 7, value.len());
+    out.extend(value.as_bytes());
+}
+
+/// Header block decoder, keeps the dynamic table of one connection.
+#[derive(Default)]
+pub struct Decoder {
+    table: VecDeque<Header>,
+    table_size: usize,
+    max_table_size: Option<usize>,
+}
+
+impl Decoder {
+    fn max_table_size(&self) -> usize {
+        self.max_table_size.unwrap_or(HEADER_TABLE_SIZE)
+    }
+
+    fn get(&self, index: usize) -> Result<Header> {
+        match index {
+            0 => bail!("Zero header index"),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((String::from(name), String::from(value)))
+            }
+            _ => self
+                .table
+                .get(index - 62)
+                .cloned()
+                .ok_or(anyhow!("Invalid header index {index}")),
+        }
+    }
+
+    fn evict(&mut self, max_size: usize) {
+        while self.table_size > max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.table_size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+
+    fn insert(&mut self, header: Header) {
+        let size = header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.evict(self.max_table_size().saturating_sub(size));
+        if size <= self.max_table_size() {
+            self.table_size += size;
+            self.table.push_front(header);
+        }
+    }
+
+    /// Literal header field, `prefix` bits hold the name index.
+    fn read_literal(&self, data: &mut &[u8], prefix: u8) -> Result<Header> {
+        let name = match read_integer(data, prefix)? {
+            0 => read_string(data)?,
+            index => self.get(index)?.0,
+        };
+        Ok((name, read_string(data)?))
+    }
+
+    /// Decode a complete header block.
+    pub fn decode(&mut self, mut data: &[u8]) -> Result<Vec<Header>> {
+        let mut res = Vec::new();
+
+        while let Some(&first) = data.first() {
+            if first & 0x80 != 0 {
+                let index = read_integer(&mut data, 7)?;
+                res.push(self.get(index)?);
+            } else if first & 0x40 != 0 {
+                let header = self.read_literal(&mut data, 6)?;
+                self.insert(header.clone());
+                res.push(header);
+            } else if first & 0x20 != 0 {
+                let size = read_integer(&mut data, 5)?;
+                if size > HEADER_TABLE_SIZE {
+                    bail!("Dynamic table size {size} is too large");
+                }
+                self.max_table_size = Some(size);
+                self.evict(size);
+            } else {
+                // Without indexing or never indexed
+                res.push(self.read_literal(&mut data, 4)?);
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// Encode headers as literals without indexing, so no encoder state is needed.
+pub fn encode(headers: &[(&str, &str)]) -> Box<[u8]> {
+    let mut res = Vec::new();
+
+    for &(name, value) in headers {
+        match STATIC_TABLE.iter().position(|x| *x == (name, value)) {
+            Some(index) => write_integer(&mut res, 0x80, 7, index + 1),
+            None => {
+                match STATIC_TABLE.iter().position(|x| x.0 == name) {
+                    Some(index) => write_integer(&mut res, 0, 4, index + 1),
+                    None => {
+                        res.push(0);
+                        write_string(&mut res, name);
+                    }
+                }
+                write_string(&mut res, value);
+            }
+        }
+    }
+
+    res.into_boxed_slice()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // RFC 7541 appendix C.4
+        let mut decoder = Decoder::default();
+        let headers = decoder
+            .decode(&[
+                0x82, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab,
+                0x90, 0xf4, 0xff,
+            ])
+            .unwrap();
+        assert_eq!(
+            headers,
+            [
+                (String::from(":method"), String::from("GET")),
+                (String::from(":scheme"), String::from("http")),
+                (String::from(":path"), String::from("/")),
+                (String::from(":authority"), String::from("www.example.com")),
+            ]
+        );
+
+        let headers = decoder
+            .decode(&[
+                0x82, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf,
+            ])
+            .unwrap();
+        assert_eq!(headers[3].1, "www.example.com");
+        assert_eq!(
+            headers[4],
+            (String::from("cache-control"), String::from("no-cache"))
+        );
+        assert_eq!(decoder.table_size, 110);
+
+        let raw = encode(&[
+            (":status", "200"),
+            ("content-type", "application/grpc"),
+            ("x", "y"),
+        ]);
+        let headers = Decoder::default().decode(&raw).unwrap();
+        assert_eq!(headers[0], (String::from(":status"), String::from("200")));
+        assert_eq!(headers[1].1, "application/grpc");
+        assert_eq!(headers[2], (String::from("x"), String::from("y")));
+
+        let mut raw = Vec::new();
+        write_integer(&mut raw, 0, 5, 1337);
+        assert_eq!(raw, [0x1f, 0x9a, 0x0a]);
+        assert_eq!(read_integer(&mut raw.as_slice(), 5).unwrap(), 1337);
+    }
+}
@@ -0,0 +1,166 @@
+use std::io::{self, Read};
+
+use anyhow::{Result, anyhow, bail};
+
+/// Largest request head accepted from a client.
+pub const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// HTTP/1 request line and headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Parse a request head from the beginning of the buffer.
+    ///
+    /// Returns the head and its size including the empty line,
+    /// or `None` if it is not complete yet
+    pub fn parse(data: &[u8]) -> Result<Option<(Self, usize)>> {
+        let Some(head_end) = data.windows(4).position(|x| x == b"\r\n\r\n") else {
+            if data.len() > MAX_HEAD_SIZE {
+                bail!("Request head is too large");
+            }
+            return Ok(None);
+        };
+        let head = str::from_utf8(&data[..head_end])?;
+
+        let mut lines = head.split("\r\n");
+        let request_line = lines.next().unwrap_or_default();
+
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Invalid request line: {request_line}");
+        };
+        if !version.starts_with("HTTP/1.") {
+            bail!("Unsupported HTTP version: {version}");
+        }
+
+        let headers = lines
+            .map(|line| {
+                let (name, value) = line
+                    .split_once(':')
+                    .ok_or(anyhow!("Invalid header: {line}"))?;
+                Ok((String::from(name.trim()), String::from(value.trim())))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Some((
+            Self {
+                method: String::from(method),
+                target: String::from(target),
+                version: String::from(version),
+                headers,
+            },
+            head_end + 4,
+        )))
+    }
+
+    /// First value of the header, names are case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a comma separated header contains the token.
+    pub fn header_has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .is_some_and(|x| x.split(',').any(|x| x.trim().eq_ignore_ascii_case(token)))
+    }
+
+    /// Request target without the query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// `Host` header without the port.
+    pub fn host(&self) -> Option<&str> {
+        self.header("Host").map(strip_port)
+    }
+
+    /// Check the request is for the configured path and host.
+    ///
+    /// Queries are ignored on both sides, clients put options there
+    pub fn check_target(&self, path: &str, host: Option<&str>) -> Result<()> {
+        let path = path.split('?').next().unwrap_or_default();
+        if self.path() != path {
+            bail!("Unexpected path {}", self.path());
+        }
+        check_host(self.host(), host)
+    }
+}
+
+/// Host without the port, IPv6 literals keep their brackets.
+pub fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    }
+}
+
+/// Check the requested host if one is configured.
+pub fn check_host(requested: Option<&str>, host: Option<&str>) -> Result<()> {
+    if let Some(host) = host
+        && !requested.is_some_and(|x| x.eq_ignore_ascii_case(host))
+    {
+        bail!("Unexpected host {requested:?}");
+    }
+    Ok(())
+}
+
+/// Head of the request and its size, or why the data is not one.
+pub type ParsedHead = Result<(RequestHead, usize)>;
+
+/// Read from the stream until a complete request head is received.
+///
+/// Returns everything read along with the parse result
+pub fn read_head(stream: &mut impl Read) -> io::Result<(Box<[u8]>, ParsedHead)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+
+    loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            let res = Err(anyhow!("Connection closed before request head"));
+            return Ok((buf.into_boxed_slice(), res));
+        }
+        buf.extend(&chunk[..n]);
+
+        match RequestHead::parse(&buf) {
+            Ok(None) => {}
+            Ok(Some(head)) => return Ok((buf.into_boxed_slice(), Ok(head))),
+            Err(e) => return Ok((buf.into_boxed_slice(), Err(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let data = b"GET /ws?ed=2048 HTTP/1.1\r\nHost: Example.com:443\r\n\
+                     Connection: keep-alive, Upgrade\r\n\r\nrest";
+        let (head, size) = RequestHead::parse(data).unwrap().unwrap();
+        assert_eq!(&data[size..], b"rest");
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.path(), "/ws");
+        assert_eq!(head.host(), Some("Example.com"));
+        assert_eq!(head.header("connection"), Some("keep-alive, Upgrade"));
+        assert!(head.header_has_token("Connection", "upgrade"));
+        assert!(!head.header_has_token("Upgrade", "websocket"));
+
+        assert!(RequestHead::parse(b"GET / HTTP/1.1\r\n").unwrap().is_none());
+        assert!(RequestHead::parse(b"GET / HTTP/2\r\n\r\n").is_err());
+        assert!(RequestHead::parse(b"\x16\x03\x01\x02\x00\r\n\r\n").is_err());
+        assert!(RequestHead::parse(&[b'a'; MAX_HEAD_SIZE + 1]).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::{Result, anyhow, bail};
+use vless::Address;
+
+use crate::http::{self, RequestHead};
+
+/// Headers meant for the proxy itself, not forwarded to the destination.
+const HOP_BY_HOP_HEADERS: [&str; 4] = [
+    "connection",
+    "keep-alive",
+    "proxy-authorization",
+    "proxy-connection",
+];
+
+pub const ESTABLISHED: &[u8] = b"HTTP/1.1 200 Connection established\r\n\r\n";
+pub const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nConnection: close\r\n\r\n";
+pub const BAD_GATEWAY: &[u8] = b"HTTP/1.1 502 Bad Gateway\r\nConnection: close\r\n\r\n";
+
+/// Destination of a proxy request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProxyRequest {
+    pub addr: Address,
+    pub port: u16,
+    /// `CONNECT` tunnel, the client waits for [`ESTABLISHED`]
+    pub tunnel: bool,
+    /// Data to send to the destination first
+    pub payload: Box<[u8]>,
+}
+
+/// Split `host:port`, IP literals become addresses of their own type.
+fn parse_authority(authority: &str, default_port: Option<u16>) -> Result<(Address, u16)> {
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => (host, Some(port.parse()?)),
+        _ => (authority, None),
+    };
+    let port = port
+        .or(default_port)
+        .ok_or(anyhow!("Missing port: {authority}"))?;
+
+    let addr = if let Some(ip) = host.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        Address::Ipv6(ip.parse::<Ipv6Addr>()?)
+    } else if let Ok(ip) = host.parse::<Ipv4Addr>() {
+        Address::Ipv4(ip)
+    } else if host.is_empty() {
+        bail!("Empty host: {authority}");
+    } else {
+        Address::Domain(host.to_lowercase())
+    };
+
+    Ok((addr, port))
+}
+
+/// Rewrite an absolute-form request for the origin server.
+///
+/// The connection is closed after the response, as following requests
+/// may go to other hosts
+fn rewrite(head: &RequestHead, path: &str) -> Box<[u8]> {
+    let mut res = format!("{} {path} {}\r\n", head.method, head.version);
+    for (name, value) in &head.headers {
+        if !HOP_BY_HOP_HEADERS.contains(&name.to_lowercase().as_str()) {
+            res.push_str(&format!("{name}: {value}\r\n"));
+        }
+    }
+    res.push_str("Connection: close\r\n\r\n");
+
+    res.into_bytes().into_boxed_slice()
+}
+
+/// Map a request head to the destination, `rest` is the beginning of the body.
+fn to_request(head: &RequestHead, rest: &[u8]) -> Result<ProxyRequest> {
+    if head.method.eq_ignore_ascii_case("CONNECT") {
+        let (addr, port) = parse_authority(&head.target, None)?;
+        return Ok(ProxyRequest {
+            addr,
+            port,
+            tunnel: true,
+            payload: Box::from(rest),
+        });
+    }
+
+    let uri = head
+        .target
+        .strip_prefix("http://")
+        .ok_or(anyhow!("Not an absolute http URI: {}", head.target))?;
+    let (authority, path) = match uri.find(['/', '?']) {
+        Some(i) => (&uri[..i], &uri[i..]),
+        None => (uri, "/"),
+    };
+    let path = if path.starts_with('?') {
+        format!("/{path}")
+    } else {
+        String::from(path)
+    };
+    let (addr, port) = parse_authority(authority, Some(80))?;
+
+    let payload = [&rewrite(head, &path), rest].concat();
+    Ok(ProxyRequest {
+        addr,
+        port,
+        tunnel: false,
+        payload: payload.into_boxed_slice(),
+    })
+}
+
+/// Read a proxy request, answering malformed ones with 400.
+pub fn accept(stream: &mut (impl Read + Write)) -> Result<ProxyRequest> {
+    let (data, head) = http::read_head(stream)?;
+    let res = head.and_then(|(head, size)| to_request(&head, &data[size..]));
+    if res.is_err() && !data.is_empty() {
+        stream.write_all(BAD_REQUEST)?;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Result<Option<ProxyRequest>> {
+        RequestHead::parse(data)?
+            .map(|(head, size)| to_request(&head, &data[size..]))
+            .transpose()
+    }
+
+    #[test]
+    fn test_parse() {
+        let request =
+            parse(b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n\x16")
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            request,
+            ProxyRequest {
+                addr: Address::Domain(String::from("example.com")),
+                port: 443,
+                tunnel: true,
+                payload: Box::new([0x16]),
+            }
+        );
+
+        let request = parse(b"CONNECT [::1]:8443 HTTP/1.1\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.addr, Address::Ipv6(Ipv6Addr::LOCALHOST));
+        assert_eq!(request.port, 8443);
+
+        let request = parse(
+            b"GET http://127.0.0.1:8080?q=1 HTTP/1.1\r\nHost: 127.0.0.1:8080\r\n\
+              Proxy-Connection: keep-alive\r\n\r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.addr, Address::Ipv4(Ipv4Addr::LOCALHOST));
+        assert_eq!(request.port, 8080);
+        assert!(!request.tunnel);
+        assert_eq!(
+            &*request.payload,
+            b"GET /?q=1 HTTP/1.1\r\nHost: 127.0.0.1:8080\r\nConnection: close\r\n\r\n"
+        );
+
+        let request = parse(b"POST http://Example.com/a HTTP/1.0\r\n\r\nbody")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.addr, Address::Domain(String::from("example.com")));
+        assert_eq!(request.port, 80);
+        assert!(request.payload.ends_with(b"\r\n\r\nbody"));
+
+        assert!(
+            parse(b"GET http://example.com/ HTTP/1.1\r\n")
+                .unwrap()
+                .is_none()
+        );
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").is_err());
+        assert!(parse(b"CONNECT example.com HTTP/1.1\r\n\r\n").is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use std::io::{Cursor, Read, Write};
+
+use anyhow::{Result, bail};
+
+use crate::{http::RequestHead, stream::TlsStream, transport::ClientStream, websocket};
+
+/// Accepted upgrade request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Upgrade {
+    /// `Sec-WebSocket-Protocol` carrying early data, echoed back to the client
+    protocol: Option<String>,
+    early_data: Box<[u8]>,
+}
+
+impl Upgrade {
+    /// Check the request against the transport settings.
+    ///
+    /// Any protocol may be named in `Upgrade`, no WebSocket handshake is done
+    pub fn new(head: &RequestHead, path: &str, host: Option<&str>) -> Result<Self> {
+        if head.method != "GET" {
+            bail!("Unexpected method {}", head.method);
+        }
+        head.check_target(path, host)?;
+        if head.header("Upgrade").is_none_or(str::is_empty)
+            || !head.header_has_token("Connection", "upgrade")
+        {
+            bail!("Not an upgrade request");
+        }
+
+        let (protocol, early_data) = websocket::early_data(head, path)?;
+        Ok(Self {
+            protocol,
+            early_data,
+        })
+    }
+
+    fn response(&self) -> String {
+        let mut res = String::from(
+            "HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n",
+        );
+        if let Some(protocol) = &self.protocol {
+            res.push_str(&format!("Sec-WebSocket-Protocol: {protocol}\r\n"));
+        }
+        res.push_str("\r\n");
+        res
+    }
+}
+
+/// Answer the upgrade request, the connection carries raw bytes afterwards.
+///
+/// `rest` is data the client sent right after the request
+pub fn accept(stream: TlsStream, upgrade: Upgrade, rest: &[u8]) -> Result<ClientStream> {
+    let (reader, mut writer) = stream.split();
+    writer.write_all(upgrade.response().as_bytes())?;
+
+    let received = [&*upgrade.early_data, rest].concat();
+    Ok(ClientStream::Wrapped {
+        reader: Box::new(Cursor::new(received).chain(reader)),
+        writer: Box::new(writer),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upgrade() {
+        let data = b"GET /up HTTP/1.1\r\nHost: example.com\r\nConnection: Upgrade\r\n\
+                     Upgrade: websocket\r\n\r\n";
+        let (head, _) = RequestHead::parse(data).unwrap().unwrap();
+        let upgrade = Upgrade::new(&head, "/up", None).unwrap();
+        assert!(upgrade.early_data.is_empty());
+        assert!(upgrade.response().ends_with("Upgrade: websocket\r\n\r\n"));
+
+        assert!(Upgrade::new(&head, "/up", Some("example.org")).is_err());
+
+        let data = b"GET /up HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let (head, _) = RequestHead::parse(data).unwrap().unwrap();
+        assert!(Upgrade::new(&head, "/up", None).is_err());
+    }
+}
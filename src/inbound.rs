@@ -0,0 +1,339 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    os::unix::net::UnixStream,
+    sync::{Arc, atomic::Ordering},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use tls::server::ServerConfig;
+use vless::{
+    Addons, Address, Command, VlessError, VlessRequestHeader, VlessResponseHeader, vision,
+};
+
+use crate::{
+    dns::Resolver,
+    fallback::{self, Fallbacks},
+    mux,
+    routing::{Destination, Network, Outbound, Router},
+    sniff::Sniffing,
+    stats::Stats,
+    stream::{CloseWrite, HandshakeInfo, TlsStream},
+    transport::{ClientStream, Transport},
+    udp,
+    users::{Metered, User, Users},
+};
+
+/// Tag of inbounds that do not set one.
+pub const DEFAULT_TAG: &str = "vless-in";
+
+/// How long rejected clients are kept reading before the connection is closed.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Listener settings shared by all of its connections.
+pub struct Inbound {
+    /// Name routing rules refer to
+    pub tag: String,
+    /// Certificate and key the TLS handshake is made with
+    pub tls: ServerConfig,
+    pub transport: Transport,
+    pub users: Users,
+    pub fallbacks: Fallbacks,
+    pub sniffing: Sniffing,
+    pub router: Arc<Router>,
+    pub resolver: Arc<Resolver>,
+    pub stats: Arc<Stats>,
+}
+
+type ClientReader = Metered<Box<dyn Read + Send>>;
+type ClientWriter = Metered<Box<dyn CloseWrite + Send>>;
+
+/// Request header and its size, or why the data is not a VLESS request.
+type ParsedRequest = Result<(VlessRequestHeader, usize)>;
+
+/// Read from the stream until a complete request header is received.
+///
+/// Returns everything read along with the parse result
+pub fn read_request(stream: &mut impl Read) -> io::Result<(Box<[u8]>, ParsedRequest)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+
+    loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            let res = Err(anyhow!("Connection closed before request header"));
+            return Ok((buf.into_boxed_slice(), res));
+        }
+        buf.extend(&chunk[..n]);
+
+        match VlessRequestHeader::from_raw(&buf) {
+            Err(e) if matches!(e.downcast_ref(), Some(VlessError::Truncated)) => {}
+            res => return Ok((buf.into_boxed_slice(), res)),
+        }
+    }
+}
+
+/// Connect to the first reachable address of the destination.
+pub fn connect(addr: &Address, port: u16, resolver: &Resolver) -> io::Result<TcpStream> {
+    TcpStream::connect(&*resolver.resolve(addr, port)?)
+}
+
+/// Destination side of a relay.
+pub trait Duplex: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+}
+
+impl Duplex for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+}
+
+impl Duplex for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+}
+
+/// Copy bytes both ways until each side finishes sending.
+pub fn relay(
+    mut reader: impl Read,
+    mut writer: impl CloseWrite + Send + 'static,
+    mut target: impl Duplex,
+) -> Result<()> {
+    let mut target_read = target.try_clone()?;
+
+    let downlink = thread::spawn(move || -> io::Result<u64> {
+        let n = io::copy(&mut target_read, &mut writer)?;
+        writer.close()?;
+        Ok(n)
+    });
+
+    let uplink = io::copy(&mut reader, &mut target);
+    _ = target.shutdown(Shutdown::Write);
+
+    let downlink = downlink.join().map_err(|_| anyhow!("Downlink panicked"))?;
+    tracing::debug!("Relay finished: uplink {uplink:?}, downlink {downlink:?}");
+
+    uplink?;
+    downlink?;
+    Ok(())
+}
+
+/// Read and discard whatever the client sends, then close without a response.
+fn drain(mut stream: TlsStream) -> Result<()> {
+    stream.set_read_timeout(Some(DRAIN_TIMEOUT))?;
+    _ = io::copy(&mut stream, &mut io::sink());
+    Ok(())
+}
+
+/// Handle anything that is not an authorized VLESS request.
+///
+/// Rejected requests look the same as any other unexpected input,
+/// inside other transports the connection is just closed
+pub fn reject(
+    stream: ClientStream,
+    info: &HandshakeInfo,
+    fallbacks: &Fallbacks,
+    data: &[u8],
+) -> Result<()> {
+    let ClientStream::Tls(stream) = stream else {
+        return Ok(());
+    };
+    match fallbacks.select(info, data) {
+        Some(fallback) => fallback::forward(stream, fallback, data),
+        None => drain(stream),
+    }
+}
+
+/// Application data at the start of the payload, the first packet for UDP.
+fn first_data(header: &VlessRequestHeader, payload: &[u8]) -> Box<[u8]> {
+    match (header.command, header.addons.flow.as_deref()) {
+        (Command::UDP, _) => {
+            let packet = payload
+                .get(2..)
+                .zip(payload.get(..2))
+                .and_then(|(rest, length)| {
+                    rest.get(..usize::from(u16::from_be_bytes([length[0], length[1]])))
+                });
+            Box::from(packet.unwrap_or_default())
+        }
+        (_, Some(vision::FLOW)) => vision::Unpadder::new(header.uuid)
+            .unpad(payload, &mut vision::TrafficState::new())
+            .map(|(content, _)| content)
+            .unwrap_or_default(),
+        _ => Box::from(payload),
+    }
+}
+
+/// Send the response header and set up the requested flow.
+///
+/// Returns client connection halves and the payload for the destination
+fn accept(
+    stream: ClientStream,
+    header: &VlessRequestHeader,
+    payload: Box<[u8]>,
+    user: &Arc<User>,
+    inbound: &Inbound,
+) -> Result<(ClientReader, ClientWriter, Box<[u8]>)> {
+    let session = Arc::new(inbound.stats.session(user, &inbound.tag)?);
+    let response = VlessResponseHeader::new(Addons::default()).to_raw()?;
+
+    let (reader, writer, payload): (Box<dyn Read + Send>, Box<dyn CloseWrite + Send>, _) =
+        match (stream, header.addons.flow.as_deref()) {
+            (ClientStream::Tls(stream), None | Some("")) => {
+                let (reader, mut writer) = stream.split();
+                writer.write_all(&response)?;
+                (Box::new(reader), Box::new(writer), payload)
+            }
+            (ClientStream::Wrapped { reader, mut writer }, None | Some("")) => {
+                writer.write_all(&response)?;
+                (reader, writer, payload)
+            }
+            // Vision splices the raw TLS connection
+            (ClientStream::Tls(stream), Some(vision::FLOW)) if header.command != Command::UDP => {
+                let (reader, mut writer) = stream.split();
+                writer.write_all(&response)?;
+                let (reader, writer) = crate::vision::wrap(reader, writer, header.uuid, &payload)?;
+                (Box::new(reader), Box::new(writer), Box::from([]))
+            }
+            (_, Some(flow)) => bail!("Unsupported flow `{flow}` for {:?}", header.command),
+        };
+
+    // Payload bypasses the metered reader
+    user.uplink
+        .fetch_add(payload.len() as u64, Ordering::Relaxed);
+    session.uplink(payload.len() as u64);
+
+    Ok((
+        Metered::new(reader, user.clone(), session.clone()),
+        Metered::new(writer, user.clone(), session),
+        payload,
+    ))
+}
+
+pub fn handle(mut stream: ClientStream, info: &HandshakeInfo, inbound: &Inbound) -> Result<()> {
+    let (data, request) = read_request(&mut stream)?;
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    let (header, size) = match request {
+        Ok(request) => request,
+        Err(e) => {
+            tracing::debug!("Invalid request: {e}");
+            return reject(stream, info, &inbound.fallbacks, &data);
+        }
+    };
+
+    let user = match inbound.users.authorize(&header) {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::warn!("Rejected {}: {e}", vless::format_uuid(header.uuid));
+            return reject(stream, info, &inbound.fallbacks, &data);
+        }
+    };
+    let payload = Box::from(&data[size..]);
+
+    // Mux sessions are routed one by one
+    if header.command == Command::Mux {
+        tracing::info!("{} -> {}:{} (Mux)", user.email, header.addr, header.port);
+        let (reader, writer, payload) = accept(stream, &header, payload, &user, inbound)?;
+
+        return mux::serve(reader, writer, &payload, &user, inbound);
+    }
+
+    let network = if header.command == Command::UDP {
+        Network::Udp
+    } else {
+        Network::Tcp
+    };
+    let (route_addr, addr) =
+        inbound
+            .sniffing
+            .apply(&header.addr, &first_data(&header, &payload), network);
+    let (tag, outbound) = inbound.router.route(&Destination {
+        addr: &route_addr,
+        port: header.port,
+        network,
+        user: user.uuid,
+        inbound_tag: &inbound.tag,
+    });
+
+    tracing::info!(
+        "{} -> {}:{} ({:?}) via {tag}",
+        user.email,
+        route_addr,
+        header.port,
+        header.command
+    );
+
+    match (outbound, header.command) {
+        (Outbound::Block, _) => Ok(()),
+        (Outbound::Direct, Command::UDP) => {
+            let target = udp::resolve(&addr, header.port, &inbound.resolver)?;
+            let (reader, writer, payload) = accept(stream, &header, payload, &user, inbound)?;
+
+            udp::relay(reader, writer, target, &payload)
+        }
+        (Outbound::Direct, _) => {
+            let mut target = connect(&addr, header.port, &inbound.resolver)?;
+            let (reader, writer, payload) = accept(stream, &header, payload, &user, inbound)?;
+            target.write_all(&payload)?;
+
+            relay(reader, writer, target)
+        }
+        // UDP packets are framed the same way on both connections
+        (Outbound::Vless(server), command) => {
+            let mut target = server.connect(command, addr, header.port)?;
+            let (reader, writer, payload) = accept(stream, &header, payload, &user, inbound)?;
+            target.write_all(&payload)?;
+
+            relay(reader, writer, target)
+        }
+    }
+}
+
+#[cfg(test)]
+impl Inbound {
+    /// Plain TCP inbound of `users` without TLS credentials, routing everything directly.
+    pub fn with_users(users: Users) -> Result<Self> {
+        use crypt::rsa::PrivateKey;
+        use num_bigint::BigUint;
+        use tls::record::handshake::extension::SignatureScheme;
+
+        use crate::dns::DnsFile;
+
+        Ok(Self {
+            tag: String::from(DEFAULT_TAG),
+            tls: ServerConfig {
+                certificate: Box::new([]),
+                signature_schemes: vec![SignatureScheme::rsa_pss_rsae_sha256],
+                private_key: PrivateKey {
+                    modulus: BigUint::default(),
+                    exponent: BigUint::default(),
+                },
+                alpn_protocols: Vec::new(),
+                cipher_suites: Vec::new(),
+            },
+            transport: Transport::default(),
+            users,
+            fallbacks: Fallbacks::default(),
+            sniffing: Sniffing::default(),
+            router: Arc::new(Router::default()),
+            resolver: Arc::new(Resolver::from_settings(DnsFile::default())?),
+            stats: Arc::new(Stats::default()),
+        })
+    }
+}
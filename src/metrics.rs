@@ -0,0 +1,221 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use tls::error::TlsAlert;
+
+use crate::{stats::Snapshot, stream::HandshakeInfo};
+
+/// Upper bounds of the handshake duration buckets in seconds.
+const DURATION_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Observations per bucket, the last one is `+Inf`
+    counts: [u64; DURATION_BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&x| value <= x)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    fn render(&self, name: &str, out: &mut String) {
+        let mut total = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            total += count;
+            let bound = DURATION_BUCKETS
+                .get(i)
+                .map_or(String::from("+Inf"), ToString::to_string);
+            _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {total}");
+        }
+        _ = writeln!(out, "{name}_sum {}", self.sum);
+        _ = writeln!(out, "{name}_count {total}");
+    }
+}
+
+#[derive(Debug, Default)]
+struct Handshakes {
+    /// By cipher suite and group
+    completed: BTreeMap<(&'static str, String), u64>,
+    /// By alert
+    failed: BTreeMap<String, u64>,
+    duration: Histogram,
+}
+
+/// Server wide counters in the Prometheus text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    handshakes_started: AtomicU64,
+    handshakes: Mutex<Handshakes>,
+    active_connections: AtomicU64,
+}
+
+/// Open TLS connection, counted as active until dropped.
+pub struct Connection<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .active_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Count a new connection, its handshake starts right away.
+    pub fn connection(&self) -> Connection<'_> {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        self.handshakes_started.fetch_add(1, Ordering::Relaxed);
+        Connection { metrics: self }
+    }
+
+    fn handshakes(&self) -> Result<MutexGuard<'_, Handshakes>> {
+        self.handshakes.lock().map_err(|_| anyhow!("Poisoned lock"))
+    }
+
+    pub fn handshake_completed(&self, info: &HandshakeInfo, duration: Duration) -> Result<()> {
+        let group = info
+            .group
+            .map_or(String::from("none"), |x| format!("{x:?}"));
+
+        let mut handshakes = self.handshakes()?;
+        *handshakes
+            .completed
+            .entry((info.cipher_suite.name(), group))
+            .or_default() += 1;
+        handshakes.duration.observe(duration.as_secs_f64());
+        Ok(())
+    }
+
+    /// Failures without an alert are counted as `Other`.
+    pub fn handshake_failed(&self, alert: Option<&TlsAlert>) -> Result<()> {
+        let alert = alert.map_or(String::from("Other"), ToString::to_string);
+        *self.handshakes()?.failed.entry(alert).or_default() += 1;
+        Ok(())
+    }
+
+    /// Exposition text, `relayed` is the traffic of all client sessions.
+    pub fn render(&self, relayed: Snapshot) -> Result<String> {
+        let handshakes = self.handshakes()?;
+        let mut out = String::new();
+
+        out.push_str("# TYPE rs_vless_tls_handshakes_started_total counter\n");
+        _ = writeln!(
+            out,
+            "rs_vless_tls_handshakes_started_total {}",
+            self.handshakes_started.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# TYPE rs_vless_tls_handshakes_completed_total counter\n");
+        for ((cipher_suite, group), count) in &handshakes.completed {
+            _ = writeln!(
+                out,
+                "rs_vless_tls_handshakes_completed_total\
+                 {{cipher_suite=\"{cipher_suite}\",group=\"{group}\"}} {count}"
+            );
+        }
+
+        out.push_str("# TYPE rs_vless_tls_handshakes_failed_total counter\n");
+        for (alert, count) in &handshakes.failed {
+            _ = writeln!(
+                out,
+                "rs_vless_tls_handshakes_failed_total{{alert=\"{alert}\"}} {count}"
+            );
+        }
+
+        out.push_str("# TYPE rs_vless_tls_handshake_duration_seconds histogram\n");
+        handshakes
+            .duration
+            .render("rs_vless_tls_handshake_duration_seconds", &mut out);
+
+        out.push_str("# TYPE rs_vless_relayed_bytes_total counter\n");
+        _ = writeln!(
+            out,
+            "rs_vless_relayed_bytes_total{{direction=\"uplink\"}} {}",
+            relayed.uplink
+        );
+        _ = writeln!(
+            out,
+            "rs_vless_relayed_bytes_total{{direction=\"downlink\"}} {}",
+            relayed.downlink
+        );
+
+        out.push_str("# TYPE rs_vless_active_connections gauge\n");
+        _ = writeln!(
+            out,
+            "rs_vless_active_connections {}",
+            self.active_connections.load(Ordering::Relaxed)
+        );
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tls::{cipher_suite::TLS_AES_256_GCM_SHA384, record::handshake::extension::NamedGroup};
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        let info = HandshakeInfo {
+            server_name: None,
+            alpn: None,
+            cipher_suite: TLS_AES_256_GCM_SHA384,
+            group: Some(NamedGroup::x25519),
+        };
+
+        let first = metrics.connection();
+        metrics
+            .handshake_completed(&info, Duration::from_millis(20))
+            .unwrap();
+        let second = metrics.connection();
+        metrics
+            .handshake_failed(Some(&TlsAlert::DecryptError))
+            .unwrap();
+        metrics.handshake_failed(None).unwrap();
+        drop(second);
+
+        let relayed = Snapshot {
+            uplink: 10,
+            downlink: 20,
+            active: 1,
+        };
+        let out = metrics.render(relayed).unwrap();
+        let lines = out.lines().collect::<Vec<_>>();
+        for expected in [
+            "rs_vless_tls_handshakes_started_total 2",
+            "rs_vless_tls_handshakes_completed_total\
+             {cipher_suite=\"TLS_AES_256_GCM_SHA384\",group=\"x25519\"} 1",
+            "rs_vless_tls_handshakes_failed_total{alert=\"DecryptError\"} 1",
+            "rs_vless_tls_handshakes_failed_total{alert=\"Other\"} 1",
+            "rs_vless_tls_handshake_duration_seconds_bucket{le=\"0.01\"} 0",
+            "rs_vless_tls_handshake_duration_seconds_bucket{le=\"0.025\"} 1",
+            "rs_vless_tls_handshake_duration_seconds_bucket{le=\"+Inf\"} 1",
+            "rs_vless_tls_handshake_duration_seconds_count 1",
+            "rs_vless_relayed_bytes_total{direction=\"downlink\"} 20",
+            "rs_vless_active_connections 1",
+        ] {
+            assert!(lines.contains(&expected), "missing {expected}");
+        }
+        drop(first);
+    }
+}
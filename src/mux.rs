@@ -0,0 +1,485 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{Shutdown, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    },
+    thread,
+    time::Instant,
+};
+
+use anyhow::{Result, anyhow, bail};
+use vless::{
+    Address, Command,
+    mux::{Frame, FrameDecoder, Network, SessionStatus, Target},
+};
+
+use crate::{
+    dns::Resolver,
+    inbound::{Duplex, Inbound, connect},
+    routing::{self, Destination, Outbound},
+    stream::CloseWrite,
+    udp::{self, IDLE_TIMEOUT, POLL_INTERVAL},
+    users::User,
+};
+
+/// Maximum amount of data carried by a single frame.
+const FRAME_DATA_SIZE: usize = 8192;
+/// Sessions open at once on a single client connection, each has its own thread.
+const MAX_SESSIONS: usize = 128;
+/// Frames queued for a session before reading from the client blocks.
+const UPLINK_QUEUE_SIZE: usize = 16;
+
+type SharedWriter = Arc<Mutex<Box<dyn CloseWrite + Send>>>;
+
+fn send(writer: &SharedWriter, frame: &Frame) -> Result<()> {
+    let raw = frame.to_raw()?;
+    writer
+        .lock()
+        .map_err(|_| anyhow!("Poisoned lock"))?
+        .write_all(&raw)?;
+    Ok(())
+}
+
+enum Uplink {
+    Data(Box<[u8]>, Option<Target>),
+    End,
+}
+
+/// Relay a session to the connection `stream` was opened with.
+fn tcp_session(
+    id: u16,
+    stream: Result<impl Duplex>,
+    rx: &Receiver<Uplink>,
+    writer: &SharedWriter,
+) -> Result<()> {
+    let mut stream = match stream {
+        Ok(stream) => stream,
+        Err(e) => {
+            send(writer, &Frame::end(id, true))?;
+            return Err(e);
+        }
+    };
+    let mut stream_read = stream.try_clone()?;
+
+    let remote_closed = Arc::new(AtomicBool::new(false));
+
+    let downlink = {
+        let writer = writer.clone();
+        let remote_closed = remote_closed.clone();
+
+        thread::spawn(move || -> Result<()> {
+            let mut buf = [0; FRAME_DATA_SIZE];
+            let res = (|| loop {
+                let n = stream_read.read(&mut buf)?;
+                if n == 0 {
+                    return Ok(());
+                }
+                send(&writer, &Frame::keep(id, &buf[..n]))?;
+            })();
+
+            if !remote_closed.load(Ordering::Relaxed) {
+                send(&writer, &Frame::end(id, res.is_err()))?;
+            }
+            res
+        })
+    };
+
+    loop {
+        match rx.recv() {
+            Ok(Uplink::Data(data, _)) => {
+                if let Err(e) = stream.write_all(&data) {
+                    tracing::debug!("Mux session {id} write failed: {e}");
+                    _ = stream.shutdown(Shutdown::Both);
+                    break;
+                }
+            }
+            // Client closed the session or the whole connection
+            Ok(Uplink::End) | Err(_) => {
+                remote_closed.store(true, Ordering::Relaxed);
+                _ = stream.shutdown(Shutdown::Both);
+                break;
+            }
+        }
+    }
+
+    downlink.join().map_err(|_| anyhow!("Downlink panicked"))?
+}
+
+fn socket_target(addr: SocketAddr) -> Target {
+    Target {
+        network: Network::UDP,
+        port: addr.port(),
+        addr: match addr {
+            SocketAddr::V4(addr) => Address::Ipv4(*addr.ip()),
+            SocketAddr::V6(addr) => Address::Ipv6(*addr.ip()),
+        },
+    }
+}
+
+fn udp_session(
+    id: u16,
+    target: &Target,
+    rx: &Receiver<Uplink>,
+    writer: &SharedWriter,
+    resolver: &Resolver,
+) -> Result<()> {
+    let default_target = match udp::resolve(&target.addr, target.port, resolver) {
+        Ok(addr) => addr,
+        Err(e) => {
+            send(writer, &Frame::end(id, true))?;
+            return Err(e.into());
+        }
+    };
+    let socket = udp::bind_for(default_target)?;
+    let socket_recv = socket.try_clone()?;
+
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let touch = {
+        let last_activity = last_activity.clone();
+        move || {
+            if let Ok(mut x) = last_activity.lock() {
+                *x = Instant::now();
+            }
+        }
+    };
+
+    let downlink = {
+        let writer = writer.clone();
+        let closed = closed.clone();
+        let touch = touch.clone();
+
+        thread::spawn(move || -> Result<()> {
+            let mut buf = [0; 0x1_0000];
+
+            loop {
+                match socket_recv.recv_from(&mut buf) {
+                    Ok((n, source)) => {
+                        touch();
+                        send(
+                            &writer,
+                            &Frame::keep_udp(id, socket_target(source), &buf[..n]),
+                        )?;
+                    }
+                    Err(e) if udp::is_timeout(&e) => {
+                        if closed.load(Ordering::Relaxed) {
+                            return Ok(());
+                        }
+
+                        let idle = last_activity
+                            .lock()
+                            .map_err(|_| anyhow!("Poisoned lock"))?
+                            .elapsed();
+                        if idle >= IDLE_TIMEOUT {
+                            closed.store(true, Ordering::Relaxed);
+                            return send(&writer, &Frame::end(id, false));
+                        }
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        })
+    };
+
+    while !closed.load(Ordering::Relaxed) {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Uplink::Data(data, packet_target)) => {
+                touch();
+                let addr = match packet_target {
+                    Some(t) => udp::resolve(&t.addr, t.port, resolver),
+                    None => Ok(default_target),
+                };
+                if let Err(e) = addr.and_then(|addr| socket.send_to(&data, addr)) {
+                    tracing::debug!("Mux session {id} UDP send failed: {e}");
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(Uplink::End) | Err(RecvTimeoutError::Disconnected) => {
+                closed.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    downlink.join().map_err(|_| anyhow!("Downlink panicked"))?
+}
+
+fn start_session(
+    id: u16,
+    target: Target,
+    outbound: Outbound,
+    resolver: Arc<Resolver>,
+    writer: &SharedWriter,
+) -> SyncSender<Uplink> {
+    let (tx, rx) = mpsc::sync_channel(UPLINK_QUEUE_SIZE);
+    let writer = writer.clone();
+
+    thread::spawn(move || {
+        let res = match (target.network, outbound) {
+            (Network::TCP, Outbound::Vless(server)) => {
+                let stream = server.connect(Command::TCP, target.addr, target.port);
+                tcp_session(id, stream, &rx, &writer)
+            }
+            (Network::TCP, _) => {
+                let stream = connect(&target.addr, target.port, &resolver).map_err(Into::into);
+                tcp_session(id, stream, &rx, &writer)
+            }
+            (Network::UDP, Outbound::Vless(_)) => send(&writer, &Frame::end(id, true))
+                .and_then(|()| bail!("UDP sessions can not be sent through VLESS outbounds")),
+            (Network::UDP, _) => udp_session(id, &target, &rx, &writer, &resolver),
+        };
+        if let Err(e) = res {
+            tracing::debug!("Mux session {id} ended with error: {e}");
+        }
+    });
+
+    tx
+}
+
+/// Demultiplex Mux.Cool sessions carried over a single client connection.
+pub fn serve(
+    mut reader: impl Read,
+    writer: impl CloseWrite + Send + 'static,
+    payload: &[u8],
+    user: &User,
+    inbound: &Inbound,
+) -> Result<()> {
+    let writer: SharedWriter = Arc::new(Mutex::new(Box::new(writer)));
+
+    let mut sessions = HashMap::<u16, SyncSender<Uplink>>::new();
+    let mut decoder = FrameDecoder::new();
+    decoder.push(payload);
+
+    let mut buf = [0; 0x4000];
+    loop {
+        while let Some(frame) = decoder.next_frame()? {
+            let id = frame.session_id;
+
+            match frame.status {
+                SessionStatus::New => {
+                    let mut target = frame.target.ok_or(anyhow!("New frame without target"))?;
+                    if sessions.len() >= MAX_SESSIONS {
+                        tracing::debug!("Mux session {id} refused, too many sessions");
+                        send(&writer, &Frame::end(id, true))?;
+                        continue;
+                    }
+                    if !user.allows_port(target.port) {
+                        tracing::warn!("Port {} is not allowed for {}", target.port, user.email);
+                        send(&writer, &Frame::end(id, true))?;
+                        continue;
+                    }
+
+                    let network = match target.network {
+                        Network::TCP => routing::Network::Tcp,
+                        Network::UDP => routing::Network::Udp,
+                    };
+                    let data = frame.data.as_deref().unwrap_or_default();
+                    let (route_addr, addr) = inbound.sniffing.apply(&target.addr, data, network);
+                    target.addr = addr;
+
+                    let (tag, outbound) = inbound.router.route(&Destination {
+                        addr: &route_addr,
+                        port: target.port,
+                        network,
+                        user: user.uuid,
+                        inbound_tag: &inbound.tag,
+                    });
+                    tracing::debug!(
+                        "Mux session {id}: {:?} {}:{} via {tag}",
+                        target.network,
+                        route_addr,
+                        target.port
+                    );
+                    if let Outbound::Block = outbound {
+                        send(&writer, &Frame::end(id, true))?;
+                        continue;
+                    }
+
+                    let tx = start_session(
+                        id,
+                        target,
+                        outbound.clone(),
+                        inbound.resolver.clone(),
+                        &writer,
+                    );
+                    if let Some(data) = frame.data {
+                        _ = tx.send(Uplink::Data(data, None));
+                    }
+                    sessions.insert(id, tx);
+                }
+                SessionStatus::Keep => {
+                    let Some(tx) = sessions.get(&id) else {
+                        send(&writer, &Frame::end(id, true))?;
+                        continue;
+                    };
+                    if let Some(data) = frame.data
+                        && tx.send(Uplink::Data(data, frame.target)).is_err()
+                    {
+                        sessions.remove(&id);
+                    }
+                }
+                SessionStatus::End => {
+                    if let Some(tx) = sessions.remove(&id) {
+                        _ = tx.send(Uplink::End);
+                    }
+                }
+                SessionStatus::KeepAlive => {}
+            }
+        }
+
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        decoder.push(&buf[..n]);
+    }
+
+    // Dropping senders closes every remaining session
+    drop(sessions);
+
+    // Client may have closed the connection without waiting for `close_notify`
+    _ = writer.lock().map_err(|_| anyhow!("Poisoned lock"))?.close();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::{Ipv4Addr, TcpListener},
+        os::unix::net::UnixStream,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::users::Users;
+
+    /// Client end of a Mux.Cool connection served on another thread.
+    struct Client {
+        conn: UnixStream,
+        decoder: FrameDecoder,
+        server: thread::JoinHandle<Result<()>>,
+    }
+
+    impl Client {
+        fn start() -> Result<Self> {
+            let (conn, server) = UnixStream::pair()?;
+            conn.set_read_timeout(Some(Duration::from_secs(5)))?;
+            let server = thread::spawn(move || {
+                let user = User::unrestricted(1, "alice@example.com");
+                serve(
+                    server.try_clone()?,
+                    server,
+                    &[],
+                    &user,
+                    &Inbound::with_users(Users::default())?,
+                )
+            });
+
+            Ok(Self {
+                conn,
+                decoder: FrameDecoder::new(),
+                server,
+            })
+        }
+
+        fn send(&mut self, frame: &Frame) -> Result<()> {
+            Ok(self.conn.write_all(&frame.to_raw()?)?)
+        }
+
+        /// Next frame from the server, `None` once it closed the connection.
+        fn receive(&mut self) -> Result<Option<Frame>> {
+            let mut buf = [0; 0x4000];
+            loop {
+                if let Some(frame) = self.decoder.next_frame()? {
+                    return Ok(Some(frame));
+                }
+                match self.conn.read(&mut buf)? {
+                    0 => return Ok(None),
+                    n => self.decoder.push(&buf[..n]),
+                }
+            }
+        }
+
+        /// Data of `Keep` frames until `expected` was received.
+        fn receive_data(&mut self, id: u16, expected: &[u8]) -> Result<()> {
+            let mut data = Vec::<u8>::new();
+            while data.len() < expected.len() {
+                let frame = self.receive()?.ok_or(anyhow!("Connection closed"))?;
+                assert_eq!((frame.session_id, frame.status), (id, SessionStatus::Keep));
+                data.extend(frame.data.as_deref().unwrap_or_default());
+            }
+            assert_eq!(data, expected);
+            Ok(())
+        }
+
+        fn finish(mut self) -> Result<()> {
+            self.conn.shutdown(Shutdown::Write)?;
+            assert_eq!(self.receive()?, None);
+            self.server.join().map_err(|_| anyhow!("Mux panicked"))?
+        }
+    }
+
+    fn echo_server() -> Result<Target> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let port = listener.local_addr()?.port();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                thread::spawn(move || -> io::Result<()> {
+                    let mut conn = conn?;
+                    io::copy(&mut conn.try_clone()?, &mut conn)?;
+                    Ok(())
+                });
+            }
+        });
+
+        Ok(Target {
+            network: Network::TCP,
+            port,
+            addr: Address::Ipv4(Ipv4Addr::LOCALHOST),
+        })
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let target = echo_server()?;
+        let mut client = Client::start()?;
+
+        client.send(&Frame::new_session(1, target, Some(b"hello")))?;
+        client.send(&Frame::keep_alive())?;
+        client.send(&Frame::keep(1, b" world"))?;
+        client.receive_data(1, b"hello world")?;
+
+        // Data for a closed session is refused
+        client.send(&Frame::end(1, false))?;
+        client.send(&Frame::keep(1, b"again"))?;
+        assert_eq!(client.receive()?, Some(Frame::end(1, true)));
+
+        client.finish()
+    }
+
+    #[test]
+    fn test_session_limit() -> Result<()> {
+        let target = echo_server()?;
+        let mut client = Client::start()?;
+
+        for id in 0..=MAX_SESSIONS as u16 {
+            client.send(&Frame::new_session(id, target.clone(), None))?;
+        }
+        assert_eq!(
+            client.receive()?,
+            Some(Frame::end(MAX_SESSIONS as u16, true))
+        );
+
+        // Closing a session makes room for another
+        client.send(&Frame::end(0, false))?;
+        client.send(&Frame::new_session(0, target, Some(b"ping")))?;
+        client.receive_data(0, b"ping")?;
+
+        client.finish()
+    }
+}
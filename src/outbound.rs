@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, anyhow};
+use vless::{Addons, Address, Command, VlessError, VlessRequestHeader, VlessResponseHeader};
+
+use crate::{
+    inbound::Duplex,
+    stream::{TlsReader, TlsStream, TlsWriter},
+    tls_client::{self, CertificatePin},
+};
+
+/// VLESS server connections are forwarded to.
+#[derive(Debug)]
+pub struct VlessOutbound {
+    /// `"host:port"`
+    pub server: String,
+    pub server_name: String,
+    pub uuid: u128,
+    pub pin: CertificatePin,
+}
+
+impl VlessOutbound {
+    /// Server name defaults to the host of `server`.
+    pub fn new(
+        server: &str,
+        server_name: Option<&str>,
+        uuid: &str,
+        pin: CertificatePin,
+    ) -> Result<Self> {
+        let (host, _port) = server
+            .rsplit_once(':')
+            .ok_or(anyhow!("Missing port in server address {server}"))?;
+
+        Ok(Self {
+            server: String::from(server),
+            server_name: server_name
+                .unwrap_or(host.trim_matches(['[', ']']))
+                .to_lowercase(),
+            uuid: vless::parse_uuid(uuid)?,
+            pin,
+        })
+    }
+
+    /// Open a VLESS connection to the server and send the request header.
+    pub fn open(&self, command: Command, addr: Address, port: u16) -> Result<TlsStream> {
+        let conn = TcpStream::connect(&self.server)?;
+        let (mut stream, _) = tls_client::connect(conn, &self.server_name, &[], self.pin)?;
+
+        let header = VlessRequestHeader::new(self.uuid, Addons::default(), command, port, addr);
+        stream.write_all(&header.to_raw()?)?;
+
+        Ok(stream)
+    }
+
+    /// Open a connection usable as a relay destination.
+    pub fn connect(&self, command: Command, addr: Address, port: u16) -> Result<Upstream> {
+        let (reader, writer) = self.open(command, addr, port)?.split();
+        Ok(Upstream {
+            reader: Arc::new(Mutex::new(ResponseReader::new(reader))),
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
+}
+
+/// Server data with the response header stripped.
+///
+/// The header is read on first use, so that the request payload is not
+/// held back waiting for the server
+pub struct ResponseReader {
+    inner: TlsReader,
+    header_read: bool,
+
+    buf: Box<[u8]>,
+    pos: usize,
+}
+
+impl ResponseReader {
+    pub fn new(inner: TlsReader) -> Self {
+        Self {
+            inner,
+            header_read: false,
+            buf: Box::new([]),
+            pos: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 4096];
+
+        loop {
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Server closed the connection before response header",
+                ));
+            }
+            buf.extend(&chunk[..n]);
+
+            match VlessResponseHeader::from_raw(&buf) {
+                Ok((_, size)) => {
+                    self.buf = Box::from(&buf[size..]);
+                    self.header_read = true;
+                    return Ok(());
+                }
+                Err(e) if matches!(e.downcast_ref(), Some(VlessError::Truncated)) => {}
+                Err(e) => return Err(io::Error::other(e)),
+            }
+        }
+    }
+}
+
+impl Read for ResponseReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.header_read {
+            self.read_header()?;
+        }
+        if self.pos == self.buf.len() {
+            return self.inner.read(buf);
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// VLESS connection to another server, clones share the same connection.
+pub struct Upstream {
+    reader: Arc<Mutex<ResponseReader>>,
+    writer: Arc<Mutex<TlsWriter>>,
+}
+
+fn poisoned<T>(_: T) -> io::Error {
+    io::Error::other("Poisoned lock")
+}
+
+impl Read for Upstream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.lock().map_err(poisoned)?.read(buf)
+    }
+}
+
+impl Write for Upstream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.lock().map_err(poisoned)?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.lock().map_err(poisoned)?.flush()
+    }
+}
+
+impl Duplex for Upstream {
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            reader: self.reader.clone(),
+            writer: self.writer.clone(),
+        })
+    }
+
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        let mut writer = self.writer.lock().map_err(poisoned)?;
+        match how {
+            Shutdown::Write => writer.close(),
+            Shutdown::Read | Shutdown::Both => writer.shutdown(),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, PoisonError, RwLock, mpsc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+use crate::{config::Config, inbound::Inbound, stats::Stats};
+
+/// How often watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Value replaced as a whole, readers keep the version they loaded.
+pub struct Swap<T> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T> Swap<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(value)),
+        }
+    }
+
+    pub fn load(&self) -> Arc<T> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn store(&self, value: T) {
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(value);
+    }
+}
+
+/// Listener of an inbound, its settings are swapped on reload.
+pub struct Listener {
+    pub listen: String,
+    pub inbound: Arc<Swap<Inbound>>,
+}
+
+fn modified(files: &[PathBuf]) -> HashMap<PathBuf, Option<SystemTime>> {
+    files
+        .iter()
+        .map(|path| {
+            let time = fs::metadata(path).and_then(|x| x.modified()).ok();
+            (path.clone(), time)
+        })
+        .collect()
+}
+
+/// Swap in the inbounds of a new configuration.
+///
+/// Listeners cannot be added, removed or moved without a restart,
+/// nothing is swapped if they differ
+fn apply(config: Config, listeners: &[Listener]) -> Result<()> {
+    if config.inbounds.len() != listeners.len() {
+        bail!("Inbounds were added or removed, restart to apply");
+    }
+    for ((listen, inbound), listener) in config.inbounds.iter().zip(listeners) {
+        if *listen != listener.listen || inbound.tag != listener.inbound.load().tag {
+            bail!(
+                "Inbound {} changed its listener, restart to apply",
+                inbound.tag
+            );
+        }
+    }
+
+    for ((_, mut inbound), listener) in config.inbounds.into_iter().zip(listeners) {
+        inbound.users.carry_traffic(&listener.inbound.load().users);
+        tracing::info!(
+            "Reloaded inbound {}: {} users, {} fallbacks, {} routing rules",
+            inbound.tag,
+            inbound.users.len(),
+            inbound.fallbacks.len(),
+            inbound.router.len(),
+        );
+        listener.inbound.store(inbound);
+    }
+    Ok(())
+}
+
+/// Reload the configuration on SIGHUP or when one of its files changes.
+///
+/// Connections already accepted keep the settings they started with
+pub fn watch(
+    path: Option<String>,
+    mut files: Vec<PathBuf>,
+    stats: Arc<Stats>,
+    listeners: Vec<Listener>,
+) -> Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if tx.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    thread::spawn(move || {
+        let mut seen = modified(&files);
+
+        loop {
+            let reason = match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(()) => "SIGHUP",
+                Err(mpsc::RecvTimeoutError::Timeout) if modified(&files) != seen => "file change",
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            };
+            tracing::info!("Reloading configuration after {reason}");
+
+            // A broken edit is reported once, not on every poll
+            let res = Config::load(path.as_deref(), &stats).and_then(|config| {
+                let new_files = config.files.clone();
+                apply(config, &listeners).map(|()| new_files)
+            });
+            match res {
+                Ok(new_files) => files = new_files,
+                Err(e) => tracing::error!("Reload failed, keeping the old configuration: {e:#}"),
+            }
+            seen = modified(&files);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use vless::{Addons, Address, Command, VlessRequestHeader};
+
+    use super::*;
+    use crate::{
+        stats::StatsConfig,
+        users::{UserEntry, Users},
+    };
+
+    const ALICE: &str = "27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d";
+
+    fn inbound() -> Result<Inbound> {
+        let entry: UserEntry = toml::from_str(&format!(
+            "uuid = \"{ALICE}\"\nemail = \"alice@example.com\"\nquota = 100"
+        ))?;
+        Inbound::with_users(Users::from_entries(vec![entry])?)
+    }
+
+    #[test]
+    fn test_swap() {
+        let swap = Swap::new(1);
+        let old = swap.load();
+        swap.store(2);
+        assert_eq!(*old, 1);
+        assert_eq!(*swap.load(), 2);
+    }
+
+    #[test]
+    fn test_reload_keeps_quota() -> Result<()> {
+        let listener = Listener {
+            listen: String::from("127.0.0.1:443"),
+            inbound: Arc::new(Swap::new(inbound()?)),
+        };
+        let request = VlessRequestHeader::new(
+            vless::parse_uuid(ALICE)?,
+            Addons::default(),
+            Command::TCP,
+            443,
+            Address::Domain(String::from("example.com")),
+        );
+
+        // Connection started before the reload runs the user over quota afterwards
+        let user = listener.inbound.load().users.authorize(&request)?;
+        let config = Config {
+            inbounds: vec![(listener.listen.clone(), inbound()?)],
+            stats: StatsConfig::default(),
+            files: Vec::new(),
+        };
+        apply(config, std::slice::from_ref(&listener))?;
+        user.downlink.fetch_add(100, Ordering::Relaxed);
+
+        let e = listener.inbound.load().users.authorize(&request).err();
+        assert!(e.is_some_and(|x| x.to_string().contains("over quota")));
+
+        // Exhausted user stays rejected after another reload
+        let config = Config {
+            inbounds: vec![(listener.listen.clone(), inbound()?)],
+            stats: StatsConfig::default(),
+            files: Vec::new(),
+        };
+        apply(config, std::slice::from_ref(&listener))?;
+        assert!(listener.inbound.load().users.authorize(&request).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    ops::RangeInclusive,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail};
+use regex_automata::meta::Regex;
+use serde::Deserialize;
+use vless::Address;
+
+use crate::{outbound::VlessOutbound, tls_client::CertificatePin, users::PortSpec};
+
+/// Tags of the outbounds that always exist.
+pub const DIRECT: &str = "direct";
+pub const BLOCK: &str = "block";
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Tcp,
+    Udp,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum OutboundEntry {
+    Direct {
+        tag: String,
+    },
+    Block {
+        tag: String,
+    },
+    Vless {
+        tag: String,
+        /// `"host:port"`
+        server: String,
+        server_name: Option<String>,
+        uuid: String,
+        /// SHA-256 fingerprint of the server certificate
+        pin: Option<String>,
+        /// Accept any server certificate if there is no pin
+        #[serde(default)]
+        insecure: bool,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RuleEntry {
+    #[serde(default)]
+    domain: Vec<String>,
+    #[serde(default)]
+    ip: Vec<String>,
+    #[serde(default)]
+    port: Vec<PortSpec>,
+    #[serde(default)]
+    user: Vec<String>,
+    #[serde(default)]
+    inbound_tag: Vec<String>,
+    #[serde(default)]
+    network: Vec<Network>,
+    outbound: String,
+}
+
+/// Where connections are sent.
+#[derive(Clone, Debug)]
+pub enum Outbound {
+    /// Straight to the destination
+    Direct,
+    /// Closed without connecting anywhere
+    Block,
+    /// Through another VLESS server
+    Vless(Arc<VlessOutbound>),
+}
+
+#[derive(Debug)]
+enum DomainMatcher {
+    Full(String),
+    /// The domain itself and its subdomains
+    Suffix(String),
+    Keyword(String),
+    Regex(Regex),
+}
+
+impl DomainMatcher {
+    /// Xray style `full:`, `domain:`, `keyword:` or `regexp:` prefixed pattern,
+    /// plain patterns are keywords
+    fn parse(pattern: &str) -> Result<Self> {
+        let (kind, value) = pattern.split_once(':').unwrap_or(("keyword", pattern));
+        Ok(match kind {
+            "full" => Self::Full(value.to_lowercase()),
+            "domain" => Self::Suffix(value.to_lowercase()),
+            "keyword" => Self::Keyword(value.to_lowercase()),
+            "regexp" => Self::Regex(Regex::new(value)?),
+            _ => bail!("Unknown domain pattern type `{kind}`"),
+        })
+    }
+
+    fn matches(&self, domain: &str) -> bool {
+        match self {
+            Self::Full(x) => domain == x,
+            Self::Suffix(x) => domain
+                .strip_suffix(x.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.ends_with('.')),
+            Self::Keyword(x) => domain.contains(x.as_str()),
+            Self::Regex(x) => x.is_match(domain),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// `"addr/prefix"`, a bare address matches only itself
+    fn parse(value: &str) -> Result<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr.parse()?, Some(prefix.parse()?)),
+            None => (value.parse()?, None),
+        };
+        let max_prefix = if matches!(addr, IpAddr::V4(_)) {
+            32
+        } else {
+            128
+        };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            bail!("Prefix length  is too long");
+        }
+        Ok(Self { addr, prefix })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Conditions of a rule, empty lists match anything.
+#[derive(Debug)]
+struct Rule {
+    domains: Vec<DomainMatcher>,
+    ips: Vec<Cidr>,
+    ports: Vec<RangeInclusive<u16>>,
+    users: Vec<u128>,
+    inbound_tags: Vec<String>,
+    networks: Vec<Network>,
+    outbound: String,
+}
+
+impl TryFrom<RuleEntry> for Rule {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RuleEntry) -> Result<Self, Self::Error> {
+        Ok(Self {
+            domains: value
+                .domain
+                .iter()
+                .map(|x| DomainMatcher::parse(x).with_context(|| format!("Invalid domain {x}")))
+                .collect::<Result<_>>()?,
+            ips: value
+                .ip
+                .iter()
+                .map(|x| Cidr::parse(x).with_context(|| format!("Invalid IP range {x}")))
+                .collect::<Result<_>>()?,
+            ports: value
+                .port
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_>>()?,
+            users: value
+                .user
+                .iter()
+                .map(|x| vless::parse_uuid(x))
+                .collect::<Result<_>>()?,
+            inbound_tags: value.inbound_tag,
+            networks: value.network,
+            outbound: value.outbound,
+        })
+    }
+}
+
+/// What a routing decision is made on.
+pub struct Destination<'a> {
+    pub addr: &'a Address,
+    pub port: u16,
+    pub network: Network,
+    pub user: u128,
+    pub inbound_tag: &'a str,
+}
+
+impl Rule {
+    fn matches(&self, dest: &Destination) -> bool {
+        // Domain rules only match domains and IP rules only addresses, nothing is resolved
+        let domain_matches = self.domains.is_empty()
+            || matches!(dest.addr, Address::Domain(domain) if {
+                let domain = domain.trim_end_matches('.').to_lowercase();
+                self.domains.iter().any(|x| x.matches(&domain))
+            });
+        let ip = match dest.addr {
+            Address::Ipv4(ip) => Some(IpAddr::V4(*ip)),
+            Address::Ipv6(ip) => {
+                Some(Ipv6Addr::to_ipv4_mapped(ip).map_or(IpAddr::V6(*ip), IpAddr::V4))
+            }
+            Address::Domain(_) => None,
+        };
+        let ip_matches =
+            self.ips.is_empty() || ip.is_some_and(|ip| self.ips.iter().any(|x| x.contains(ip)));
+
+        domain_matches
+            && ip_matches
+            && (self.ports.is_empty() || self.ports.iter().any(|x| x.contains(&dest.port)))
+            && (self.users.is_empty() || self.users.contains(&dest.user))
+            && (self.inbound_tags.is_empty()
+                || self.inbound_tags.iter().any(|x| x == dest.inbound_tag))
+            && (self.networks.is_empty() || self.networks.contains(&dest.network))
+    }
+}
+
+/// Picks an outbound for every connection, the first matching rule wins.
+#[derive(Debug)]
+pub struct Router {
+    outbounds: HashMap<String, Outbound>,
+    rules: Vec<Rule>,
+    default: String,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self {
+            outbounds: HashMap::from([
+                (String::from(DIRECT), Outbound::Direct),
+                (String::from(BLOCK), Outbound::Block),
+            ]),
+            rules: Vec::new(),
+            default: String::from(DIRECT),
+        }
+    }
+}
+
+impl Router {
+    /// Outbound tags are checked here, `direct` and `block` always exist.
+    pub fn new(
+        outbounds: Vec<OutboundEntry>,
+        rules: Vec<RuleEntry>,
+        default: Option<String>,
+    ) -> Result<Self> {
+        let mut res = Self::default();
+
+        for (i, entry) in outbounds.into_iter().enumerate() {
+            let (tag, outbound) = match entry {
+                OutboundEntry::Direct { tag } => (tag, Outbound::Direct),
+                OutboundEntry::Block { tag } => (tag, Outbound::Block),
+                OutboundEntry::Vless {
+                    tag,
+                    server,
+                    server_name,
+                    uuid,
+                    pin,
+                    insecure,
+                } => {
+                    let outbound = CertificatePin::new(pin.as_deref(), insecure)
+                        .and_then(|pin| {
+                            VlessOutbound::new(&server, server_name.as_deref(), &uuid, pin)
+                        })
+                        .with_context(|| format!("outbound[{i}]: Invalid outbound {tag}"))?;
+                    (tag, Outbound::Vless(Arc::new(outbound)))
+                }
+            };
+            if res.outbounds.insert(tag.clone(), outbound).is_some() {
+                bail!("outbound[{i}].tag: Duplicate outbound tag {tag}");
+            }
+        }
+
+        for (i, entry) in rules.into_iter().enumerate() {
+            let rule = Rule::try_from(entry).with_context(|| format!("rule[{i}]"))?;
+            if !res.outbounds.contains_key(&rule.outbound) {
+                bail!("rule[{i}].outbound: Unknown outbound {}", rule.outbound);
+            }
+            res.rules.push(rule);
+        }
+
+        if let Some(default) = default {
+            if !res.outbounds.contains_key(&default) {
+                bail!("default: Unknown outbound {default}");
+            }
+            res.default = default;
+        }
+
+        Ok(res)
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Outbound tag and outbound for a connection.
+    pub fn route(&self, dest: &Destination) -> (&str, &Outbound) {
+        let tag = self
+            .rules
+            .iter()
+            .find(|x| x.matches(dest))
+            .map_or(&self.default, |x| &x.outbound);
+        // Tags are checked on load
+        (tag, &self.outbounds[tag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct RoutingFile {
+        /// Outbound used when no rule matches
+        default: Option<String>,
+        #[serde(default, rename = "outbound")]
+        outbounds: Vec<OutboundEntry>,
+        #[serde(default, rename = "rule")]
+        rules: Vec<RuleEntry>,
+    }
+
+    impl Router {
+        fn parse(raw: &str) -> Result<Self> {
+            let file: RoutingFile = toml::from_str(raw)?;
+            Self::new(file.outbounds, file.rules, file.default)
+        }
+    }
+
+    const ROUTING: &str = r#"
+        default = "proxy"
+
+        [[outbound]]
+        type = "vless"
+        tag = "proxy"
+        server = "example.com:443"
+        uuid = "27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d"
+        pin = "9f:86:d0:81:88:4c:7d:65:9a:2f:ea:a0:c5:5a:d0:15:a3:bf:4f:1b:2b:0b:82:2c:d1:5d:6c:15:b0:f0:0a:08"
+
+        [[rule]]
+        domain = ["domain:ads.example", "regexp:^track\\d+\\."]
+        outbound = "block"
+
+        [[rule]]
+        ip = ["10.0.0.0/8", "::1"]
+        port = [22, "8000-8080"]
+        outbound = "direct"
+
+        [[rule]]
+        network = ["udp"]
+        inbound_tag = ["vless-in"]
+        user = ["27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d"]
+        outbound = "block"
+    "#;
+
+    fn route(router: &Router, addr: &str, port: u16, network: Network) -> String {
+        let addr = match addr.parse() {
+            Ok(IpAddr::V4(ip)) => Address::Ipv4(ip),
+            Ok(IpAddr::V6(ip)) => Address::Ipv6(ip),
+            Err(_) => Address::Domain(String::from(addr)),
+        };
+        let dest = Destination {
+            addr: &addr,
+            port,
+            network,
+            user: vless::parse_uuid("27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d").unwrap(),
+            inbound_tag: "vless-in",
+        };
+        String::from(router.route(&dest).0)
+    }
+
+    #[test]
+    fn test_route() {
+        let router = Router::parse(ROUTING).unwrap();
+        assert_eq!(route(&router, "ads.example", 443, Network::Tcp), "block");
+        assert_eq!(route(&router, "x.Ads.Example.", 443, Network::Tcp), "block");
+        assert_eq!(route(&router, "badads.example", 443, Network::Tcp), "proxy");
+        assert_eq!(
+            route(&router, "track12.example.org", 80, Network::Tcp),
+            "block"
+        );
+        assert_eq!(route(&router, "10.1.2.3", 8080, Network::Tcp), "direct");
+        assert_eq!(route(&router, "10.1.2.3", 443, Network::Tcp), "proxy");
+        assert_eq!(route(&router, "::1", 22, Network::Tcp), "direct");
+        assert_eq!(
+            route(&router, "::ffff:10.0.0.1", 22, Network::Tcp),
+            "direct"
+        );
+        assert_eq!(route(&router, "11.0.0.1", 22, Network::Tcp), "proxy");
+        assert_eq!(route(&router, "11.0.0.1", 53, Network::Udp), "block");
+
+        assert_eq!(
+            route(&Router::default(), "example.com", 443, Network::Tcp),
+            "direct"
+        );
+
+        assert!(Router::parse("[[rule]]\noutbound = \"missing\"").is_err());
+        assert!(Router::parse("[[rule]]\nip = [\"10.0.0.0/33\"]\noutbound = \"direct\"").is_err());
+        assert!(Router::parse("[[outbound]]\ntype = \"block\"\ntag = \"direct\"").is_err());
+        // Vless outbounds need a certificate pin or `insecure`
+        let vless = "[[outbound]]\ntype = \"vless\"\ntag = \"proxy\"\nserver = \"example.com:443\"\n\
+                     uuid = \"27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d\"";
+        assert!(Router::parse(vless).is_err());
+        assert!(Router::parse(&format!("{vless}\ninsecure = true")).is_ok());
+    }
+}
@@ -0,0 +1,414 @@
+use std::net::IpAddr;
+
+use crypt::{
+    aead::aes_gcm::decrypt_aes_128_gcm,
+    block_cipher::aes::{Aes, Aes128Cipher},
+    hash::sha::Sha256,
+};
+use serde::Deserialize;
+use tls::{
+    hkdf::{hkdf_expand_label, hkdf_extract},
+    record::{
+        content_types,
+        handshake::{extension::extension_types, handshake_types},
+    },
+};
+use vless::Address;
+
+use crate::{http::RequestHead, routing::Network};
+
+/// QUIC version 1, RFC 9000.
+const QUIC_V1: u32 = 1;
+
+/// RFC 9001 section 5.2.
+const QUIC_V1_INITIAL_SALT: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+
+/// `NameType` of a DNS host name in the server_name extension, RFC 6066.
+const HOST_NAME: u8 = 0;
+
+mod quic_frame_types {
+    pub const PADDING: u64 = 0x00;
+    pub const PING: u64 = 0x01;
+    pub const CRYPTO: u64 = 0x06;
+}
+
+/// Size of the AEAD tag and of the header protection sample.
+const QUIC_TAG_SIZE: usize = 16;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Http,
+    Tls,
+    Quic,
+}
+
+fn all_protocols() -> Vec<Protocol> {
+    Vec::from([Protocol::Http, Protocol::Tls, Protocol::Quic])
+}
+
+/// Which hostnames found in the first payload replace the requested destination.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Sniffing {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "all_protocols")]
+    pub dest_override: Vec<Protocol>,
+    /// Route on the sniffed domain but connect to the requested address
+    #[serde(default)]
+    pub route_only: bool,
+}
+
+impl Default for Sniffing {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dest_override: all_protocols(),
+            route_only: false,
+        }
+    }
+}
+
+impl Sniffing {
+    /// Addresses to route on and to connect to.
+    ///
+    /// `data` is the first payload, a single packet for UDP
+    pub fn apply(&self, addr: &Address, data: &[u8], network: Network) -> (Address, Address) {
+        let sniffed = if self.enabled {
+            sniff(data, network).filter(|(protocol, _)| self.dest_override.contains(protocol))
+        } else {
+            None
+        };
+
+        match sniffed {
+            Some((protocol, domain)) => {
+                tracing::debug!("Sniffed {protocol:?} domain {domain} for {addr}");
+                let domain = Address::Domain(domain);
+                let connect = if self.route_only {
+                    addr.clone()
+                } else {
+                    domain.clone()
+                };
+                (domain, connect)
+            }
+            None => (addr.clone(), addr.clone()),
+        }
+    }
+}
+
+/// Hostname the payload is meant for, if it belongs to a known protocol.
+pub fn sniff(data: &[u8], network: Network) -> Option<(Protocol, String)> {
+    let (protocol, name) = match network {
+        Network::Tcp => match data.first() {
+            Some(&content_types::HANDSHAKE) => (Protocol::Tls, tls_server_name(data)?),
+            _ => (Protocol::Http, http_host(data)?),
+        },
+        Network::Udp => (Protocol::Quic, quic_server_name(data)?),
+    };
+
+    let name = name.trim_end_matches('.').to_lowercase();
+    let valid = !name.is_empty()
+        && name.len() <= 253
+        && name.parse::<IpAddr>().is_err()
+        && name
+            .bytes()
+            .all(|x| x.is_ascii_alphanumeric() || matches!(x, b'-' | b'.' | b'_'));
+    valid.then_some((protocol, name))
+}
+
+fn http_host(data: &[u8]) -> Option<String> {
+    let (head, _) = RequestHead::parse(data).ok()??;
+    head.host().map(String::from)
+}
+
+/// Split off a vector with a `prefix` byte length.
+fn read_vector(data: &[u8], prefix: usize) -> Option<(&[u8], &[u8])> {
+    let (length, rest) = data.split_at_checked(prefix)?;
+    let length = length.iter().fold(0, |acc, &x| acc << 8 | usize::from(x));
+    rest.split_at_checked(length)
+}
+
+/// Server name of a complete ClientHello handshake message.
+///
+/// Only vector lengths are walked, the rest of the message is not validated
+fn client_hello_server_name(handshake: &[u8]) -> Option<String> {
+    let (&msg_type, rest) = handshake.split_first()?;
+    if msg_type != handshake_types::CLIENT_HELLO {
+        return None;
+    }
+    let (body, _) = read_vector(rest, 3)?;
+
+    // Version and random, then session ID, cipher suites and compression methods
+    let mut data = body.get(2 + 32..)?;
+    for prefix in [1, 2, 1] {
+        data = read_vector(data, prefix)?.1;
+    }
+    let (mut extensions, _) = read_vector(data, 2)?;
+    while !extensions.is_empty() {
+        let (extension_type, rest) = extensions.split_at_checked(2)?;
+        let (extension, rest) = read_vector(rest, 2)?;
+        extensions = rest;
+        if extension_type != extension_types::SERVER_NAME.to_be_bytes() {
+            continue;
+        }
+
+        // Entries are a name type followed by the name
+        let (mut list, _) = read_vector(extension, 2)?;
+        while let Some((&name_type, rest)) = list.split_first() {
+            let (name, rest) = read_vector(rest, 2)?;
+            list = rest;
+            if name_type == HOST_NAME {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+        return None;
+    }
+    None
+}
+
+/// Server name of a ClientHello in the leading handshake records.
+fn tls_server_name(mut data: &[u8]) -> Option<String> {
+    let mut handshake = Vec::new();
+    while data.first() == Some(&content_types::HANDSHAKE) {
+        let Some((fragment, rest)) = data.get(3..).and_then(|x| read_vector(x, 2)) else {
+            break;
+        };
+        handshake.extend(fragment);
+        data = rest;
+    }
+    client_hello_server_name(&handshake)
+}
+
+/// QUIC variable length integer, RFC 9000 section 16.
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let first = *data.first()?;
+    let length = 1 << (first >> 6);
+    let (raw, rest) = data.split_at_checked(length)?;
+    *data = rest;
+    Some(
+        raw[1..]
+            .iter()
+            .fold(u64::from(first & 0x3F), |acc, &x| acc << 8 | u64::from(x)),
+    )
+}
+
+/// Packet protection keys of client Initial packets: key, IV and header protection key.
+fn quic_initial_keys(dcid: &[u8]) -> Option<([u8; 16], [u8; 12], [u8; 16])> {
+    let initial_secret = hkdf_extract::<Sha256>(&QUIC_V1_INITIAL_SALT, dcid);
+    let secret = hkdf_expand_label::<Sha256>(&initial_secret, "client in", &[], 32);
+
+    Some((
+        (*hkdf_expand_label::<Sha256>(&secret, "quic key", &[], 16))
+            .try_into()
+            .ok()?,
+        (*hkdf_expand_label::<Sha256>(&secret, "quic iv", &[], 12))
+            .try_into()
+            .ok()?,
+        (*hkdf_expand_label::<Sha256>(&secret, "quic hp", &[], 16))
+            .try_into()
+            .ok()?,
+    ))
+}
+
+/// Decrypt a client Initial packet, RFC 9001 section 5.
+///
+/// Returns the frames of the packet
+fn quic_initial_payload(packet: &[u8]) -> Option<Box<[u8]>> {
+    let first = *packet.first()?;
+    // Long header with the fixed bit, packet type Initial
+    if first & 0xF0 != 0xC0 {
+        return None;
+    }
+    let version = u32::from_be_bytes(packet.get(1..5)?.try_into().ok()?);
+    if version != QUIC_V1 {
+        return None;
+    }
+
+    let mut data = &packet[5..];
+    let (dcid, rest) = read_vector(data, 1)?;
+    let (_scid, rest) = read_vector(rest, 1)?;
+    data = rest;
+    let token_length = usize::try_from(read_varint(&mut data)?).ok()?;
+    data = data.get(token_length..)?;
+    let length = usize::try_from(read_varint(&mut data)?).ok()?;
+
+    let pn_offset = packet.len() - data.len();
+    let packet = packet.get(..pn_offset + length)?;
+    let (key, iv, hp) = quic_initial_keys(dcid)?;
+
+    // Header protection, the sample starts 4 bytes after the packet number offset
+    let sample = packet.get((pn_offset + 4)..(pn_offset + 4 + QUIC_TAG_SIZE))?;
+    let mask = Aes::new(Aes128Cipher::new(&hp)).encrypt(sample);
+    let mut header = Vec::from(&packet[..pn_offset]);
+    header[0] ^= mask[0] & 0x0F;
+    let pn_length = usize::from(header[0] & 0x03) + 1;
+    let mut packet_number = [0; 8];
+    for i in 0..pn_length {
+        let byte = packet.get(pn_offset + i)? ^ mask[1 + i];
+        header.push(byte);
+        packet_number[8 - pn_length + i] = byte;
+    }
+
+    let mut nonce = iv;
+    for (x, y) in nonce[4..].iter_mut().zip(packet_number) {
+        *x ^= y;
+    }
+
+    let (ciphertext, tag) = packet
+        .get(header.len()..)?
+        .split_at_checked(length.checked_sub(pn_length + QUIC_TAG_SIZE)?)?;
+    decrypt_aes_128_gcm(&key, &nonce, ciphertext, &header, tag).ok()
+}
+
+/// Server name of a ClientHello carried by a QUIC Initial packet.
+///
+/// The ClientHello has to fit into the first packet
+fn quic_server_name(packet: &[u8]) -> Option<String> {
+    let mut frames = &*quic_initial_payload(packet)?;
+
+    // Crypto frames may come in any order
+    let mut chunks = Vec::new();
+    while !frames.is_empty() {
+        match read_varint(&mut frames)? {
+            quic_frame_types::PADDING | quic_frame_types::PING => {}
+            quic_frame_types::CRYPTO => {
+                let offset = usize::try_from(read_varint(&mut frames)?).ok()?;
+                let length = usize::try_from(read_varint(&mut frames)?).ok()?;
+                let (data, rest) = frames.split_at_checked(length)?;
+                chunks.push((offset, data));
+                frames = rest;
+            }
+            _ => break,
+        }
+    }
+    chunks.sort_by_key(|(offset, _)| *offset);
+
+    let mut crypto = Vec::new();
+    for (offset, data) in chunks {
+        if offset > crypto.len() {
+            break;
+        }
+        crypto.extend(data.get((crypto.len() - offset)..).unwrap_or_default());
+    }
+    client_hello_server_name(&crypto)
+}
+
+#[cfg(test)]
+mod tests {
+    use crypt::aead::aes_gcm::encrypt_aes_128_gcm;
+    use tls::{
+        cipher_suite::TLS_AES_128_GCM_SHA256,
+        record::{
+            TlsPlaintext,
+            handshake::{
+                Handshake,
+                client_hello::{ClientHello, ClientHelloExtension},
+            },
+        },
+    };
+
+    use super::*;
+
+    /// ClientHello record.
+    fn client_hello(name: &str) -> Box<[u8]> {
+        let client_hello = ClientHello::new(
+            &[7; 32],
+            &[],
+            &[TLS_AES_128_GCM_SHA256],
+            &[
+                ClientHelloExtension::new_supported_versions(&[0x0304]).unwrap(),
+                ClientHelloExtension::new_server_name(name.as_bytes()).unwrap(),
+            ],
+        )
+        .unwrap();
+        TlsPlaintext::new_handshake(Handshake::ClientHello(client_hello))
+            .unwrap()
+            .to_raw()
+    }
+
+    /// Client Initial packet with a single CRYPTO frame split in two, padded to 1200 bytes.
+    fn quic_initial(dcid: &[u8], crypto: &[u8]) -> Box<[u8]> {
+        let (first, second) = crypto.split_at(crypto.len() / 2);
+        let mut frames = Vec::new();
+        for (offset, data) in [(first.len(), second), (0, first)] {
+            frames.push(0x06);
+            frames.extend([0x40 | (offset >> 8) as u8, offset as u8]);
+            frames.extend([0x40 | (data.len() >> 8) as u8, data.len() as u8]);
+            frames.extend(data);
+        }
+        frames.resize(1100, 0);
+
+        let length = 2 + frames.len() + QUIC_TAG_SIZE;
+        let mut header = Vec::from([0xC1, 0, 0, 0, 1, dcid.len() as u8]);
+        header.extend(dcid);
+        header.extend([0, 0, 0x40 | (length >> 8) as u8, length as u8]);
+        let pn_offset = header.len();
+        header.extend([0, 2]);
+
+        let (key, iv, hp) = quic_initial_keys(dcid).unwrap();
+        let mut nonce = iv;
+        nonce[11] ^= 2;
+        let (ciphertext, tag) = encrypt_aes_128_gcm(&key, &nonce, &frames, &header).unwrap();
+
+        let mut packet = [header, ciphertext.to_vec(), tag.to_vec()].concat();
+        let sample = &packet[(pn_offset + 4)..(pn_offset + 4 + QUIC_TAG_SIZE)];
+        let mask = Aes::new(Aes128Cipher::new(&hp)).encrypt(sample);
+        packet[0] ^= mask[0] & 0x0F;
+        packet[pn_offset] ^= mask[1];
+        packet[pn_offset + 1] ^= mask[2];
+        packet.into_boxed_slice()
+    }
+
+    #[test]
+    fn test_sniff() {
+        let record = client_hello("Example.COM");
+        assert_eq!(
+            sniff(&record, Network::Tcp),
+            Some((Protocol::Tls, String::from("example.com")))
+        );
+        assert_eq!(sniff(&record[..record.len() - 1], Network::Tcp), None);
+
+        // Empty server_name extension body
+        let body = [
+            &[3, 3][..],
+            &[7; 32],
+            &[0, 0, 2, 0x13, 1, 1, 0, 0, 4, 0, 0, 0, 0],
+        ]
+        .concat();
+        let record = [
+            &[22, 3, 1, 0, 4 + body.len() as u8, 1, 0, 0, body.len() as u8][..],
+            &body,
+        ]
+        .concat();
+        assert_eq!(sniff(&record, Network::Tcp), None);
+
+        let request = b"GET / HTTP/1.1\r\nHost: example.org:8080\r\n\r\n";
+        assert_eq!(
+            sniff(request, Network::Tcp),
+            Some((Protocol::Http, String::from("example.org")))
+        );
+        assert_eq!(
+            sniff(b"GET / HTTP/1.1\r\nHost: 10.0.0.1\r\n\r\n", Network::Tcp),
+            None
+        );
+        assert_eq!(sniff(b"SSH-2.0-OpenSSH_9.6\r\n", Network::Tcp), None);
+
+        // RFC 9001 appendix A.1
+        let dcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+        let (key, iv, hp) = quic_initial_keys(&dcid).unwrap();
+        assert_eq!(key[..4], [0x1f, 0x36, 0x96, 0x13]);
+        assert_eq!(iv[..4], [0xfa, 0x04, 0x4b, 0x2f]);
+        assert_eq!(hp[..4], [0x9f, 0x50, 0x44, 0x9e]);
+
+        let packet = quic_initial(&dcid, &client_hello("quic.example.net")[5..]);
+        assert_eq!(
+            sniff(&packet, Network::Udp),
+            Some((Protocol::Quic, String::from("quic.example.net")))
+        );
+        assert_eq!(sniff(&packet[..packet.len() - 1], Network::Udp), None);
+    }
+}
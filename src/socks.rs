@@ -0,0 +1,145 @@
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+use anyhow::{Result, anyhow, bail};
+use vless::Address;
+
+const VERSION: u8 = 5;
+
+const NO_AUTHENTICATION: u8 = 0x00;
+const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+
+const CONNECT: u8 = 0x01;
+
+pub mod replies {
+    pub const SUCCEEDED: u8 = 0x00;
+    pub const GENERAL_FAILURE: u8 = 0x01;
+    pub const COMMAND_NOT_SUPPORTED: u8 = 0x07;
+    pub const ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+}
+
+mod address_types {
+    pub const IPV4: u8 = 0x01;
+    pub const DOMAIN: u8 = 0x03;
+    pub const IPV6: u8 = 0x04;
+}
+
+fn read_array<const N: usize>(stream: &mut impl Read) -> Result<[u8; N]> {
+    let mut buf = [0; N];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Negotiate the method and read a SOCKS5 request.
+///
+/// Only unauthenticated `CONNECT` is supported, anything else is answered
+/// with an error reply. Returns the requested destination
+pub fn accept(stream: &mut (impl Read + Write)) -> Result<(Address, u16)> {
+    let [version, methods_length] = read_array(stream)?;
+    if version != VERSION {
+        bail!("Unsupported SOCKS version: {version}");
+    }
+    let mut methods = vec![0; methods_length as usize];
+    stream.read_exact(&mut methods)?;
+
+    if !methods.contains(&NO_AUTHENTICATION) {
+        stream.write_all(&[VERSION, NO_ACCEPTABLE_METHODS])?;
+        bail!("Client does not support unauthenticated access");
+    }
+    stream.write_all(&[VERSION, NO_AUTHENTICATION])?;
+
+    let [version, command, _reserved, address_type] = read_array(stream)?;
+    if version != VERSION {
+        bail!("Unsupported SOCKS version: {version}");
+    }
+
+    let addr = match address_type {
+        address_types::IPV4 => Address::Ipv4(Ipv4Addr::from(read_array::<4>(stream)?)),
+        address_types::DOMAIN => {
+            let [length] = read_array(stream)?;
+            let mut domain = vec![0; length as usize];
+            stream.read_exact(&mut domain)?;
+            Address::Domain(String::from_utf8(domain).map_err(|_| anyhow!("Invalid domain"))?)
+        }
+        address_types::IPV6 => Address::Ipv6(Ipv6Addr::from(read_array::<16>(stream)?)),
+        _ => {
+            reply(stream, replies::ADDRESS_TYPE_NOT_SUPPORTED)?;
+            bail!("Unknown SOCKS address type: {address_type}");
+        }
+    };
+    let port = u16::from_be_bytes(read_array(stream)?);
+
+    if command != CONNECT {
+        reply(stream, replies::COMMAND_NOT_SUPPORTED)?;
+        bail!("Unsupported SOCKS command: {command}");
+    }
+
+    Ok((addr, port))
+}
+
+/// Answer the request, the bound address is not reported.
+pub fn reply(stream: &mut impl Write, status: u8) -> Result<()> {
+    stream.write_all(&[VERSION, status, 0, address_types::IPV4, 0, 0, 0, 0, 0, 0])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    struct MockStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn accept_raw(input: &[u8]) -> (Result<(Address, u16)>, Vec<u8>) {
+        let mut stream = MockStream {
+            input: Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        };
+        let res = accept(&mut stream);
+        (res, stream.output)
+    }
+
+    #[test]
+    fn test_accept() {
+        let (res, output) = accept_raw(b"\x05\x02\x02\x00\x05\x01\x00\x03\x0bexample.com\x01\xbb");
+        assert_eq!(
+            res.unwrap(),
+            (Address::Domain(String::from("example.com")), 443)
+        );
+        assert_eq!(output, [VERSION, NO_AUTHENTICATION]);
+
+        let (res, _) = accept_raw(b"\x05\x01\x00\x05\x01\x00\x01\x7f\x00\x00\x01\x00\x50");
+        assert_eq!(res.unwrap(), (Address::Ipv4(Ipv4Addr::LOCALHOST), 80));
+
+        let (res, output) = accept_raw(b"\x05\x01\x02");
+        assert!(res.is_err());
+        assert_eq!(output, [VERSION, NO_ACCEPTABLE_METHODS]);
+
+        // UDP ASSOCIATE
+        let (res, output) = accept_raw(b"\x05\x01\x00\x05\x03\x00\x01\x00\x00\x00\x00\x00\x00");
+        assert!(res.is_err());
+        assert_eq!(output[3], replies::COMMAND_NOT_SUPPORTED);
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Read, Write},
+    sync::{
+        Arc, Condvar, LazyLock, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    h2::{self, Request, StreamReader, StreamWriter, error_codes},
+    http::{self, RequestHead},
+    inbound::{self, Inbound},
+    stream::{CloseWrite, HandshakeInfo, TlsStream, TlsWriter},
+    transport::ClientStream,
+};
+
+/// Uploads buffered ahead of a missing sequence number.
+const MAX_PENDING_UPLOADS: usize = 30;
+
+/// Largest upload request body.
+const MAX_UPLOAD_SIZE: usize = 1 << 20;
+
+/// Largest chunk of the download response.
+const DOWNLOAD_CHUNK_SIZE: usize = 1 << 14;
+
+/// How long a session waits for its download request.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+const MAX_SESSION_ID_LENGTH: usize = 64;
+
+/// Sessions open at once over all inbounds.
+const MAX_SESSIONS: usize = 1024;
+
+/// Uploaded bytes buffered over all sessions.
+const MAX_PENDING_BYTES: usize = 64 << 20;
+
+const UPLOAD_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+const DOWNLOAD_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\
+                                   Cache-Control: no-store\r\nX-Accel-Buffering: no\r\n\
+                                   Transfer-Encoding: chunked\r\n\r\n";
+const BAD_REQUEST: &[u8] =
+    b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const NOT_FOUND: &[u8] =
+    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+#[derive(Default)]
+struct Uploads {
+    next_seq: u64,
+    pending: BTreeMap<u64, Box<[u8]>>,
+    closed: bool,
+}
+
+/// Upload side of a client connection, put in order by sequence number.
+struct Session {
+    uploads: Mutex<Uploads>,
+    ready: Condvar,
+    created: Instant,
+    /// Shared by all sessions, see [`MAX_PENDING_BYTES`]
+    pending_bytes: Arc<AtomicUsize>,
+}
+
+impl Session {
+    fn lock(&self) -> io::Result<MutexGuard<'_, Uploads>> {
+        self.uploads
+            .lock()
+            .map_err(|_| io::Error::other("Poisoned lock"))
+    }
+
+    fn push(&self, seq: u64, data: Box<[u8]>) -> Result<()> {
+        let mut uploads = self.lock()?;
+        if uploads.closed {
+            bail!("Session is closed");
+        }
+        if seq < uploads.next_seq || uploads.pending.contains_key(&seq) {
+            bail!("Duplicate upload {seq}");
+        }
+        if uploads.pending.len() >= MAX_PENDING_UPLOADS {
+            bail!("Too many pending uploads");
+        }
+        let size = data.len();
+        if self.pending_bytes.fetch_add(size, Ordering::Relaxed) + size > MAX_PENDING_BYTES {
+            self.pending_bytes.fetch_sub(size, Ordering::Relaxed);
+            bail!("Too many pending upload bytes");
+        }
+        uploads.pending.insert(seq, data);
+        self.ready.notify_all();
+
+        Ok(())
+    }
+
+    fn close(&self) {
+        if let Ok(mut uploads) = self.lock() {
+            uploads.closed = true;
+        }
+        self.ready.notify_all();
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let uploads = self
+            .uploads
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        let size: usize = uploads.pending.values().map(|x| x.len()).sum();
+        self.pending_bytes.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+/// Session and whether its download has started.
+type SessionEntry = (Arc<Session>, bool);
+
+/// Sessions keyed by inbound tag and session ID.
+#[derive(Default)]
+struct Sessions {
+    entries: HashMap<(String, String), SessionEntry>,
+    pending_bytes: Arc<AtomicUsize>,
+}
+
+impl Sessions {
+    /// Get or create a session, forgetting ones that never got a download.
+    fn get(&mut self, inbound: &str, id: &str, download: bool) -> Result<Arc<Session>> {
+        self.entries.retain(|_, (session, downloading)| {
+            *downloading || session.created.elapsed() < SESSION_TIMEOUT
+        });
+
+        let key = (String::from(inbound), String::from(id));
+        if !self.entries.contains_key(&key) && self.entries.len() >= MAX_SESSIONS {
+            bail!("Too many sessions");
+        }
+        let (session, downloading) = self.entries.entry(key).or_insert_with(|| {
+            let session = Session {
+                uploads: Mutex::default(),
+                ready: Condvar::new(),
+                created: Instant::now(),
+                pending_bytes: self.pending_bytes.clone(),
+            };
+            (Arc::new(session), false)
+        });
+        if download {
+            if *downloading {
+                bail!("Session {id} is already downloading");
+            }
+            *downloading = true;
+        }
+
+        Ok(session.clone())
+    }
+
+    fn remove(&mut self, inbound: &str, id: &str) {
+        let key = (String::from(inbound), String::from(id));
+        if let Some((session, _)) = self.entries.remove(&key) {
+            session.close();
+        }
+    }
+}
+
+static SESSIONS: LazyLock<Mutex<Sessions>> = LazyLock::new(Mutex::default);
+
+fn session(inbound: &str, id: &str, download: bool) -> Result<Arc<Session>> {
+    let mut sessions = SESSIONS.lock().map_err(|_| anyhow!("Poisoned lock"))?;
+    sessions.get(inbound, id, download)
+}
+
+fn remove_session(inbound: &str, id: &str) {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.remove(inbound, id);
+    }
+}
+
+/// Uploaded data in sequence order, ends when the session is closed.
+struct UploadReader {
+    session: Arc<Session>,
+
+    buf: Box<[u8]>,
+    pos: usize,
+}
+
+impl Read for UploadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            let mut uploads = self.session.lock()?;
+            loop {
+                let seq = uploads.next_seq;
+                if let Some(data) = uploads.pending.remove(&seq) {
+                    uploads.next_seq += 1;
+                    self.session
+                        .pending_bytes
+                        .fetch_sub(data.len(), Ordering::Relaxed);
+                    if data.is_empty() {
+                        continue;
+                    }
+                    self.buf = data;
+                    self.pos = 0;
+                    break;
+                }
+                if uploads.closed {
+                    return Ok(0);
+                }
+                uploads = self
+                    .session
+                    .ready
+                    .wait(uploads)
+                    .map_err(|_| io::Error::other("Poisoned lock"))?;
+            }
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+enum Download {
+    /// Chunked response body
+    Http1(TlsWriter),
+    H2(StreamWriter),
+}
+
+/// Body of the download response, closing it ends the session.
+struct DownloadWriter {
+    inner: Download,
+    session: Arc<Session>,
+}
+
+impl Write for DownloadWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let res = match &mut self.inner {
+            Download::Http1(writer) => {
+                let n = buf.len().min(DOWNLOAD_CHUNK_SIZE);
+                let chunk = [format!("{n:x}\r\n").as_bytes(), &buf[..n], b"\r\n"].concat();
+                writer.write_all(&chunk).map(|()| n)
+            }
+            Download::H2(writer) => writer.write(buf),
+        };
+        if res.is_err() {
+            self.session.close();
+        }
+        res
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Download::Http1(writer) => writer.flush(),
+            Download::H2(writer) => writer.flush(),
+        }
+    }
+}
+
+impl CloseWrite for DownloadWriter {
+    /// The client can not end uploads on its own, so the session ends here too.
+    fn close(&mut self) -> io::Result<()> {
+        self.session.close();
+        match &mut self.inner {
+            Download::Http1(writer) => {
+                writer.write_all(b"0\r\n\r\n")?;
+                writer.close()
+            }
+            Download::H2(writer) => writer.finish(),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.session.close();
+        match &self.inner {
+            Download::Http1(writer) => writer.shutdown(),
+            Download::H2(writer) => writer.reset(error_codes::CANCEL),
+        }
+    }
+}
+
+impl Drop for DownloadWriter {
+    fn drop(&mut self) {
+        self.session.close();
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Route<'a> {
+    Download(&'a str),
+    Upload(&'a str, u64),
+}
+
+/// Map `<path>/<session>` downloads and `<path>/<session>/<seq>` uploads.
+fn route<'a>(method: &str, target: &'a str, path: &str) -> Option<Route<'a>> {
+    let path = path
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    let target = target.split('?').next().unwrap_or_default();
+    let rest = target.strip_prefix(path)?.strip_prefix('/')?;
+
+    let mut parts = rest.trim_end_matches('/').split('/');
+    let id = parts
+        .next()
+        .filter(|x| !x.is_empty() && x.len() <= MAX_SESSION_ID_LENGTH)?;
+
+    match (method, parts.next(), parts.next()) {
+        ("GET", None, _) => Some(Route::Download(id)),
+        ("POST", Some(seq), None) => Some(Route::Upload(id, seq.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Whether the first request of a connection belongs to the transport.
+pub fn accepts(head: &RequestHead, path: &str, host: Option<&str>) -> bool {
+    http::check_host(head.host(), host).is_ok() && route(&head.method, &head.target, path).is_some()
+}
+
+/// Transport settings and what the inbound needs for every request.
+struct Context<'a> {
+    path: &'a str,
+    host: Option<&'a str>,
+    info: &'a HandshakeInfo,
+    inbound: &'a Inbound,
+}
+
+/// Hand a session over to the VLESS inbound once the download starts.
+fn download(id: &str, inner: Download, ctx: &Context) -> Result<()> {
+    let session = match session(&ctx.inbound.tag, id, true) {
+        Ok(session) => session,
+        Err(e) => {
+            if let Download::H2(writer) = &inner {
+                writer.reset(error_codes::REFUSED_STREAM)?;
+            }
+            return Err(e);
+        }
+    };
+    tracing::debug!("SplitHTTP session {id} started");
+
+    let stream = ClientStream::Wrapped {
+        reader: Box::new(UploadReader {
+            session: session.clone(),
+            buf: Box::new([]),
+            pos: 0,
+        }),
+        writer: Box::new(DownloadWriter { inner, session }),
+    };
+    let res = inbound::handle(stream, ctx.info, ctx.inbound);
+    remove_session(&ctx.inbound.tag, id);
+    res
+}
+
+/// HTTP/1 connection with data read past the last request.
+struct Http1Conn {
+    stream: TlsStream,
+    buf: Vec<u8>,
+}
+
+impl Http1Conn {
+    fn fill(&mut self) -> Result<usize> {
+        let mut chunk = [0; 4096];
+        let n = self.stream.read(&mut chunk)?;
+        self.buf.extend(&chunk[..n]);
+        Ok(n)
+    }
+
+    fn read_head(&mut self) -> Result<Option<RequestHead>> {
+        loop {
+            if let Some((head, size)) = RequestHead::parse(&self.buf)? {
+                self.buf.drain(..size);
+                return Ok(Some(head));
+            }
+            if self.fill()? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                bail!("Connection closed inside request head");
+            }
+        }
+    }
+
+    fn read_body(&mut self, length: usize) -> Result<Box<[u8]>> {
+        while self.buf.len() < length {
+            if self.fill()? == 0 {
+                bail!("Connection closed inside request body");
+            }
+        }
+        Ok(self.buf.drain(..length).collect())
+    }
+}
+
+/// Serve requests of an HTTP/1 connection, `data` is what was read so far.
+pub fn serve_http1(
+    stream: TlsStream,
+    data: &[u8],
+    info: &HandshakeInfo,
+    path: &str,
+    host: Option<&str>,
+    inbound: &Inbound,
+) -> Result<()> {
+    let ctx = Context {
+        path,
+        host,
+        info,
+        inbound,
+    };
+    let mut conn = Http1Conn {
+        stream,
+        buf: data.to_vec(),
+    };
+
+    while let Some(head) = conn.read_head()? {
+        if http::check_host(head.host(), host).is_err() {
+            conn.stream.write_all(NOT_FOUND)?;
+            bail!("Unexpected host {:?}", head.host());
+        }
+        match route(&head.method, &head.target, path) {
+            Some(Route::Download(id)) => {
+                let (reader, mut writer) = conn.stream.split();
+                writer.write_all(DOWNLOAD_RESPONSE)?;
+
+                // Nothing more is sent on the connection, reads only notice the client leaving
+                let (tag, id_owned) = (inbound.tag.clone(), String::from(id));
+                thread::spawn(move || {
+                    let mut reader = reader;
+                    _ = io::copy(&mut reader, &mut io::sink());
+                    remove_session(&tag, &id_owned);
+                });
+                return download(id, Download::Http1(writer), &ctx);
+            }
+            Some(Route::Upload(id, seq)) => {
+                let length = head
+                    .header("Content-Length")
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .filter(|&x| x <= MAX_UPLOAD_SIZE);
+                let Some(length) = length else {
+                    conn.stream.write_all(BAD_REQUEST)?;
+                    bail!("Missing or invalid upload length");
+                };
+                let data = conn.read_body(length)?;
+
+                if let Err(e) = session(&inbound.tag, id, false).and_then(|x| x.push(seq, data)) {
+                    conn.stream.write_all(BAD_REQUEST)?;
+                    return Err(e);
+                }
+                conn.stream.write_all(UPLOAD_RESPONSE)?;
+
+                if head.header_has_token("Connection", "close") {
+                    break;
+                }
+            }
+            None => {
+                conn.stream.write_all(NOT_FOUND)?;
+                bail!("Unexpected request {} {}", head.method, head.target);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_stream(
+    request: &Request,
+    mut reader: StreamReader,
+    writer: StreamWriter,
+    ctx: &Context,
+) -> Result<()> {
+    let authority = request.header(":authority").map(http::strip_port);
+    let route = match route(&request.method, &request.path, ctx.path) {
+        Some(route) if http::check_host(authority, ctx.host).is_ok() => route,
+        _ => {
+            writer.send_headers(&[(":status", "404")], true)?;
+            bail!("Unexpected request {} {}", request.method, request.path);
+        }
+    };
+
+    match route {
+        Route::Download(id) => {
+            writer.send_headers(
+                &[
+                    (":status", "200"),
+                    ("content-type", "text/event-stream"),
+                    ("cache-control", "no-store"),
+                    ("x-accel-buffering", "no"),
+                ],
+                false,
+            )?;
+            download(id, Download::H2(writer), ctx)
+        }
+        Route::Upload(id, seq) => {
+            let mut data = Vec::new();
+            (&mut reader)
+                .take(MAX_UPLOAD_SIZE as u64 + 1)
+                .read_to_end(&mut data)?;
+            if data.len() > MAX_UPLOAD_SIZE {
+                writer.send_headers(&[(":status", "413")], true)?;
+                bail!("Upload is too large");
+            }
+
+            let session = session(&ctx.inbound.tag, id, false);
+            if let Err(e) = session.and_then(|x| x.push(seq, data.into_boxed_slice())) {
+                writer.send_headers(&[(":status", "400")], true)?;
+                return Err(e);
+            }
+            writer.send_headers(&[(":status", "200")], true)?;
+            Ok(())
+        }
+    }
+}
+
+/// Serve an HTTP/2 connection the preface was already read from.
+pub fn serve_h2(
+    stream: TlsStream,
+    info: &HandshakeInfo,
+    path: &str,
+    host: Option<&str>,
+    inbound: &Inbound,
+) -> Result<()> {
+    let ctx = Context {
+        path,
+        host,
+        info,
+        inbound,
+    };
+    h2::serve(stream, &|request, reader, writer| {
+        _ = handle_stream(&request, reader, writer, &ctx)
+            .inspect_err(|e| tracing::error!("SplitHTTP stream handle error: {e:?}"));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(
+            route("GET", "/split/abc?x_padding=000", "/split"),
+            Some(Route::Download("abc"))
+        );
+        assert_eq!(
+            route("POST", "/split/abc/7", "/split/"),
+            Some(Route::Upload("abc", 7))
+        );
+        assert_eq!(route("POST", "/split/abc", "/split"), None);
+        assert_eq!(route("GET", "/splitabc", "/split"), None);
+        assert_eq!(route("GET", "/split/", "/split"), None);
+        assert_eq!(route("POST", "/split/abc/x", "/split"), None);
+    }
+
+    #[test]
+    fn test_session_order() {
+        let session = Sessions::default().get("vless-in", "abc", false).unwrap();
+        session.push(1, Box::from(*b"world")).unwrap();
+        session.push(0, Box::from(*b"hello ")).unwrap();
+        assert!(session.push(1, Box::from(*b"again")).is_err());
+        session.close();
+
+        let mut reader = UploadReader {
+            session,
+            buf: Box::new([]),
+            pos: 0,
+        };
+        let mut data = String::new();
+        reader.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "hello world");
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let mut sessions = Sessions::default();
+        let pending_bytes = sessions.pending_bytes.clone();
+
+        // IDs are only unique within an inbound
+        let session = sessions.get("a", "0", false).unwrap();
+        assert!(!Arc::ptr_eq(
+            &session,
+            &sessions.get("b", "0", false).unwrap()
+        ));
+
+        for i in 2..MAX_SESSIONS {
+            sessions.get("a", &i.to_string(), false).unwrap();
+        }
+        assert!(sessions.get("a", "new", false).is_err());
+        assert!(Arc::ptr_eq(
+            &session,
+            &sessions.get("a", "0", true).unwrap()
+        ));
+
+        // Uploads ahead of the missing first one stay buffered
+        let chunk: Box<[u8]> = vec![0; MAX_UPLOAD_SIZE].into_boxed_slice();
+        let mut pushed = 0;
+        'fill: for i in 2..MAX_SESSIONS {
+            let session = sessions.get("a", &i.to_string(), false).unwrap();
+            for seq in 1..=MAX_PENDING_UPLOADS as u64 {
+                if session.push(seq, chunk.clone()).is_err() {
+                    break 'fill;
+                }
+                pushed += chunk.len();
+            }
+        }
+        assert_eq!(pushed, MAX_PENDING_BYTES);
+        assert_eq!(pending_bytes.load(Ordering::Relaxed), MAX_PENDING_BYTES);
+
+        drop(sessions);
+        assert_eq!(pending_bytes.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread,
+};
+
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::{http, metrics::Metrics, users::User};
+
+const NOT_FOUND: &[u8] =
+    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+const METHOD_NOT_ALLOWED: &[u8] =
+    b"HTTP/1.1 405 Method Not Allowed\r\nAllow: GET\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// Stats API settings, the API is disabled without a listen address.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StatsConfig {
+    /// `"host:port"`, the API has no authentication so keep it local
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    uplink: AtomicU64,
+    downlink: AtomicU64,
+    active: AtomicU64,
+}
+
+impl Counters {
+    /// Traffic is zeroed on reset, active connections are not.
+    fn snapshot(&self, reset: bool) -> Snapshot {
+        let read = |x: &AtomicU64| {
+            if reset {
+                x.swap(0, Ordering::Relaxed)
+            } else {
+                x.load(Ordering::Relaxed)
+            }
+        };
+        Snapshot {
+            uplink: read(&self.uplink),
+            downlink: read(&self.downlink),
+            active: self.active.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Bytes received from clients
+    pub uplink: u64,
+    /// Bytes sent to clients
+    pub downlink: u64,
+    /// Open connections
+    pub active: u64,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct UserSnapshot {
+    pub email: String,
+    #[serde(flatten)]
+    pub counters: Snapshot,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Keyed by UUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users: Option<BTreeMap<String, UserSnapshot>>,
+    /// Keyed by tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inbounds: Option<BTreeMap<String, Snapshot>>,
+}
+
+type UserCounters = HashMap<u128, (String, Arc<Counters>)>;
+
+/// Traffic of users and inbounds since they were first seen or last reset.
+///
+/// Unlike the quota counters of users these can be reset
+#[derive(Debug, Default)]
+pub struct Stats {
+    users: Mutex<UserCounters>,
+    inbounds: Mutex<HashMap<String, Arc<Counters>>>,
+    /// All sessions since the server started, never reset
+    total: Arc<Counters>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>> {
+    mutex.lock().map_err(|_| anyhow!("Poisoned lock"))
+}
+
+impl Stats {
+    /// Start counting a connection of the user on the inbound.
+    pub fn session(&self, user: &User, inbound: &str) -> Result<Session> {
+        let user = lock(&self.users)?
+            .entry(user.uuid)
+            .and_modify(|(email, _)| email.clone_from(&user.email))
+            .or_insert_with(|| (user.email.clone(), Arc::default()))
+            .1
+            .clone();
+        let inbound = lock(&self.inbounds)?
+            .entry(String::from(inbound))
+            .or_default()
+            .clone();
+
+        let counters = [user, inbound, self.total.clone()];
+        for x in &counters {
+            x.active.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(Session { counters })
+    }
+
+    pub fn total(&self) -> Snapshot {
+        self.total.snapshot(false)
+    }
+
+    pub fn users(&self, reset: bool) -> Result<BTreeMap<String, UserSnapshot>> {
+        Ok(lock(&self.users)?
+            .iter()
+            .map(|(uuid, (email, counters))| {
+                let snapshot = UserSnapshot {
+                    email: email.clone(),
+                    counters: counters.snapshot(reset),
+                };
+                (vless::format_uuid(*uuid), snapshot)
+            })
+            .collect())
+    }
+
+    pub fn inbounds(&self, reset: bool) -> Result<BTreeMap<String, Snapshot>> {
+        Ok(lock(&self.inbounds)?
+            .iter()
+            .map(|(tag, counters)| (tag.clone(), counters.snapshot(reset)))
+            .collect())
+    }
+}
+
+/// Counters of a single client connection, active until dropped.
+#[derive(Debug)]
+pub struct Session {
+    /// User, inbound and total
+    counters: [Arc<Counters>; 3],
+}
+
+impl Session {
+    pub fn uplink(&self, n: u64) {
+        for x in &self.counters {
+            x.uplink.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    pub fn downlink(&self, n: u64) {
+        for x in &self.counters {
+            x.downlink.fetch_add(n, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        for x in &self.counters {
+            x.active.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Answer a single API request.
+///
+/// `GET /stats`, `/stats/users` or `/stats/inbounds` return JSON,
+/// with `?reset=true` traffic counters are zeroed as they are read.
+/// `GET /metrics` is for Prometheus
+fn handle_request(mut conn: TcpStream, stats: &Stats, metrics: &Metrics) -> Result<()> {
+    let (_, head) = http::read_head(&mut conn)?;
+    let (head, _) = head?;
+
+    if head.method != "GET" {
+        conn.write_all(METHOD_NOT_ALLOWED)?;
+        return Ok(());
+    }
+    let reset = head
+        .target
+        .split_once('?')
+        .is_some_and(|(_, query)| query.split('&').any(|x| x == "reset=true" || x == "reset"));
+
+    let snapshot = match head.path() {
+        "/stats" => StatsSnapshot {
+            users: Some(stats.users(reset)?),
+            inbounds: Some(stats.inbounds(reset)?),
+        },
+        "/stats/users" => StatsSnapshot {
+            users: Some(stats.users(reset)?),
+            ..Default::default()
+        },
+        "/stats/inbounds" => StatsSnapshot {
+            inbounds: Some(stats.inbounds(reset)?),
+            ..Default::default()
+        },
+        "/metrics" => {
+            let body = metrics.render(stats.total())?;
+            return respond(&mut conn, METRICS_CONTENT_TYPE, body.as_bytes());
+        }
+        _ => {
+            conn.write_all(NOT_FOUND)?;
+            return Ok(());
+        }
+    };
+
+    respond(
+        &mut conn,
+        "application/json",
+        &serde_json::to_vec(&snapshot)?,
+    )
+}
+
+fn respond(conn: &mut TcpStream, content_type: &str, body: &[u8]) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        body.len()
+    );
+    conn.write_all(head.as_bytes())?;
+    conn.write_all(body)?;
+    Ok(())
+}
+
+/// Serve the stats API on its own threads.
+pub fn serve(listener: TcpListener, stats: Arc<Stats>, metrics: Arc<Metrics>) {
+    thread::spawn(move || {
+        for conn in listener.incoming().filter_map(Result::ok) {
+            let stats = stats.clone();
+            let metrics = metrics.clone();
+            thread::spawn(move || {
+                _ = handle_request(conn, &stats, &metrics)
+                    .inspect_err(|e| tracing::debug!("Stats request error: {e}"));
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::default();
+        let alice = User::unrestricted(1, "alice@example.com");
+        let bob = User::unrestricted(2, "bob@example.com");
+
+        let first = stats.session(&alice, "vless-in").unwrap();
+        let second = stats.session(&alice, "other").unwrap();
+        let third = stats.session(&bob, "vless-in").unwrap();
+        first.uplink(10);
+        second.downlink(20);
+        third.uplink(5);
+        drop(second);
+
+        let users = stats.users(false).unwrap();
+        let counters = users[&vless::format_uuid(1)].counters;
+        assert_eq!(
+            counters,
+            Snapshot {
+                uplink: 10,
+                downlink: 20,
+                active: 1
+            }
+        );
+        let inbounds = stats.inbounds(true).unwrap();
+        assert_eq!(inbounds["vless-in"].uplink, 15);
+        assert_eq!(inbounds["vless-in"].active, 2);
+        assert_eq!(inbounds["other"].downlink, 20);
+
+        // Reset only zeroes traffic
+        let inbounds = stats.inbounds(false).unwrap();
+        assert_eq!(inbounds["vless-in"].uplink, 0);
+        assert_eq!(inbounds["vless-in"].active, 2);
+        assert_eq!(
+            stats.users(false).unwrap()[&vless::format_uuid(2)]
+                .counters
+                .uplink,
+            5
+        );
+
+        drop((first, third));
+        assert_eq!(stats.inbounds(false).unwrap()["vless-in"].active, 0);
+        assert_eq!(stats.total().uplink, 15);
+    }
+}
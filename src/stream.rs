@@ -0,0 +1,252 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use tls::{
+    connection::{Event, RecordReader},
+    key_schedule::TrafficKeys,
+    record::{
+        TlsPlaintext,
+        alert::{Alert, AlertDescription, AlertLevel},
+    },
+};
+use utils::concat_dyn;
+
+pub use tls::server::HandshakeInfo;
+
+const MAX_FRAGMENT_LENGTH: usize = 1 << 14;
+
+/// Application data reading half of an established TLS connection.
+pub struct TlsReader {
+    conn: TcpStream,
+    records: RecordReader,
+
+    buf: Box<[u8]>,
+    pos: usize,
+    closed: bool,
+}
+
+impl TlsReader {
+    fn fill_buf(&mut self) -> Result<()> {
+        while self.pos == self.buf.len() && !self.closed {
+            match self.records.next_event()? {
+                Some(Event::ApplicationData(data)) => {
+                    self.buf = data;
+                    self.pos = 0;
+                }
+                Some(Event::Closed) => self.closed = true,
+                Some(Event::HandshakeComplete) => bail!("Unexpected handshake completion"),
+                None => {
+                    let mut chunk = [0; 1 << 14];
+                    let n = self.conn.read(&mut chunk)?;
+                    if n == 0 {
+                        if !self.records.is_empty() {
+                            bail!("Connection closed within a record");
+                        }
+                        self.closed = true;
+                    }
+                    self.records.push(&chunk[..n]);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take decrypted data that was not read yet, followed by received bytes
+    /// that were not processed as records.
+    pub fn take_buffered(&mut self) -> Box<[u8]> {
+        let res = concat_dyn!(&self.buf[self.pos..], self.records.take_received());
+        self.buf = Box::new([]);
+        self.pos = 0;
+        res
+    }
+
+    /// Underlying socket, for bypassing the TLS layer.
+    pub fn raw_conn(&self) -> io::Result<TcpStream> {
+        self.conn.try_clone()
+    }
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill_buf().map_err(io::Error::other)?;
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Application data writing half of an established TLS connection.
+pub struct TlsWriter {
+    conn: TcpStream,
+    keys: TrafficKeys,
+}
+
+impl TlsWriter {
+    fn write_record(&mut self, record: &TlsPlaintext) -> io::Result<()> {
+        let encrypted = self.keys.encrypt(record).map_err(io::Error::other)?;
+        self.conn.write_all(&encrypted.to_raw())
+    }
+
+    /// Send `close_notify` and shut down the write side of the socket.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.write_record(&TlsPlaintext::new_alert(Alert {
+            level: AlertLevel::Warning,
+            description: AlertDescription::CloseNotify,
+        }))?;
+        self.conn.shutdown(Shutdown::Write)
+    }
+
+    /// Shut down both directions of the socket, unblocking the reading half.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.conn.shutdown(Shutdown::Both)
+    }
+
+    /// Underlying socket, for bypassing the TLS layer.
+    pub fn raw_conn(&self) -> io::Result<TcpStream> {
+        self.conn.try_clone()
+    }
+}
+
+/// Writing half of a client connection.
+pub trait CloseWrite: Write {
+    /// Signal the end of data to the client.
+    fn close(&mut self) -> io::Result<()>;
+
+    /// Shut down both directions of the connection, unblocking the reading half.
+    fn shutdown(&self) -> io::Result<()>;
+}
+
+impl CloseWrite for TlsWriter {
+    fn close(&mut self) -> io::Result<()> {
+        TlsWriter::close(self)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        TlsWriter::shutdown(self)
+    }
+}
+
+impl<T: CloseWrite + ?Sized> CloseWrite for Box<T> {
+    fn close(&mut self) -> io::Result<()> {
+        (**self).close()
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        (**self).shutdown()
+    }
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(MAX_FRAGMENT_LENGTH);
+        let record = TlsPlaintext::new_application_data(&buf[..n]).map_err(io::Error::other)?;
+        self.write_record(&record)?;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.conn.flush()
+    }
+}
+
+/// Established TLS connection carrying application data.
+pub struct TlsStream {
+    reader: TlsReader,
+    writer: TlsWriter,
+}
+
+impl TlsStream {
+    /// `records` reads what the peer sends, `write_keys` protect our own records
+    pub fn new(conn: TcpStream, records: RecordReader, write_keys: TrafficKeys) -> Result<Self> {
+        Ok(Self {
+            reader: TlsReader {
+                conn: conn.try_clone()?,
+                records,
+                buf: Box::new([]),
+                pos: 0,
+                closed: false,
+            },
+            writer: TlsWriter {
+                conn,
+                keys: write_keys,
+            },
+        })
+    }
+
+    pub fn split(self) -> (TlsReader, TlsWriter) {
+        (self.reader, self.writer)
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.reader.conn.set_read_timeout(timeout)
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Client connection writing half that keeps everything in memory.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryWriter {
+    pub data: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+    pub closed: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(test)]
+impl Write for MemoryWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl CloseWrite for MemoryWriter {
+    fn close(&mut self) -> io::Result<()> {
+        self.closed
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl CloseWrite for std::os::unix::net::UnixStream {
+    fn close(&mut self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Write)
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use anyhow::{Result, anyhow, bail};
+use crypt::hash::{Hasher, sha::Sha256};
+use tls::{
+    client::{ClientConfig, ClientConnection, ServerCertVerifier},
+    record::handshake::extension::SignatureScheme,
+};
+
+use crate::{credentials, stream::TlsStream};
+
+/// How the server certificate is checked, there is no trust store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CertificatePin {
+    /// SHA-256 of the DER encoded server certificate
+    Sha256([u8; 32]),
+    /// Any certificate is accepted
+    Insecure,
+}
+
+impl CertificatePin {
+    /// Parse a hex fingerprint, `:` separators as printed by OpenSSL are allowed.
+    ///
+    /// Without a fingerprint any certificate is accepted only if `insecure` is set
+    pub fn new(fingerprint: Option<&str>, insecure: bool) -> Result<Self> {
+        let Some(fingerprint) = fingerprint else {
+            if insecure {
+                return Ok(Self::Insecure);
+            }
+            bail!("Missing server certificate pin");
+        };
+
+        let digits = fingerprint.replace(':', "");
+        if digits.len() != 64 || !digits.bytes().all(|x| x.is_ascii_hexdigit()) {
+            bail!("Invalid SHA-256 fingerprint {fingerprint}");
+        }
+        let mut res = [0; 32];
+        for (i, x) in res.iter_mut().enumerate() {
+            *x = u8::from_str_radix(&digits[(2 * i)..(2 * i + 2)], 16)?;
+        }
+        Ok(Self::Sha256(res))
+    }
+}
+
+/// Checks the server certificate against the pin and the handshake signature
+/// against the certificate.
+struct SignatureVerifier {
+    pin: CertificatePin,
+}
+
+impl ServerCertVerifier for SignatureVerifier {
+    fn verify_certificate(&self, certificates: &[&[u8]], _server_name: &str) -> Result<()> {
+        let CertificatePin::Sha256(pin) = self.pin else {
+            return Ok(());
+        };
+        let certificate = certificates
+            .first()
+            .ok_or(anyhow!("No server certificate"))?;
+        if *Sha256::hash(certificate) != pin {
+            bail!("Server certificate does not match the pin");
+        }
+        Ok(())
+    }
+
+    fn verify_signature(
+        &self,
+        certificate: &[u8],
+        scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        match scheme {
+            SignatureScheme::rsa_pss_rsae_sha256 | SignatureScheme::rsa_pss_pss_sha256 => {
+                crypt::rsa::rsassa_pss_verify::<Sha256, { Sha256::DIGEST_SIZE }>(
+                    &credentials::public_key(certificate)?,
+                    message,
+                    signature,
+                )
+            }
+            _ => bail!("Unsupported signature scheme {scheme:?}"),
+        }
+    }
+}
+
+/// Perform client side of the handshake over a connected socket.
+///
+/// Offers only x25519 and the supported AEAD suites. The certificate chain is
+/// not verified, the server certificate must match `pin` and the server must
+/// hold its key. Returns the stream and the negotiated application protocol
+pub fn connect(
+    mut conn: TcpStream,
+    server_name: &str,
+    alpn: &[&str],
+    pin: CertificatePin,
+) -> Result<(TlsStream, Option<String>)> {
+    let config = ClientConfig {
+        alpn_protocols: alpn.iter().map(|x| String::from(*x)).collect(),
+        verifier: Box::new(SignatureVerifier { pin }),
+    };
+    let mut tls = ClientConnection::new(&config, server_name)?;
+    conn.write_all(&tls.take_output())?;
+
+    let mut chunk = [0; 1 << 14];
+    while tls.is_handshaking() {
+        let n = conn.read(&mut chunk)?;
+        if n == 0 {
+            bail!("Connection closed during handshake");
+        }
+        let res = tls.receive(&chunk[..n]);
+        conn.write_all(&tls.take_output())?;
+        res?;
+    }
+
+    let protocol = tls.alpn().map(String::from);
+    let (records, client_keys) = tls.into_parts()?;
+    Ok((TlsStream::new(conn, records, client_keys)?, protocol))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_certificate_pin() -> Result<()> {
+        let certificate = [0x30, 0x00];
+        let fingerprint: String = Sha256::hash(&certificate)
+            .iter()
+            .map(|x| format!("{x:02X}:"))
+            .collect();
+        let pin = CertificatePin::new(Some(fingerprint.trim_end_matches(':')), false)?;
+
+        let verifier = SignatureVerifier { pin };
+        verifier.verify_certificate(&[&certificate], "example.com")?;
+        assert!(
+            verifier
+                .verify_certificate(&[&[0x30, 0x01]], "example.com")
+                .is_err()
+        );
+        assert!(verifier.verify_certificate(&[], "example.com").is_err());
+
+        // Fail closed without a pin
+        assert!(CertificatePin::new(None, false).is_err());
+        assert_eq!(CertificatePin::new(None, true)?, CertificatePin::Insecure);
+        assert!(CertificatePin::new(Some("9f86"), true).is_err());
+        assert!(CertificatePin::new(Some(&"+f".repeat(32)), false).is_err());
+
+        Ok(())
+    }
+}
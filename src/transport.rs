@@ -0,0 +1,185 @@
+use std::io::{self, Read};
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::{
+    grpc, h2, http, http_upgrade,
+    inbound::{self, Inbound},
+    split_http,
+    stream::{CloseWrite, HandshakeInfo, TlsStream},
+    websocket::{self, Upgrade},
+};
+
+/// How VLESS requests are carried inside TLS.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Transport {
+    /// Directly over TLS
+    #[default]
+    Tcp,
+    /// Binary messages of a WebSocket connection
+    #[serde(rename = "ws")]
+    WebSocket {
+        path: String,
+        /// Expected `Host` header, any host is accepted if not set
+        host: Option<String>,
+    },
+    /// `Tun` streams of gRPC over HTTP/2, negotiated with ALPN
+    Grpc { service_name: String },
+    /// Raw bytes after an HTTP/1.1 upgrade
+    #[serde(rename = "httpupgrade")]
+    HttpUpgrade { path: String, host: Option<String> },
+    /// Uploads in sequenced POST requests and the download in a streaming GET response,
+    /// over HTTP/1.1 or HTTP/2
+    #[serde(rename = "xhttp", alias = "splithttp")]
+    SplitHttp { path: String, host: Option<String> },
+}
+
+/// Client connection after the transport is set up.
+pub enum ClientStream {
+    Tls(TlsStream),
+    /// Carried by another protocol on top of TLS
+    Wrapped {
+        reader: Box<dyn Read + Send>,
+        writer: Box<dyn CloseWrite + Send>,
+    },
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tls(stream) => stream.read(buf),
+            Self::Wrapped { reader, .. } => reader.read(buf),
+        }
+    }
+}
+
+/// Set up the transport and handle the VLESS request inside it.
+///
+/// Requests that do not match the transport are rejected like invalid VLESS requests
+pub fn serve(mut stream: TlsStream, info: &HandshakeInfo, inbound: &Inbound) -> Result<()> {
+    let fallbacks = &inbound.fallbacks;
+    match &inbound.transport {
+        Transport::Tcp => inbound::handle(ClientStream::Tls(stream), info, inbound),
+        Transport::WebSocket { path, host } => {
+            let (data, head) = http::read_head(&mut stream)?;
+            if data.is_empty() {
+                return Ok(());
+            }
+
+            let upgrade = head.and_then(|(head, size)| {
+                if size != data.len() {
+                    bail!("Unexpected data after upgrade request");
+                }
+                Upgrade::new(&head, path, host.as_deref())
+            });
+            let upgrade = match upgrade {
+                Ok(upgrade) => upgrade,
+                Err(e) => {
+                    tracing::debug!("Invalid WebSocket upgrade: {e}");
+                    return inbound::reject(ClientStream::Tls(stream), info, fallbacks, &data);
+                }
+            };
+
+            let (reader, writer) = websocket::accept(stream, upgrade)?;
+            let stream = ClientStream::Wrapped {
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+            };
+            inbound::handle(stream, info, inbound)
+        }
+        Transport::Grpc { service_name } => {
+            let data = if info.alpn.as_deref() == Some("h2") {
+                h2::read_preface(&mut stream)?
+            } else {
+                http::read_head(&mut stream)?.0
+            };
+            if data.is_empty() {
+                return Ok(());
+            }
+            if *data != *h2::PREFACE {
+                tracing::debug!("Not an HTTP/2 connection");
+                return inbound::reject(ClientStream::Tls(stream), info, fallbacks, &data);
+            }
+
+            grpc::serve(stream, info, service_name, inbound)
+        }
+        Transport::HttpUpgrade { path, host } => {
+            let (data, head) = http::read_head(&mut stream)?;
+            if data.is_empty() {
+                return Ok(());
+            }
+
+            let upgrade = head.and_then(|(head, size)| {
+                Ok((
+                    http_upgrade::Upgrade::new(&head, path, host.as_deref())?,
+                    size,
+                ))
+            });
+            let (upgrade, size) = match upgrade {
+                Ok(upgrade) => upgrade,
+                Err(e) => {
+                    tracing::debug!("Invalid HTTP upgrade: {e}");
+                    return inbound::reject(ClientStream::Tls(stream), info, fallbacks, &data);
+                }
+            };
+
+            let stream = http_upgrade::accept(stream, upgrade, &data[size..])?;
+            inbound::handle(stream, info, inbound)
+        }
+        Transport::SplitHttp { path, host } => {
+            let host = host.as_deref();
+            if info.alpn.as_deref() == Some("h2") {
+                let data = h2::read_preface(&mut stream)?;
+                if data.is_empty() {
+                    return Ok(());
+                }
+                if *data != *h2::PREFACE {
+                    tracing::debug!("Not an HTTP/2 connection");
+                    return inbound::reject(ClientStream::Tls(stream), info, fallbacks, &data);
+                }
+                return split_http::serve_h2(stream, info, path, host, inbound);
+            }
+
+            let (data, head) = http::read_head(&mut stream)?;
+            if data.is_empty() {
+                return Ok(());
+            }
+            if !head.is_ok_and(|(head, _)| split_http::accepts(&head, path, host)) {
+                tracing::debug!("Not a SplitHTTP request");
+                return inbound::reject(ClientStream::Tls(stream), info, fallbacks, &data);
+            }
+
+            split_http::serve_http1(stream, &data, info, path, host, inbound)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let transport: Transport = toml::from_str("type = \"ws\"\npath = \"/ws\"").unwrap();
+        assert_eq!(
+            transport,
+            Transport::WebSocket {
+                path: String::from("/ws"),
+                host: None
+            }
+        );
+        assert_eq!(
+            toml::from_str::<Transport>("type = \"tcp\"").unwrap(),
+            Transport::Tcp
+        );
+        assert_eq!(
+            toml::from_str::<Transport>("type = \"grpc\"\nservice_name = \"tun\"").unwrap(),
+            Transport::Grpc {
+                service_name: String::from("tun")
+            }
+        );
+        assert!(toml::from_str::<Transport>("type = \"ws\"").is_err());
+    }
+}
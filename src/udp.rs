@@ -0,0 +1,180 @@
+use std::{
+    io::{self, Read},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use vless::{
+    Address,
+    udp::{PacketDecoder, encode_packet},
+};
+
+use crate::{dns::Resolver, stream::CloseWrite};
+
+/// Association is closed after no packets were sent in either direction for this long.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often the downlink checks for idle timeout and uplink shutdown.
+pub const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Preferred address of the destination.
+pub fn resolve(addr: &Address, port: u16, resolver: &Resolver) -> io::Result<SocketAddr> {
+    resolver
+        .resolve(addr, port)?
+        .into_iter()
+        .next()
+        .ok_or(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No addresses for {addr}"),
+        ))
+}
+
+/// Bind a socket of the same address family as `target`, reads time out every [`POLL_INTERVAL`].
+pub fn bind_for(target: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = match target {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+    Ok(socket)
+}
+
+pub fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Relay length-prefixed packets between the client stream and a single UDP destination.
+pub fn relay(
+    mut reader: impl Read,
+    mut writer: impl CloseWrite + Send + 'static,
+    target: SocketAddr,
+    payload: &[u8],
+) -> Result<()> {
+    let socket = bind_for(target)?;
+    socket.connect(target)?;
+    let socket_recv = socket.try_clone()?;
+
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let uplink_done = Arc::new(AtomicBool::new(false));
+
+    let touch = {
+        let last_activity = last_activity.clone();
+        move || {
+            if let Ok(mut x) = last_activity.lock() {
+                *x = Instant::now();
+            }
+        }
+    };
+
+    let downlink = {
+        let last_activity = last_activity.clone();
+        let uplink_done = uplink_done.clone();
+        let touch = touch.clone();
+
+        thread::spawn(move || -> Result<()> {
+            let mut buf = [0; 0x1_0000];
+
+            loop {
+                match socket_recv.recv(&mut buf) {
+                    Ok(n) => {
+                        touch();
+                        writer.write_all(&encode_packet(&buf[..n])?)?;
+                    }
+                    Err(e) if is_timeout(&e) => {
+                        let idle = last_activity
+                            .lock()
+                            .map_err(|_| anyhow!("Poisoned lock"))?
+                            .elapsed();
+                        if uplink_done.load(Ordering::Relaxed) || idle >= IDLE_TIMEOUT {
+                            tracing::debug!(
+                                "UDP association to {target} closed after {idle:?} idle"
+                            );
+                            break;
+                        }
+                    }
+                    // ICMP port unreachable surfaces as a receive error on connected sockets
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            // Client may already be gone if the uplink finished first
+            _ = writer.close();
+            // Unblock the uplink if it is still waiting for the client
+            _ = writer.shutdown();
+            Ok(())
+        })
+    };
+
+    let uplink = (|| -> Result<()> {
+        let mut decoder = PacketDecoder::new();
+        decoder.push(payload);
+
+        let mut buf = [0; 0x4000];
+        loop {
+            while let Some(packet) = decoder.next_packet() {
+                touch();
+                if let Err(e) = socket.send(&packet) {
+                    tracing::debug!("UDP send to {target} failed: {e}");
+                }
+            }
+
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            decoder.push(&buf[..n]);
+        }
+    })();
+    uplink_done.store(true, Ordering::Relaxed);
+
+    let downlink = downlink.join().map_err(|_| anyhow!("Downlink panicked"))?;
+
+    // Uplink read is interrupted when the downlink shuts the socket down on idle timeout
+    if let Err(e) = uplink {
+        tracing::debug!("UDP uplink to {target} ended: {e}");
+    }
+    downlink
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stream::MemoryWriter;
+
+    use super::*;
+
+    #[test]
+    fn test_relay() -> Result<()> {
+        let echo = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let target = echo.local_addr()?;
+        thread::spawn(move || {
+            let mut buf = [0; 0x100];
+            while let Ok((n, peer)) = echo.recv_from(&mut buf) {
+                _ = echo.send_to(&buf[..n].to_ascii_uppercase(), peer);
+            }
+        });
+
+        // Second packet is split between the request payload and the stream
+        let uplink = [encode_packet(b"ping")?, encode_packet(b"pong")?].concat();
+        let (payload, rest) = uplink.split_at(9);
+
+        let writer = MemoryWriter::default();
+        relay(rest, writer.clone(), target, payload)?;
+        assert!(writer.closed.load(Ordering::Relaxed));
+
+        let mut decoder = PacketDecoder::new();
+        decoder.push(&writer.data.lock().unwrap());
+        assert_eq!(decoder.next_packet().as_deref(), Some(&b"PING"[..]));
+        assert_eq!(decoder.next_packet().as_deref(), Some(&b"PONG"[..]));
+        assert_eq!(decoder.next_packet(), None);
+        Ok(())
+    }
+}
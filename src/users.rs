@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    ops::RangeInclusive,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Deserialize;
+use vless::{Command, VlessRequestHeader};
+
+use crate::{stats::Session, stream::CloseWrite};
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum PortSpec {
+    Single(u16),
+    /// `"from-to"`
+    Range(String),
+}
+
+impl TryFrom<PortSpec> for RangeInclusive<u16> {
+    type Error = anyhow::Error;
+
+    fn try_from(value: PortSpec) -> Result<Self, Self::Error> {
+        match value {
+            PortSpec::Single(port) => Ok(port..=port),
+            PortSpec::Range(range) => {
+                let (from, to) = range.split_once('-').unwrap_or((&range, &range));
+                let from = from.trim().parse()?;
+                let to = to.trim().parse()?;
+                if from > to {
+                    bail!("Empty port range: {range}");
+                }
+                Ok(from..=to)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct UserEntry {
+    uuid: String,
+    email: String,
+    /// Unix time in seconds
+    expires_at: Option<u64>,
+    /// Bytes in both directions
+    quota: Option<u64>,
+    flows: Option<Vec<String>>,
+    ports: Option<Vec<PortSpec>>,
+}
+
+#[derive(Debug)]
+pub struct User {
+    pub uuid: u128,
+    pub email: String,
+    pub expires_at: Option<SystemTime>,
+    pub quota: Option<u64>,
+    /// Allowed flows, empty string stands for no flow. `None` allows any
+    pub flows: Option<Vec<String>>,
+    /// Allowed destination ports. `None` allows any
+    pub ports: Option<Vec<RangeInclusive<u16>>>,
+
+    /// Bytes received from the user since the server started, kept across reloads
+    pub uplink: Arc<AtomicU64>,
+    /// Bytes sent to the user since the server started, kept across reloads
+    pub downlink: Arc<AtomicU64>,
+}
+
+impl TryFrom<UserEntry> for User {
+    type Error = anyhow::Error;
+
+    fn try_from(value: UserEntry) -> Result<Self, Self::Error> {
+        Ok(Self {
+            uuid: vless::parse_uuid(&value.uuid)?,
+            email: value.email,
+            expires_at: value
+                .expires_at
+                .map(|x| UNIX_EPOCH + Duration::from_secs(x)),
+            quota: value.quota,
+            flows: value.flows,
+            ports: value
+                .ports
+                .map(|ports| ports.into_iter().map(TryInto::try_into).collect())
+                .transpose()?,
+            uplink: Arc::default(),
+            downlink: Arc::default(),
+        })
+    }
+}
+
+impl User {
+    pub fn traffic(&self) -> u64 {
+        self.uplink.load(Ordering::Relaxed) + self.downlink.load(Ordering::Relaxed)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|x| x <= SystemTime::now())
+    }
+
+    pub fn is_over_quota(&self) -> bool {
+        self.quota.is_some_and(|x| self.traffic() >= x)
+    }
+
+    pub fn allows_flow(&self, flow: &str) -> bool {
+        self.flows
+            .as_ref()
+            .is_none_or(|flows| flows.iter().any(|x| x == flow))
+    }
+
+    pub fn allows_port(&self, port: u16) -> bool {
+        self.ports
+            .as_ref()
+            .is_none_or(|ports| ports.iter().any(|x| x.contains(&port)))
+    }
+}
+
+#[cfg(test)]
+impl User {
+    /// User without expiry, quota or restrictions.
+    pub fn unrestricted(uuid: u128, email: &str) -> Self {
+        Self {
+            uuid,
+            email: String::from(email),
+            expires_at: None,
+            quota: None,
+            flows: None,
+            ports: None,
+            uplink: Arc::default(),
+            downlink: Arc::default(),
+        }
+    }
+}
+
+/// Registry of users allowed to connect.
+#[derive(Debug, Default)]
+pub struct Users {
+    users: HashMap<u128, Arc<User>>,
+}
+
+impl Users {
+    pub fn from_entries(entries: Vec<UserEntry>) -> Result<Self> {
+        let mut users = HashMap::new();
+        for (i, entry) in entries.into_iter().enumerate() {
+            let user = User::try_from(entry).with_context(|| format!("user[{i}]"))?;
+            if users.contains_key(&user.uuid) {
+                bail!(
+                    "user[{i}]: Duplicate user {}",
+                    vless::format_uuid(user.uuid)
+                );
+            }
+            users.insert(user.uuid, Arc::new(user));
+        }
+
+        Ok(Self { users })
+    }
+
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Share the traffic counters of users also in `old`, so reloading does not reset quotas.
+    ///
+    /// Connections of the old users keep counting towards the new ones
+    pub fn carry_traffic(&mut self, old: &Users) {
+        for (uuid, user) in &mut self.users {
+            if let Some(user) = Arc::get_mut(user)
+                && let Some(old) = old.users.get(uuid)
+            {
+                user.uplink = old.uplink.clone();
+                user.downlink = old.downlink.clone();
+            }
+        }
+    }
+
+    /// Check the request against the user policy.
+    pub fn authorize(&self, header: &VlessRequestHeader) -> Result<Arc<User>> {
+        let user = self
+            .users
+            .get(&header.uuid)
+            .ok_or(anyhow!("Unknown user"))?;
+
+        if user.is_expired() {
+            bail!("User {} expired", user.email);
+        }
+        if user.is_over_quota() {
+            bail!("User {} is over quota", user.email);
+        }
+
+        let flow = header.addons.flow.as_deref().unwrap_or_default();
+        if !user.allows_flow(flow) {
+            bail!("Flow `{flow}` is not allowed for {}", user.email);
+        }
+        // Mux sessions are checked individually
+        if header.command != Command::Mux && !user.allows_port(header.port) {
+            bail!("Port {} is not allowed for {}", header.port, user.email);
+        }
+
+        Ok(user.clone())
+    }
+}
+
+/// Client connection half counting traffic of its user and session.
+///
+/// Fails once the user runs over quota
+pub struct Metered<T> {
+    inner: T,
+    user: Arc<User>,
+    session: Arc<Session>,
+}
+
+impl<T> Metered<T> {
+    pub fn new(inner: T, user: Arc<User>, session: Arc<Session>) -> Self {
+        Self {
+            inner,
+            user,
+            session,
+        }
+    }
+
+    fn check_quota(&self) -> io::Result<()> {
+        if self.user.is_over_quota() {
+            return Err(io::Error::other(format!(
+                "User {} ran over quota",
+                self.user.email
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for Metered<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check_quota()?;
+        let n = self.inner.read(buf)?;
+        self.user.uplink.fetch_add(n as u64, Ordering::Relaxed);
+        self.session.uplink(n as u64);
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Metered<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_quota()?;
+        let n = self.inner.write(buf)?;
+        self.user.downlink.fetch_add(n as u64, Ordering::Relaxed);
+        self.session.downlink(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: CloseWrite> CloseWrite for Metered<T> {
+    fn close(&mut self) -> io::Result<()> {
+        self.inner.close()
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        self.inner.shutdown()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vless::{Addons, Address};
+
+    use super::*;
+
+    #[derive(Deserialize, Debug)]
+    struct UsersFile {
+        #[serde(default, rename = "user")]
+        users: Vec<UserEntry>,
+    }
+
+    const USERS: &str = r#"
+        [[user]]
+        uuid = "27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d"
+        email = "alice@example.com"
+        quota = 100
+        flows = ["", "xtls-rprx-vision"]
+        ports = [443, "8000-8080"]
+
+        [[user]]
+        uuid = "b831381d-6324-4d53-ad4f-8cda48b30811"
+        email = "bob@example.com"
+        expires_at = 0
+    "#;
+
+    fn request(uuid: &str, flow: Option<&str>, port: u16) -> VlessRequestHeader {
+        VlessRequestHeader::new(
+            vless::parse_uuid(uuid).unwrap(),
+            Addons {
+                flow: flow.map(String::from),
+                seed: None,
+            },
+            Command::TCP,
+            port,
+            Address::Domain(String::from("example.com")),
+        )
+    }
+
+    #[test]
+    fn test_authorize() {
+        let file: UsersFile = toml::from_str(USERS).unwrap();
+        let users = Users {
+            users: file
+                .users
+                .into_iter()
+                .map(|x| User::try_from(x).unwrap())
+                .map(|x| (x.uuid, Arc::new(x)))
+                .collect(),
+        };
+        let alice = "27848a7b-5e0d-4b5a-9a5c-0f1e3d2b4c6d";
+
+        let user = users.authorize(&request(alice, None, 443)).unwrap();
+        assert_eq!(user.email, "alice@example.com");
+        assert!(users.authorize(&request(alice, None, 8080)).is_ok());
+        assert!(users.authorize(&request(alice, None, 80)).is_err());
+        assert!(
+            users
+                .authorize(&request(alice, Some("other"), 443))
+                .is_err()
+        );
+
+        user.downlink.store(100, Ordering::Relaxed);
+        assert!(users.authorize(&request(alice, None, 443)).is_err());
+
+        let bob = "b831381d-6324-4d53-ad4f-8cda48b30811";
+        assert!(users.authorize(&request(bob, None, 443)).is_err());
+
+        let unknown = "00000000-0000-0000-0000-000000000000";
+        assert!(users.authorize(&request(unknown, None, 443)).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use std::{
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::Result;
+use utils::concat_dyn;
+use vless::vision::{MAX_CONTENT_SIZE, Padder, TrafficState, Unpadder};
+
+use crate::stream::{CloseWrite, TlsReader, TlsWriter};
+
+type SharedState = Arc<Mutex<TrafficState>>;
+
+fn lock(state: &SharedState) -> io::Result<MutexGuard<'_, TrafficState>> {
+    state.lock().map_err(|_| io::Error::other("Poisoned lock"))
+}
+
+enum Source {
+    Tls(TlsReader),
+    Raw(TcpStream),
+}
+
+/// Reading half of a Vision connection, strips padding from client data.
+pub struct VisionReader {
+    source: Source,
+    unpadder: Unpadder,
+    state: SharedState,
+
+    buf: Box<[u8]>,
+    pos: usize,
+}
+
+impl VisionReader {
+    fn process(&mut self, data: &[u8]) -> io::Result<()> {
+        let (content, direct) = self
+            .unpadder
+            .unpad(data, &mut *lock(&self.state)?)
+            .map_err(io::Error::other)?;
+
+        self.buf = content;
+        self.pos = 0;
+
+        if direct && let Source::Tls(reader) = &mut self.source {
+            tracing::debug!("Vision uplink switched to direct copy");
+
+            // Records after the last padded block were sent bypassing TLS
+            let rest = reader.take_buffered();
+            let conn = reader.raw_conn()?;
+            self.buf = concat_dyn!(&self.buf, rest);
+            self.source = Source::Raw(conn);
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for VisionReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; 1 << 14];
+
+        while self.pos == self.buf.len() {
+            let n = match &mut self.source {
+                Source::Tls(reader) => reader.read(&mut chunk)?,
+                Source::Raw(conn) => return conn.read(buf),
+            };
+            if n == 0 {
+                return Ok(0);
+            }
+            self.process(&chunk[..n])?;
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+enum Sink {
+    Tls(TlsWriter),
+    Raw(TcpStream),
+}
+
+/// Writing half of a Vision connection, pads data sent to the client.
+pub struct VisionWriter {
+    sink: Sink,
+    padder: Padder,
+    state: SharedState,
+}
+
+impl Write for VisionWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let writer = match &mut self.sink {
+            Sink::Tls(writer) => writer,
+            Sink::Raw(conn) => return conn.write(buf),
+        };
+
+        let n = buf.len().min(MAX_CONTENT_SIZE);
+        let (data, direct) = self
+            .padder
+            .pad(&buf[..n], &mut *lock(&self.state)?)
+            .map_err(io::Error::other)?;
+        writer.write_all(&data)?;
+
+        if direct {
+            tracing::debug!("Vision downlink switched to direct copy");
+            self.sink = Sink::Raw(writer.raw_conn()?);
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Tls(writer) => writer.flush(),
+            Sink::Raw(conn) => conn.flush(),
+        }
+    }
+}
+
+impl CloseWrite for VisionWriter {
+    fn close(&mut self) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Tls(writer) => writer.close(),
+            // Inner TLS connection carries its own `close_notify`
+            Sink::Raw(conn) => conn.shutdown(Shutdown::Write),
+        }
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        match &self.sink {
+            Sink::Tls(writer) => writer.shutdown(),
+            Sink::Raw(conn) => conn.shutdown(Shutdown::Both),
+        }
+    }
+}
+
+/// Wrap both halves of the client connection into the Vision flow.
+///
+/// `payload` is the data received after the request header
+pub fn wrap(
+    reader: TlsReader,
+    writer: TlsWriter,
+    uuid: u128,
+    payload: &[u8],
+) -> Result<(VisionReader, VisionWriter)> {
+    let state = Arc::new(Mutex::new(TrafficState::new()));
+
+    let mut reader = VisionReader {
+        source: Source::Tls(reader),
+        unpadder: Unpadder::new(uuid),
+        state: state.clone(),
+        buf: Box::new([]),
+        pos: 0,
+    };
+    if !payload.is_empty() {
+        reader.process(payload)?;
+    }
+
+    let writer = VisionWriter {
+        sink: Sink::Tls(writer),
+        padder: Padder::new(uuid),
+        state,
+    };
+
+    Ok((reader, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    use tls::{
+        cipher_suite::TLS_AES_128_GCM_SHA256, connection::RecordReader, key_schedule::TrafficKeys,
+    };
+    use vless::vision::TrafficState;
+
+    use super::*;
+    use crate::stream::TlsStream;
+
+    const UUID: u128 = 0x27848a7b_5e0d_4b5a_9a5c_0f1e3d2b4c6d;
+
+    fn keys(secret: u8) -> Result<TrafficKeys> {
+        TrafficKeys::from_secret(TLS_AES_128_GCM_SHA256, &[secret; 32])
+    }
+
+    #[test]
+    fn test_unpad_switches_to_direct() -> Result<()> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+        let client_conn = TcpStream::connect(listener.local_addr()?)?;
+        let (server_conn, _) = listener.accept()?;
+        let mut client = TlsStream::new(
+            client_conn.try_clone()?,
+            RecordReader::new(keys(2)?),
+            keys(1)?,
+        )?;
+        let server = TlsStream::new(server_conn, RecordReader::new(keys(1)?), keys(2)?)?;
+
+        // Inner TLS 1.3 ServerHello followed by application data
+        let mut server_hello = vec![
+            0x16, 0x03, 0x03, 0x00, 0x56, 0x02, 0x00, 0x00, 0x52, 0x03, 0x03,
+        ];
+        server_hello.extend([0; 32]);
+        server_hello.extend([0x00, 0x13, 0x01, 0x00, 0x00, 0x2e]);
+        server_hello.extend([0x00, 0x2b, 0x00, 0x02, 0x03, 0x04]);
+        server_hello.resize(91, 0);
+        let application_data = [0x17, 0x03, 0x03, 0x00, 0x02, 0xAA, 0xBB];
+        let raw = [0x17, 0x03, 0x03, 0x00, 0x01, 0xCC];
+
+        let mut padder = Padder::new(UUID);
+        let mut state = TrafficState::new();
+        let (first, _) = padder.pad(&server_hello, &mut state)?;
+        let (second, direct) = padder.pad(&application_data, &mut state)?;
+        assert!(direct);
+
+        // First block arrives along with the request header, the last one is
+        // followed by records sent bypassing TLS
+        client.write_all(&second)?;
+        (&client_conn).write_all(&raw)?;
+        client_conn.shutdown(Shutdown::Write)?;
+
+        let (reader, writer) = server.split();
+        let (mut reader, _writer) = wrap(reader, writer, UUID, &first)?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        assert_eq!(data, [&server_hello[..], &application_data, &raw].concat());
+        assert!(matches!(reader.source, Source::Raw(_)));
+        Ok(())
+    }
+}
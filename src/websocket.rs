@@ -0,0 +1,396 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use anyhow::{Result, bail};
+use crypt::hash::{Hasher, sha::Sha1};
+
+use crate::{
+    http::RequestHead,
+    stream::{CloseWrite, TlsReader, TlsStream, TlsWriter},
+};
+
+/// Appended to the client key for `Sec-WebSocket-Accept`, RFC 6455 section 1.3.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest frame payload accepted from the client.
+const MAX_PAYLOAD_SIZE: u64 = 1 << 20;
+
+/// Largest frame payload sent to the client.
+const FRAME_DATA_SIZE: usize = 1 << 14;
+
+const NORMAL_CLOSURE: u16 = 1000;
+
+mod opcodes {
+    pub const CONTINUATION: u8 = 0x0;
+    pub const TEXT: u8 = 0x1;
+    pub const BINARY: u8 = 0x2;
+    pub const CLOSE: u8 = 0x8;
+    pub const PING: u8 = 0x9;
+    pub const PONG: u8 = 0xA;
+}
+
+/// Accepted upgrade request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Upgrade {
+    key: String,
+    /// `Sec-WebSocket-Protocol` carrying early data, echoed back to the client
+    protocol: Option<String>,
+    early_data: Box<[u8]>,
+}
+
+impl Upgrade {
+    /// Check the request against the transport settings.
+    pub fn new(head: &RequestHead, path: &str, host: Option<&str>) -> Result<Self> {
+        if head.method != "GET" {
+            bail!("Unexpected method {}", head.method);
+        }
+        head.check_target(path, host)?;
+        if !head.header_has_token("Upgrade", "websocket")
+            || !head.header_has_token("Connection", "upgrade")
+        {
+            bail!("Not a WebSocket upgrade");
+        }
+        if head.header("Sec-WebSocket-Version") != Some("13") {
+            bail!("Unsupported WebSocket version");
+        }
+        let Some(key) = head.header("Sec-WebSocket-Key") else {
+            bail!("Missing Sec-WebSocket-Key");
+        };
+
+        let (protocol, early_data) = early_data(head, path)?;
+
+        Ok(Self {
+            key: String::from(key),
+            protocol,
+            early_data,
+        })
+    }
+
+    fn response(&self) -> String {
+        let accept = utils::base64::encode(&Sha1::hash(format!("{}{GUID}", self.key).as_bytes()));
+
+        let mut res = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n"
+        );
+        if let Some(protocol) = &self.protocol {
+            res.push_str(&format!("Sec-WebSocket-Protocol: {protocol}\r\n"));
+        }
+        res.push_str("\r\n");
+        res
+    }
+}
+
+/// Most early data accepted, set with `ed` in the query of the configured path.
+fn max_early_data(path: &str) -> Option<usize> {
+    let (_, query) = path.split_once('?')?;
+    query
+        .split('&')
+        .find_map(|x| x.strip_prefix("ed="))
+        .and_then(|x| x.parse().ok())
+}
+
+/// Early data sent as `Sec-WebSocket-Protocol`, along with the header to echo.
+///
+/// Only read if `path` enables it, otherwise the header names a real subprotocol
+pub fn early_data(head: &RequestHead, path: &str) -> Result<(Option<String>, Box<[u8]>)> {
+    let (Some(max), Some(protocol)) = (max_early_data(path), head.header("Sec-WebSocket-Protocol"))
+    else {
+        return Ok((None, Box::new([])));
+    };
+    let Some(data) = utils::base64::decode(protocol) else {
+        bail!("Invalid early data");
+    };
+    if data.len() > max {
+        bail!("Early data is too large: {}", data.len());
+    }
+    Ok((Some(String::from(protocol)), data))
+}
+
+#[allow(clippy::cast_possible_truncation)]
+fn encode_frame(opcode: u8, payload: &[u8]) -> Box<[u8]> {
+    let mut res = vec![0x80 | opcode];
+
+    match payload.len() {
+        length @ 0..=125 => res.push(length as u8),
+        length @ 126..=0xFFFF => {
+            res.push(126);
+            res.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            res.push(127);
+            res.extend((length as u64).to_be_bytes());
+        }
+    }
+    res.extend(payload);
+
+    res.into_boxed_slice()
+}
+
+struct Frame {
+    opcode: u8,
+    payload: Box<[u8]>,
+}
+
+/// Read a single client frame, returns `None` on end of stream.
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<Frame>> {
+    let mut header = [0; 2];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let [first, second] = header;
+
+    if first & 0x70 != 0 {
+        return Err(io::Error::other("Unexpected reserved bits"));
+    }
+    if second & 0x80 == 0 {
+        return Err(io::Error::other("Client frame is not masked"));
+    }
+    // Control frames can not be fragmented or longer than 125 bytes, RFC 6455 section 5.5
+    if first & 0x08 != 0 && (first & 0x80 == 0 || second & 0x7F > 125) {
+        return Err(io::Error::other("Invalid control frame"));
+    }
+
+    let length = match second & 0x7F {
+        126 => {
+            let mut raw = [0; 2];
+            reader.read_exact(&mut raw)?;
+            u64::from(u16::from_be_bytes(raw))
+        }
+        127 => {
+            let mut raw = [0; 8];
+            reader.read_exact(&mut raw)?;
+            u64::from_be_bytes(raw)
+        }
+        length => u64::from(length),
+    };
+    if length > MAX_PAYLOAD_SIZE {
+        return Err(io::Error::other(format!("Frame is too large: {length}")));
+    }
+
+    let mut mask = [0; 4];
+    reader.read_exact(&mut mask)?;
+
+    #[allow(clippy::cast_possible_truncation)]
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, x) in payload.iter_mut().enumerate() {
+        *x ^= mask[i % 4];
+    }
+
+    Ok(Some(Frame {
+        opcode: first & 0x0F,
+        payload: payload.into_boxed_slice(),
+    }))
+}
+
+struct Shared {
+    writer: TlsWriter,
+    close_sent: bool,
+}
+
+type SharedWriter = Arc<Mutex<Shared>>;
+
+fn lock(shared: &SharedWriter) -> io::Result<MutexGuard<'_, Shared>> {
+    shared.lock().map_err(|_| io::Error::other("Poisoned lock"))
+}
+
+/// Reading half of a WebSocket connection, yields binary message data.
+pub struct WebSocketReader {
+    inner: TlsReader,
+    shared: SharedWriter,
+
+    buf: Box<[u8]>,
+    pos: usize,
+    closed: bool,
+}
+
+impl WebSocketReader {
+    fn on_close(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.closed = true;
+
+        let mut shared = lock(&self.shared)?;
+        if !shared.close_sent {
+            shared.close_sent = true;
+            let status = payload.get(..2).unwrap_or_default();
+            shared
+                .writer
+                .write_all(&encode_frame(opcodes::CLOSE, status))?;
+        }
+        // Closing handshake is complete
+        _ = shared.writer.close();
+        Ok(())
+    }
+}
+
+impl Read for WebSocketReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.buf.len() && !self.closed {
+            let Some(frame) = read_frame(&mut self.inner)? else {
+                self.closed = true;
+                break;
+            };
+
+            match frame.opcode {
+                opcodes::CONTINUATION | opcodes::TEXT | opcodes::BINARY => {
+                    self.buf = frame.payload;
+                    self.pos = 0;
+                }
+                opcodes::PING => {
+                    let mut shared = lock(&self.shared)?;
+                    if !shared.close_sent {
+                        let pong = encode_frame(opcodes::PONG, &frame.payload);
+                        shared.writer.write_all(&pong)?;
+                    }
+                }
+                opcodes::PONG => {}
+                opcodes::CLOSE => self.on_close(&frame.payload)?,
+                opcode => {
+                    return Err(io::Error::other(format!("Unknown opcode: {opcode}")));
+                }
+            }
+        }
+
+        let n = buf.len().min(self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..(self.pos + n)]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Writing half of a WebSocket connection, sends data as binary messages.
+pub struct WebSocketWriter {
+    shared: SharedWriter,
+}
+
+impl Write for WebSocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(FRAME_DATA_SIZE);
+
+        let mut shared = lock(&self.shared)?;
+        if shared.close_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "WebSocket is closed",
+            ));
+        }
+        shared
+            .writer
+            .write_all(&encode_frame(opcodes::BINARY, &buf[..n]))?;
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.shared)?.writer.flush()
+    }
+}
+
+impl CloseWrite for WebSocketWriter {
+    /// Start the closing handshake, TLS is closed once the client answers.
+    fn close(&mut self) -> io::Result<()> {
+        let mut shared = lock(&self.shared)?;
+        if !shared.close_sent {
+            shared.close_sent = true;
+            let close = encode_frame(opcodes::CLOSE, &NORMAL_CLOSURE.to_be_bytes());
+            shared.writer.write_all(&close)?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) -> io::Result<()> {
+        lock(&self.shared)?.writer.shutdown()
+    }
+}
+
+/// Answer the upgrade request and wrap the connection.
+pub fn accept(stream: TlsStream, upgrade: Upgrade) -> Result<(WebSocketReader, WebSocketWriter)> {
+    let (reader, mut writer) = stream.split();
+    writer.write_all(upgrade.response().as_bytes())?;
+
+    let shared = Arc::new(Mutex::new(Shared {
+        writer,
+        close_sent: false,
+    }));
+
+    Ok((
+        WebSocketReader {
+            inner: reader,
+            shared: shared.clone(),
+            buf: upgrade.early_data,
+            pos: 0,
+            closed: false,
+        },
+        WebSocketWriter { shared },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut res = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        res.extend(mask);
+        res.extend(payload.iter().enumerate().map(|(i, x)| x ^ mask[i % 4]));
+        res
+    }
+
+    #[test]
+    fn test_upgrade() {
+        let data = b"GET /ws?ed=2048 HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\n\
+                     Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                     Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: AAEC\r\n\r\n";
+        let (head, _) = RequestHead::parse(data).unwrap().unwrap();
+
+        let upgrade = Upgrade::new(&head, "/ws?ed=2048", Some("example.com")).unwrap();
+        assert_eq!(*upgrade.early_data, [0, 1, 2]);
+        let response = upgrade.response();
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert!(response.contains("Sec-WebSocket-Protocol: AAEC\r\n"));
+
+        assert!(Upgrade::new(&head, "/other", None).is_err());
+        assert!(Upgrade::new(&head, "/ws", Some("example.org")).is_err());
+        assert!(Upgrade::new(&head, "/ws?ed=2", None).is_err());
+
+        // Without early data the header names a subprotocol
+        let upgrade = Upgrade::new(&head, "/ws", None).unwrap();
+        assert!(upgrade.early_data.is_empty());
+        assert!(!upgrade.response().contains("Sec-WebSocket-Protocol"));
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut raw = masked(opcodes::BINARY, b"hello");
+        raw.extend(masked(opcodes::CLOSE, &NORMAL_CLOSURE.to_be_bytes()));
+        let mut reader = raw.as_slice();
+
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(frame.opcode, opcodes::BINARY);
+        assert_eq!(&*frame.payload, b"hello");
+        let frame = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(frame.opcode, opcodes::CLOSE);
+        assert!(read_frame(&mut reader).unwrap().is_none());
+
+        // Unmasked frames are rejected
+        assert!(read_frame(&mut encode_frame(opcodes::BINARY, b"x").as_ref()).is_err());
+
+        // Fragmented or long control frames are rejected
+        let mut ping = masked(opcodes::PING, b"x");
+        ping[0] &= 0x7F;
+        assert!(read_frame(&mut ping.as_slice()).is_err());
+        let ping = masked(opcodes::PING, &[0; 126]);
+        assert!(read_frame(&mut ping.as_slice()).is_err());
+
+        assert_eq!(
+            encode_frame(opcodes::BINARY, &[0; 200])[..4],
+            [0x82, 126, 0, 200]
+        );
+    }
+}